@@ -0,0 +1,100 @@
+// Compares per-chunk and stream-wide compression (`StreamOptions::stream_compression`)
+// on a synthetic log archive: size, ratio and lock / unlock throughput.
+//
+// Run with `cargo bench --bench stream_compression` from src-tauri/.
+// QRE_BENCH_MB sets the archive size (default 64).
+
+use qre_core::{decrypt_stream, encrypt_stream, FileMetadata, MasterKey, StreamOptions, UnlockKeys};
+use std::time::Instant;
+
+/// Web server logs: a few hundred distinct requests, varying clients and timestamps,
+/// and older log files repeated further down (as in an archive of rotated logs).
+fn log_archive(size: usize) -> Vec<u8> {
+    let paths = ["/", "/login", "/api/v1/items", "/api/v1/items/42", "/static/app.js", "/static/style.css", "/health"];
+    let agents = ["Mozilla/5.0 (X11; Linux x86_64)", "curl/8.5.0", "Mozilla/5.0 (Windows NT 10.0; Win64; x64)"];
+    let mut state = 0x2545_F491_4F6C_DD1Du64;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+
+    let mut out = Vec::with_capacity(size + 256);
+    let mut timestamp = 1_700_000_000u64;
+    while out.len() < size {
+        // Now and then, a copy of an earlier stretch (a rotated log kept twice)
+        if out.len() > 16 << 20 && next() % 40_000 == 0 {
+            let start = (next() as usize) % (out.len() - (4 << 20));
+            let copy = out[start..start + (2 << 20)].to_vec();
+            out.extend_from_slice(&copy);
+            continue;
+        }
+        timestamp += next() % 3;
+        let r = next();
+        let line = format!(
+            "10.0.{}.{} - - [{}] \"GET {} HTTP/1.1\" {} {} \"{}\"\n",
+            r % 16,
+            (r >> 8) % 256,
+            timestamp,
+            paths[(r >> 16) as usize % paths.len()],
+            [200, 200, 200, 304, 404][(r >> 24) as usize % 5],
+            (r >> 32) % 20_000,
+            agents[(r >> 48) as usize % agents.len()],
+        );
+        out.extend_from_slice(line.as_bytes());
+    }
+    out.truncate(size);
+    out
+}
+
+fn main() {
+    let megabytes: usize = std::env::var("QRE_BENCH_MB").ok().and_then(|v| v.parse().ok()).unwrap_or(64);
+    let plaintext = log_archive(megabytes << 20);
+    let mk = MasterKey([7u8; 32]);
+    let keys = UnlockKeys { master_key: &mk, keyfile_bytes: None, identity: None, passphrase: None };
+    let mb = plaintext.len() as f64 / (1024.0 * 1024.0);
+
+    println!("{:.0} MB log archive, {} threads", mb, std::thread::available_parallelism().map_or(1, |n| n.get()));
+    println!("{:<22} {:>12} {:>8} {:>12} {:>12}", "mode", "locked size", "ratio", "lock MB/s", "unlock MB/s");
+
+    for (label, stream_compression, level) in [
+        ("per-chunk, level 3", false, 3),
+        ("stream-wide, level 3", true, 3),
+        ("per-chunk, level 9", false, 9),
+        ("stream-wide, level 9", true, 9),
+    ] {
+        let options = StreamOptions { compression_level: level, stream_compression, ..Default::default() };
+
+        let start = Instant::now();
+        let mut locked = Vec::new();
+        encrypt_stream(
+            plaintext.as_slice(),
+            &mut locked,
+            FileMetadata::for_stream("logs.tar", Some(plaintext.len() as u64)),
+            &mk,
+            None,
+            None,
+            &options,
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+        let lock_secs = start.elapsed().as_secs_f64();
+
+        let start = Instant::now();
+        let mut restored = Vec::with_capacity(plaintext.len());
+        decrypt_stream(locked.as_slice(), &mut restored, &keys, 0, Some(locked.len() as u64), |_, _| {})
+            .expect("Decryption failed");
+        let unlock_secs = start.elapsed().as_secs_f64();
+        assert!(restored == plaintext, "Round trip mismatch");
+
+        println!(
+            "{:<22} {:>12} {:>7.2}x {:>12.1} {:>12.1}",
+            label,
+            locked.len(),
+            plaintext.len() as f64 / locked.len() as f64,
+            mb / lock_secs,
+            mb / unlock_secs,
+        );
+    }
+}
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// V5 / V6 stream headers: length prefix, key slots, header MAC.
+fuzz_target!(|data: &[u8]| {
+    qre_core::fuzzing::stream_header(data);
+});
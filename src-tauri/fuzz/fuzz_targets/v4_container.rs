@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// Legacy V4 containers: header, ciphertext length prefix, key unwrapping.
+fuzz_target!(|data: &[u8]| {
+    qre_core::fuzzing::v4_container(data);
+});
@@ -0,0 +1,466 @@
+use crate::utils;
+use serde::{Deserialize, Serialize};
+#[cfg(unix)]
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use uuid::Uuid;
+use walkdir::WalkDir;
+
+// A locked folder is a single plaintext stream fed to the encryption engine:
+//
+//   [u32 entry_len][bincode ArchiveEntry][file content] ... [u32 0]
+//
+// The content follows only File entries (exactly `size` bytes).
+// The stream never exists on disk: it is produced while reading the folder
+// and consumed while writing the restored tree.
+
+// --- CONSTANTS ---
+
+// Upper bound for one serialized entry. Real entries are a path plus a few numbers.
+const MAX_ENTRY_LEN: usize = 64 * 1024;
+
+// --- DATA STRUCTURES ---
+
+/// What to do with symbolic links found in a folder being locked.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SymlinkPolicy {
+    /// Leave links out of the archive.
+    #[default]
+    Skip,
+    /// Archive what links point to, as regular files and folders.
+    Follow,
+    /// Archive the links themselves. Restored on Unix only, and only
+    /// if they point inside the restored folder.
+    Preserve,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum EntryKind {
+    File { size: u64 },
+    // Stored explicitly so empty folders survive the round trip.
+    Directory,
+    Symlink { target: String },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ArchiveEntry {
+    // Relative to the locked folder, '/'-separated (e.g., "photos/2024/beach.jpg").
+    path: String,
+    kind: EntryKind,
+    // Seconds since the Unix Epoch.
+    modified: Option<u64>,
+    // Unix permission bits.
+    permissions: Option<u32>,
+}
+
+impl ArchiveEntry {
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let body = bincode::serialize(self).map_err(io::Error::other)?;
+        let mut out = Vec::with_capacity(4 + body.len());
+        out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        out.extend_from_slice(&body);
+        Ok(out)
+    }
+}
+
+// --- WRITING (LOCK) ---
+
+/// Produces the archive stream of a folder through `Read`, one file at a time.
+/// Pass it to `crypto_stream::encrypt_stream`: file contents go straight
+/// from disk into the encrypted chunks.
+pub struct ArchiveReader {
+    entries: std::vec::IntoIter<(PathBuf, ArchiveEntry)>,
+    // Encoded header of the current entry, handed out before its content.
+    pending: Vec<u8>,
+    pending_pos: usize,
+    current: Option<(io::Take<File>, String)>,
+    finished: bool,
+    stream_len: u64,
+}
+
+impl ArchiveReader {
+    /// Lists the folder (names and attributes only). Contents are read later, on demand.
+    pub fn new(root: &Path, symlinks: SymlinkPolicy) -> anyhow::Result<Self> {
+        let mut entries = Vec::new();
+        let mut stream_len = 4; // End marker
+
+        let walker = WalkDir::new(root)
+            .min_depth(1)
+            .follow_links(symlinks == SymlinkPolicy::Follow)
+            .sort_by_file_name();
+
+        for entry in walker {
+            let entry = entry?;
+            let path = entry.path().to_path_buf();
+            let name = path
+                .strip_prefix(root)?
+                .to_str()
+                .ok_or_else(|| anyhow::anyhow!("Non-UTF8 path: {}", path.display()))?
+                .replace('\\', "/"); // Normalize Windows paths
+
+            // Follows the link when `follow_links` is set
+            let meta = entry.metadata()?;
+            let file_type = entry.file_type();
+
+            let kind = if file_type.is_symlink() {
+                if symlinks != SymlinkPolicy::Preserve {
+                    continue;
+                }
+                let target = fs::read_link(&path)?;
+                let target = target
+                    .to_str()
+                    .ok_or_else(|| anyhow::anyhow!("Non-UTF8 link target: {}", path.display()))?
+                    .replace('\\', "/");
+                EntryKind::Symlink { target }
+            } else if file_type.is_dir() {
+                EntryKind::Directory
+            } else if file_type.is_file() {
+                stream_len += meta.len();
+                EntryKind::File { size: meta.len() }
+            } else {
+                continue; // Sockets, FIFOs, devices
+            };
+
+            let archive_entry = ArchiveEntry {
+                path: name,
+                kind,
+                modified: modified_secs(&meta),
+                permissions: permission_bits(&meta),
+            };
+            stream_len += archive_entry.encode()?.len() as u64;
+            entries.push((path, archive_entry));
+        }
+
+        Ok(Self {
+            entries: entries.into_iter(),
+            pending: Vec::new(),
+            pending_pos: 0,
+            current: None,
+            finished: false,
+            stream_len,
+        })
+    }
+
+    /// Exact length of the stream this reader produces (for progress reporting).
+    pub fn stream_len(&self) -> u64 {
+        self.stream_len
+    }
+}
+
+impl Read for ArchiveReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // 1. Header of the current entry
+            if self.pending_pos < self.pending.len() {
+                let n = std::cmp::min(buf.len(), self.pending.len() - self.pending_pos);
+                buf[..n].copy_from_slice(&self.pending[self.pending_pos..self.pending_pos + n]);
+                self.pending_pos += n;
+                return Ok(n);
+            }
+
+            // 2. Content of the current file
+            if let Some((file, name)) = &mut self.current {
+                let n = file.read(buf)?;
+                if n > 0 {
+                    return Ok(n);
+                }
+                // The size is already recorded in the header: a shrunk file cannot be stored
+                if file.limit() > 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("{} changed while being locked", name),
+                    ));
+                }
+                self.current = None;
+            }
+
+            // 3. Next entry (or the end marker)
+            match self.entries.next() {
+                Some((path, entry)) => {
+                    if let EntryKind::File { size } = entry.kind {
+                        self.current = Some((File::open(&path)?.take(size), entry.path.clone()));
+                    }
+                    self.pending = entry.encode()?;
+                }
+                None if !self.finished => {
+                    self.finished = true;
+                    self.pending = 0u32.to_le_bytes().to_vec();
+                }
+                None => return Ok(0),
+            }
+            self.pending_pos = 0;
+        }
+    }
+}
+
+// --- READING (UNLOCK) ---
+
+/// Rebuilds a folder from the archive stream written into it (`Write`).
+///
+/// Everything lands in a hidden temporary folder next to the destination,
+/// which is wiped if the extractor is dropped before `commit`.
+/// Links are only created at commit, so no entry is ever written through one.
+pub struct ArchiveExtractor {
+    temp_dir: PathBuf,
+    committed: bool,
+    // Entry header being assembled (it may arrive split across writes).
+    header: Vec<u8>,
+    current: Option<(File, u64, PathBuf, ArchiveEntry)>,
+    // Folder attributes are applied last: adding files changes their timestamps.
+    directories: Vec<(PathBuf, ArchiveEntry)>,
+    symlinks: Vec<(PathBuf, ArchiveEntry, String)>,
+    finished: bool,
+}
+
+impl ArchiveExtractor {
+    /// Creates the temporary folder next to `final_dir`.
+    pub fn create(final_dir: &Path) -> io::Result<Self> {
+        let parent = final_dir.parent().unwrap_or(Path::new("."));
+        let temp_dir = parent.join(format!(".qre-partial-{}", Uuid::new_v4()));
+        fs::create_dir(&temp_dir)?;
+        Ok(Self {
+            temp_dir,
+            committed: false,
+            header: Vec::new(),
+            current: None,
+            directories: Vec::new(),
+            symlinks: Vec::new(),
+            finished: false,
+        })
+    }
+
+    /// Checks the archive was complete, restores folder attributes and links,
+    /// then renames the folder to `final_dir`.
+    pub fn commit(mut self, final_dir: &Path) -> io::Result<()> {
+        if !self.finished || self.current.is_some() || !self.header.is_empty() {
+            return Err(invalid_data("Folder archive is incomplete"));
+        }
+
+        #[cfg(unix)]
+        {
+            let links: HashSet<&str> = self.symlinks.iter().map(|(_, entry, _)| entry.path.as_str()).collect();
+            for (path, entry, target) in &self.symlinks {
+                if link_stays_inside(&entry.path, target, &links) {
+                    std::os::unix::fs::symlink(target, path)?;
+                }
+            }
+        }
+
+        // Deepest folders first, so restoring a parent's timestamp is the last change to it
+        for (path, entry) in self.directories.iter().rev() {
+            apply_attributes(None, path, entry);
+        }
+
+        fs::rename(&self.temp_dir, final_dir)?;
+        self.committed = true;
+        Ok(())
+    }
+
+    fn start_entry(&mut self, entry: ArchiveEntry) -> io::Result<()> {
+        let path = self.temp_dir.join(safe_relative_path(&entry.path)?);
+
+        match &entry.kind {
+            EntryKind::Directory => {
+                fs::create_dir_all(&path)?;
+                self.directories.push((path, entry));
+            }
+            EntryKind::File { size } => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // create_new: a duplicate entry must not overwrite an earlier one
+                let file = fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+                let size = *size;
+                self.current = Some((file, size, path, entry));
+                if size == 0 {
+                    self.finish_file();
+                }
+            }
+            EntryKind::Symlink { target } => {
+                let target = target.clone();
+                self.symlinks.push((path, entry, target));
+            }
+        }
+        Ok(())
+    }
+
+    fn finish_file(&mut self) {
+        if let Some((file, _, path, entry)) = self.current.take() {
+            apply_attributes(Some(&file), &path, &entry);
+        }
+    }
+}
+
+impl Write for ArchiveExtractor {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut rest = buf;
+
+        while !rest.is_empty() {
+            if self.finished {
+                return Err(invalid_data("Data after the end of the folder archive"));
+            }
+
+            // 1. Content of the current file
+            if let Some((file, remaining, _, _)) = &mut self.current {
+                let n = std::cmp::min(*remaining, rest.len() as u64) as usize;
+                file.write_all(&rest[..n])?;
+                *remaining -= n as u64;
+                rest = &rest[n..];
+                if *remaining == 0 {
+                    self.finish_file();
+                }
+                continue;
+            }
+
+            // 2. Entry header: [u32 entry_len][bincode ArchiveEntry]
+            let needed = if self.header.len() < 4 {
+                4
+            } else {
+                4 + u32::from_le_bytes(self.header[..4].try_into().unwrap()) as usize
+            };
+            let n = std::cmp::min(needed - self.header.len(), rest.len());
+            self.header.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+
+            if self.header.len() < 4 {
+                continue;
+            }
+            let entry_len = u32::from_le_bytes(self.header[..4].try_into().unwrap()) as usize;
+            if entry_len == 0 {
+                self.header.clear();
+                self.finished = true;
+            } else if entry_len > MAX_ENTRY_LEN {
+                return Err(invalid_data("Corrupted folder archive (entry too large)"));
+            } else if self.header.len() == 4 + entry_len {
+                let entry: ArchiveEntry = bincode::deserialize(&self.header[4..])
+                    .map_err(|_| invalid_data("Corrupted folder archive entry"))?;
+                self.header.clear();
+                self.start_entry(entry)?;
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ArchiveExtractor {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.current.take();
+            // Links are not created yet, so this never leaves the temporary folder
+            for entry in WalkDir::new(&self.temp_dir).into_iter().flatten() {
+                if entry.file_type().is_file() {
+                    let _ = utils::wipe_and_remove(entry.path());
+                }
+            }
+            let _ = fs::remove_dir_all(&self.temp_dir);
+        }
+    }
+}
+
+// --- HELPER FUNCTIONS ---
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Turns an archived path into a relative path that cannot leave the
+/// destination folder ("../", absolute paths and drive letters are rejected).
+fn safe_relative_path(name: &str) -> io::Result<PathBuf> {
+    let mut path = PathBuf::new();
+    for part in name.split('/') {
+        let mut components = Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(c)), None) => path.push(c),
+            _ => return Err(invalid_data(&format!("Unsafe path in folder archive: {}", name))),
+        }
+    }
+    Ok(path)
+}
+
+/// True if a link stored at `link_path` resolves to a location inside the restored folder.
+///
+/// The check is lexical, so it only holds if no other archived link (`links`) is
+/// crossed on the way: `d -> .` then `e -> d/..` would leave the folder.
+/// Such links are rejected, whether the crossing is in the target or in `link_path`.
+#[cfg(unix)]
+fn link_stays_inside(link_path: &str, target: &str, links: &HashSet<&str>) -> bool {
+    if target.starts_with('/') {
+        return false;
+    }
+    // Folder containing the link
+    let mut parts: Vec<&str> = link_path.split('/').collect();
+    parts.pop();
+    for i in 1..=parts.len() {
+        if links.contains(parts[..i].join("/").as_str()) {
+            return false;
+        }
+    }
+
+    let steps: Vec<&str> = target.split('/').filter(|p| !p.is_empty() && *p != ".").collect();
+    for (i, part) in steps.iter().enumerate() {
+        if *part == ".." {
+            if parts.pop().is_none() {
+                return false;
+            }
+            continue;
+        }
+        parts.push(part);
+        // Ending on another link is fine (it is checked on its own), going through one is not
+        if i + 1 < steps.len() && links.contains(parts.join("/").as_str()) {
+            return false;
+        }
+    }
+    true
+}
+
+fn modified_secs(meta: &fs::Metadata) -> Option<u64> {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs())
+}
+
+#[cfg(unix)]
+fn permission_bits(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode())
+}
+
+#[cfg(not(unix))]
+fn permission_bits(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Restores timestamp and permissions. Best-effort, like `FileMetadata::apply_to`.
+fn apply_attributes(file: Option<&File>, path: &Path, entry: &ArchiveEntry) {
+    if let Some(secs) = entry.modified {
+        let time = std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        match file {
+            Some(f) => {
+                let _ = f.set_modified(time);
+            }
+            None => {
+                if let Ok(f) = File::open(path) {
+                    let _ = f.set_modified(time);
+                }
+            }
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = entry.permissions {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode));
+    }
+}
@@ -0,0 +1,111 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm,
+};
+use anyhow::{anyhow, Result};
+use chacha20poly1305::XChaCha20Poly1305;
+use serde::{Deserialize, Serialize};
+
+/// The AEAD algorithm a (.qre) stream is encrypted with.
+/// Stored in the V6 header so decryption can dispatch on it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherSuite {
+    /// AES-256-GCM with 96-bit nonces. Fastest on CPUs with AES instructions.
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    /// XChaCha20-Poly1305 with 192-bit nonces. Constant-time and fast in pure
+    /// software, so it is preferred on devices without AES acceleration.
+    #[serde(rename = "xchacha20-poly1305")]
+    XChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    /// Picks AES-256-GCM when the CPU accelerates it, XChaCha20-Poly1305 otherwise
+    /// (common on low-end Android devices).
+    pub fn auto() -> Self {
+        if has_aes_hardware() {
+            CipherSuite::Aes256Gcm
+        } else {
+            CipherSuite::XChaCha20Poly1305
+        }
+    }
+
+    /// Nonce length in bytes.
+    pub fn nonce_len(self) -> usize {
+        match self {
+            CipherSuite::Aes256Gcm => 12,
+            CipherSuite::XChaCha20Poly1305 => 24,
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn has_aes_hardware() -> bool {
+    std::is_x86_feature_detected!("aes") && std::is_x86_feature_detected!("pclmulqdq")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn has_aes_hardware() -> bool {
+    std::arch::is_aarch64_feature_detected!("aes") && std::arch::is_aarch64_feature_detected!("pmull")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn has_aes_hardware() -> bool {
+    false
+}
+
+/// A keyed AEAD instance for one of the supported suites.
+/// Nonce lengths are checked on every call, so malformed input returns an error
+/// instead of panicking.
+pub enum AeadCipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(Box<XChaCha20Poly1305>),
+}
+
+impl AeadCipher {
+    /// Creates the cipher from a 256-bit key.
+    pub fn new(suite: CipherSuite, key: &[u8]) -> Result<Self> {
+        Ok(match suite {
+            CipherSuite::Aes256Gcm => AeadCipher::Aes256Gcm(Box::new(
+                Aes256Gcm::new_from_slice(key).map_err(|_| anyhow!("Invalid key length"))?,
+            )),
+            CipherSuite::XChaCha20Poly1305 => AeadCipher::XChaCha20Poly1305(Box::new(
+                XChaCha20Poly1305::new_from_slice(key).map_err(|_| anyhow!("Invalid key length"))?,
+            )),
+        })
+    }
+
+    pub fn suite(&self) -> CipherSuite {
+        match self {
+            AeadCipher::Aes256Gcm(_) => CipherSuite::Aes256Gcm,
+            AeadCipher::XChaCha20Poly1305(_) => CipherSuite::XChaCha20Poly1305,
+        }
+    }
+
+    pub fn encrypt(&self, nonce: &[u8], msg: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        let payload = Payload { msg, aad };
+        match self {
+            AeadCipher::Aes256Gcm(c) => c.encrypt(nonce.into(), payload),
+            AeadCipher::XChaCha20Poly1305(c) => c.encrypt(nonce.into(), payload),
+        }
+        .map_err(|_| anyhow!("Encryption failed"))
+    }
+
+    pub fn decrypt(&self, nonce: &[u8], ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        self.check_nonce(nonce)?;
+        let payload = Payload { msg: ciphertext, aad };
+        match self {
+            AeadCipher::Aes256Gcm(c) => c.decrypt(nonce.into(), payload),
+            AeadCipher::XChaCha20Poly1305(c) => c.decrypt(nonce.into(), payload),
+        }
+        .map_err(|_| anyhow!("Decryption failed"))
+    }
+
+    fn check_nonce(&self, nonce: &[u8]) -> Result<()> {
+        if nonce.len() != self.suite().nonce_len() {
+            return Err(anyhow!("Invalid nonce length: {}", nonce.len()));
+        }
+        Ok(())
+    }
+}
@@ -1,4 +1,4 @@
-use tauri::{AppHandle, Manager};
+use tauri::{AppHandle, Emitter, Manager};
 use std::fs;
 use std::path::{Path, PathBuf};
 use sha2::{Sha256, Digest};
@@ -14,7 +14,11 @@ use crate::state::SessionState;
 use crate::utils;
 use crate::keychain;
 use crate::crypto;        
-use crate::crypto_stream;
+use crate::crypto_stream::{self, PaddingScheme};
+use crate::archive::SymlinkPolicy;
+use crate::cipher::CipherSuite;
+use crate::hybrid::HybridSecretKey;
+use crate::qre_reader::QreReader;
 use crate::vault::PasswordVault;
 use crate::notes::NotesVault;
 use crate::clipboard_store::{ClipboardVault};
@@ -24,13 +28,25 @@ use rand::RngCore;
 use crate::breach;
 use crate::qr;
 use crate::bookmarks::BookmarksVault;
+use crate::contacts::{Contact, ContactsVault};
+use crate::hybrid::HybridPublicKey;
+use crate::inspect::{self, InspectReport};
+use crate::rotation::{self, RewrapOutcome};
+use crate::signing::{self, TrustedSigner, TrustedSigners};
+use crate::volumes;
+use crate::parity;
 type CommandResult<T> = Result<T, String>;
 
-#[derive(serde::Serialize)]
+// Largest slice `read_locked_range` hands to the frontend in one call.
+const MAX_RANGE_READ: u64 = 16 * 1024 * 1024;
+
+#[derive(serde::Serialize, Clone)]
 pub struct BatchItemResult {
     pub name: String,
     pub success: bool,
     pub message: String,
+    /// The file decrypted but did not match its recorded hash (corrupted, not a wrong key).
+    pub integrity_failed: bool,
 }
 
 // --- HELPER: Resolve Keychain Path ---
@@ -44,21 +60,104 @@ fn resolve_keychain_path(app: &AppHandle) -> Result<PathBuf, String> {
     Ok(data_dir.join("keychain.json"))
 }
 
-// --- HELPER: Smart Compression Detection ---
-fn is_already_compressed(filename: &str) -> bool {
-    let ext = Path::new(filename)
-        .extension()
-        .and_then(|s| s.to_str())
-        .unwrap_or("")
-        .to_lowercase();
-    
-    matches!(
-        ext.as_str(), 
-        "jpg" | "jpeg" | "png" | "gif" | "webp" | 
-        "zip" | "7z" | "rar" | "gz" | "bz2" | "xz" | 
-        "mp4" | "mkv" | "mov" | "avi" | "webm" | 
-        "mp3" | "aac" | "flac" | "wav" | "pdf"
-    )
+// --- HELPER: Load the Vault Identity (for Post-Quantum files) ---
+fn load_vault_identity(app: &AppHandle, master_key: &keychain::MasterKey) -> Result<Option<HybridSecretKey>, String> {
+    let path = resolve_keychain_path(app)?;
+    keychain::load_identity(&path, master_key).map_err(|e| e.to_string())
+}
+
+// --- HELPER: Describe who signed a file ---
+fn describe_signer(signer: &Option<[u8; 32]>, trusted: &TrustedSigners) -> String {
+    match signer {
+        Some(key) => match trusted.entries.iter().find(|s| &s.public_key == key) {
+            Some(s) => format!(" (signed by {}, {})", s.name, s.fingerprint),
+            None => format!(" (signed by untrusted key {})", signing::signer_fingerprint(key)),
+        },
+        None => String::new(),
+    }
+}
+
+// --- HELPER: Key Slots requested for a locked file ---
+
+/// Post-Quantum mode wraps the File Key for the vault's Hybrid identity
+/// instead of the Master Key. Keyfiles only apply to the Master Key slot.
+/// Locking for contacts implies it, and always includes our own identity
+/// so we can still open what we locked.
+fn resolve_recipients(
+    app: &AppHandle,
+    state: &tauri::State<'_, SessionState>,
+    master_key: &keychain::MasterKey,
+    post_quantum: Option<bool>,
+    recipient_ids: Option<Vec<String>>,
+    has_keyfile: bool,
+) -> Result<Vec<HybridPublicKey>, String> {
+    let recipient_ids = recipient_ids.unwrap_or_default();
+    if !post_quantum.unwrap_or(false) && recipient_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+    if has_keyfile {
+        return Err("Keyfiles cannot be combined with Post-Quantum mode.".to_string());
+    }
+
+    let path = resolve_keychain_path(app)?;
+    let identity = keychain::ensure_identity(&path, master_key).map_err(|e| e.to_string())?;
+    let mut keys = vec![identity.public_key()];
+
+    if !recipient_ids.is_empty() {
+        let contacts = load_contacts_vault(app.clone(), state.clone())?;
+        for id in &recipient_ids {
+            let contact = contacts.entries.iter().find(|c| &c.id == id)
+                .ok_or_else(|| format!("Unknown contact: {}", id))?;
+            if !keys.contains(&contact.public_key) {
+                keys.push(contact.public_key.clone());
+            }
+        }
+    }
+    Ok(keys)
+}
+
+/// Portable mode: a per-file passphrase replaces the Master Key slot.
+fn check_passphrase(passphrase: Option<&str>, has_keyfile: bool) -> Result<(), String> {
+    if let Some(p) = passphrase {
+        if p.is_empty() {
+            return Err("Passphrase cannot be empty.".to_string());
+        }
+        if has_keyfile {
+            return Err("Keyfiles cannot be combined with a passphrase.".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// SHA-256 of the Keyfile, from its bytes (Android) or its path (Desktop).
+fn hash_keyfile(keyfile_path: Option<String>, keyfile_bytes: Option<Vec<u8>>) -> Result<Option<Vec<u8>>, String> {
+    match keyfile_bytes {
+        Some(bytes) => Ok(Some(Sha256::digest(&bytes).to_vec())),
+        None => utils::process_keyfile(keyfile_path),
+    }
+}
+
+// --- HELPER: Vault stores (JSON documents sealed with the Master Key, next to keychain.json) ---
+// Returns `None` if the store was never saved.
+fn load_store<T: serde::de::DeserializeOwned>(path: &Path, master_key: &keychain::MasterKey, parse_error: &str) -> Result<Option<T>, String> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let payload = crypto_stream::load_store(path, master_key).map_err(|e| e.to_string())?;
+    serde_json::from_slice(&payload.content).map(Some).map_err(|_| parse_error.to_string())
+}
+
+fn save_store<T: serde::Serialize>(path: &Path, master_key: &keychain::MasterKey, json_name: &str, vault: &T) -> Result<(), String> {
+    let json_data = serde_json::to_vec(vault).map_err(|e| e.to_string())?;
+    crypto_stream::save_store(path, master_key, json_name, &json_data).map_err(|e| e.to_string())
+}
+
+// --- HELPER: Write a whole file via a temporary file (no partial output on failure) ---
+fn write_atomic(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+    let output = utils::AtomicFile::create(path)?;
+    output.file().write_all(data)?;
+    output.commit(path)
 }
 
 // --- METADATA CLEANER (RESTORED) ---
@@ -86,21 +185,8 @@ pub fn load_bookmarks_vault(app: AppHandle, state: tauri::State<SessionState>) -
     };
 
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("bookmarks.qre");
-    
-    if !path.exists() {
-        return Ok(BookmarksVault::new());
-    }
-
-    let container = crypto::EncryptedFileContainer::load(path.to_str().unwrap())
-        .map_err(|e| e.to_string())?;
-        
-    let payload = crypto::decrypt_file_with_master_key(&master_key, None, &container)
-        .map_err(|e| e.to_string())?;
-
-    let vault: BookmarksVault = serde_json::from_slice(&payload.content)
-        .map_err(|_| "Failed to parse bookmarks data".to_string())?;
-
-    Ok(vault)
+    let vault = load_store(&path, &master_key, "Failed to parse bookmarks data")?;
+    Ok(vault.unwrap_or_else(BookmarksVault::new))
 }
 
 #[tauri::command]
@@ -118,16 +204,7 @@ pub fn save_bookmarks_vault(
     };
 
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("bookmarks.qre");
-    
-    let json_data = serde_json::to_vec(&vault).map_err(|e| e.to_string())?;
-    
-    let container = crypto::encrypt_file_with_master_key(
-        &master_key, None, "bookmarks.json", &json_data, None, 3
-    ).map_err(|e| e.to_string())?;
-
-    container.save(path.to_str().unwrap()).map_err(|e| e.to_string())?;
-    
-    Ok(())
+    save_store(&path, &master_key, "bookmarks.json", &vault)
 }
 
 #[tauri::command]
@@ -198,19 +275,9 @@ pub fn load_clipboard_vault(
     };
 
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("clipboard.qre");
-    
-    if !path.exists() {
+    let Some(mut vault) = load_store::<ClipboardVault>(&path, &master_key, "Failed to parse clipboard data")? else {
         return Ok(ClipboardVault::new());
-    }
-
-    let container = crypto::EncryptedFileContainer::load(path.to_str().unwrap())
-        .map_err(|e| e.to_string())?;
-        
-    let payload = crypto::decrypt_file_with_master_key(&master_key, None, &container)
-        .map_err(|e| e.to_string())?;
-
-    let mut vault: ClipboardVault = serde_json::from_slice(&payload.content)
-        .map_err(|_| "Failed to parse clipboard data".to_string())?;
+    };
 
     // Auto-Cleanup logic
     let ttl_ms = retention_hours * 60 * 60 * 1000;
@@ -223,11 +290,7 @@ pub fn load_clipboard_vault(
     vault.entries.retain(|e| (now - e.created_at) < (ttl_ms as i64));
 
     if vault.entries.len() != initial_count {
-        let json_data = serde_json::to_vec(&vault).map_err(|e| e.to_string())?;
-        let container = crypto::encrypt_file_with_master_key(
-            &master_key, None, "clipboard.json", &json_data, None, 3
-        ).map_err(|e| e.to_string())?;
-        container.save(path.to_str().unwrap()).map_err(|e| e.to_string())?;
+        save_store(&path, &master_key, "clipboard.json", &vault)?;
     }
 
     Ok(vault)
@@ -248,14 +311,7 @@ pub fn save_clipboard_vault(
     };
 
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("clipboard.qre");
-    let json_data = serde_json::to_vec(&vault).map_err(|e| e.to_string())?;
-    
-    let container = crypto::encrypt_file_with_master_key(
-        &master_key, None, "clipboard.json", &json_data, None, 3
-    ).map_err(|e| e.to_string())?;
-
-    container.save(path.to_str().unwrap()).map_err(|e| e.to_string())?;
-    Ok(())
+    save_store(&path, &master_key, "clipboard.json", &vault)
 }
 
 #[tauri::command]
@@ -332,9 +388,25 @@ pub fn init_vault(app: AppHandle, password: String, state: tauri::State<SessionS
 #[tauri::command]
 pub fn login(app: AppHandle, password: String, state: tauri::State<SessionState>) -> CommandResult<String> {
     let path = resolve_keychain_path(&app)?;
+    let vault_dir = path.parent().unwrap();
+    // An interrupted Master Key rotation decides which keychain the password opens
+    rotation::recover_interrupted(vault_dir).map_err(|e| e.to_string())?;
     let master_key = keychain::unlock_keychain(&path, &password).map_err(|e| e.to_string())?;
     let mut guard = state.master_key.lock().unwrap();
-    *guard = Some(master_key);
+    *guard = Some(master_key.clone());
+    drop(guard);
+
+    // Locked files it did not reach yet are rewrapped in the background,
+    // the outcome is sent to the UI as a "qre:rewrap" event
+    let vault_dir = vault_dir.to_path_buf();
+    tauri::async_runtime::spawn_blocking(move || {
+        let files = match rotation::resume_rewrap(&vault_dir, &master_key) {
+            Ok(outcomes) if outcomes.is_empty() => return,
+            Ok(outcomes) => rewrap_results(outcomes),
+            Err(e) => vec![rotation_error(e)],
+        };
+        let _ = app.emit("qre:rewrap", files);
+    });
     Ok("Logged in".to_string())
 }
 
@@ -360,6 +432,7 @@ pub fn change_user_password(app: AppHandle, new_password: String, state: tauri::
 #[tauri::command]
 pub fn recover_vault(app: AppHandle, recovery_code: String, new_password: String, state: tauri::State<SessionState>) -> CommandResult<String> {
     let path = resolve_keychain_path(&app)?;
+    rotation::recover_interrupted(path.parent().unwrap()).map_err(|e| e.to_string())?;
     let master_key = keychain::recover_with_code(&path, &recovery_code, &new_password).map_err(|e| e.to_string())?;
     let mut guard = state.master_key.lock().unwrap();
     *guard = Some(master_key);
@@ -415,8 +488,8 @@ pub async fn delete_items(app: AppHandle, paths: Vec<String>) -> CommandResult<V
                 utils::emit_progress(&app, &format!("Deleting {}", filename), 50);
                 let res = if p.is_dir() { fs::remove_dir_all(p) } else { fs::remove_file(p) };
                 match res {
-                     Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Deleted".into() }),
-                     Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string() }),
+                     Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Deleted".into(), integrity_failed: false }),
+                     Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string(), integrity_failed: false }),
                 }
             }
 
@@ -424,8 +497,8 @@ pub async fn delete_items(app: AppHandle, paths: Vec<String>) -> CommandResult<V
             {
                 utils::emit_progress(&app, &format!("Preparing to shred {}", filename), 0);
                 match utils::shred_recursive(&app, p) {
-                    Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Deleted".into() }),
-                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e }),
+                    Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Deleted".into(), integrity_failed: false }),
+                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e, integrity_failed: false }),
                 }
             }
         }
@@ -433,6 +506,21 @@ pub async fn delete_items(app: AppHandle, paths: Vec<String>) -> CommandResult<V
     }).await.map_err(|e| e.to_string())?
 }
 
+/// Removes an original after it was locked (see `lock_file`), the way `delete_items` does.
+fn destroy_original(app: &AppHandle, path: &Path) -> Result<(), String> {
+    #[cfg(target_os = "android")]
+    {
+        let _ = app;
+        let res = if path.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+        res.map_err(|e| e.to_string())
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        utils::shred_recursive(app, path)
+    }
+}
+
 #[tauri::command]
 pub async fn trash_items(app: AppHandle, paths: Vec<String>) -> CommandResult<Vec<BatchItemResult>> {
     tauri::async_runtime::spawn_blocking(move || {
@@ -447,8 +535,8 @@ pub async fn trash_items(app: AppHandle, paths: Vec<String>) -> CommandResult<Ve
                 utils::emit_progress(&app, &format!("Deleting {}", filename), 50);
                 let res = if p.is_dir() { fs::remove_dir_all(p) } else { fs::remove_file(p) };
                 match res {
-                     Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Deleted (No Trash on Mobile)".into() }),
-                     Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string() }),
+                     Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Deleted (No Trash on Mobile)".into(), integrity_failed: false }),
+                     Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string(), integrity_failed: false }),
                 }
             }
 
@@ -456,8 +544,8 @@ pub async fn trash_items(app: AppHandle, paths: Vec<String>) -> CommandResult<Ve
             {
                 utils::emit_progress(&app, &format!("Trashing {}", filename), 50);
                 match utils::move_to_trash(p) {
-                    Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Moved to Trash".into() }),
-                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e }),
+                    Ok(_) => results.push(BatchItemResult { name: filename, success: true, message: "Moved to Trash".into(), integrity_failed: false }),
+                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e, integrity_failed: false }),
                 }
             }
         }
@@ -506,6 +594,7 @@ pub fn show_in_folder(path: String) -> CommandResult<()> {
 // --- CRYPTO LOGIC ---
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn lock_file(
     app: AppHandle,
     state: tauri::State<'_, SessionState>,
@@ -513,7 +602,20 @@ pub async fn lock_file(
     keyfile_path: Option<String>, 
     keyfile_bytes: Option<Vec<u8>>, 
     extra_entropy: Option<Vec<u8>>,
-    compression_mode: Option<String> 
+    compression_mode: Option<String>,
+    stream_compression: Option<bool>,
+    threads: Option<usize>,
+    cipher_suite: Option<CipherSuite>,
+    post_quantum: Option<bool>,
+    recipient_ids: Option<Vec<String>>,
+    passphrase: Option<String>,
+    symlink_policy: Option<SymlinkPolicy>,
+    padding: Option<PaddingScheme>,
+    fixed_size_chunks: Option<bool>,
+    sign: Option<bool>,
+    volume_size: Option<u64>,
+    recovery_overhead: Option<u32>,
+    shred_original: Option<bool>
 ) -> CommandResult<Vec<BatchItemResult>> {
     
     let master_key = {
@@ -524,14 +626,30 @@ pub async fn lock_file(
         }
     };
 
-    let keyfile_hash = if let Some(bytes) = keyfile_bytes {
-         let mut hasher = Sha256::new();
-         hasher.update(&bytes);
-         Some(hasher.finalize().to_vec())
+    let has_keyfile = keyfile_path.is_some() || keyfile_bytes.is_some();
+    let recipients = resolve_recipients(&app, &state, &master_key, post_quantum, recipient_ids, has_keyfile)?;
+    check_passphrase(passphrase.as_deref(), has_keyfile)?;
+
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+
+    if volume_size.is_some_and(|size| size < volumes::MIN_VOLUME_SIZE) {
+        return Err(format!("Volume size must be at least {} bytes.", volumes::MIN_VOLUME_SIZE));
+    }
+    if recovery_overhead.is_some_and(|pct| pct == 0 || pct > parity::MAX_OVERHEAD_PERCENT) {
+        return Err(format!("Recovery overhead must be between 1% and {}%.", parity::MAX_OVERHEAD_PERCENT));
+    }
+
+    let signer = if sign.unwrap_or(false) {
+        let path = resolve_keychain_path(&app)?;
+        Some(keychain::ensure_signing_key(&path, &master_key).map_err(|e| e.to_string())?)
     } else {
-         utils::process_keyfile(keyfile_path)?
+        None
     };
 
+    // Lock & Destroy: the new file is unlocked again (and compared) before the original goes
+    let shred_original = shred_original.unwrap_or(false);
+    let identity = if shred_original { load_vault_identity(&app, &master_key)? } else { None };
+
     let entropy_seed = if let Some(bytes) = extra_entropy {
         let mut hasher = Sha256::new();
         hasher.update(&bytes);
@@ -551,33 +669,19 @@ pub async fn lock_file(
             
             utils::emit_progress(&app, &format!("Preparing: {}", filename), 5);
 
+            // "auto": the engine stores incompressible chunks itself, whatever the file is called
             let level = match mode_str.as_str() {
                 "store" => 0,
                 "extreme" => 19,
-                "auto" | _ => {
-                    if is_already_compressed(&filename) { 1 } else { 3 }
-                }
-            };
-
-            let (input_path_str, is_temp) = if path.is_dir() {
-                let parent = path.parent().unwrap_or(Path::new("."));
-                let temp_zip_name = format!("{}.zip", filename);
-                let temp_zip_path = utils::get_unique_path(&parent.join(&temp_zip_name));
-                
-                utils::emit_progress(&app, &format!("Zipping Folder: {}", filename), 10);
-                
-                if let Err(e) = utils::zip_directory_to_file(path, &temp_zip_path) {
-                    results.push(BatchItemResult { name: filename.to_string(), success: false, message: format!("Zip failed: {}", e) });
-                    continue;
-                }
-                
-                (temp_zip_path.to_string_lossy().to_string(), true)
-            } else {
-                (file_path.clone(), false)
+                "auto" | _ => 3,
             };
 
+            // Split output: `final_path` is the base name of the volumes (".qre.001"...)
             let raw_output = format!("{}.qre", file_path);
-            let final_path = utils::get_unique_path(Path::new(&raw_output));
+            let final_path = match volume_size {
+                Some(_) => volumes::unique_base(Path::new(&raw_output)),
+                None => utils::get_unique_path(Path::new(&raw_output)),
+            };
             let final_path_str = final_path.to_string_lossy().to_string();
 
             let app_handle = app.clone();
@@ -586,32 +690,110 @@ pub async fn lock_file(
             let progress_cb = move |processed: u64, total: u64| {
                 if total > 0 {
                     let pct = (processed as f64 / total as f64 * 100.0) as u8;
-                    let display_pct = if is_temp { 20 + (pct as f64 * 0.8) as u8 } else { pct };
-                    utils::emit_progress(&app_handle, &format!("Encrypting: {}", f_name_clone), display_pct);
+                    utils::emit_progress(&app_handle, &format!("Encrypting: {}", f_name_clone), pct);
                 }
             };
 
+            let options = crypto_stream::StreamOptions {
+                compression_level: level,
+                // One Zstd stream across chunks: smaller large text/log archives, no ranged reads
+                stream_compression: stream_compression.unwrap_or(false),
+                threads: threads.unwrap_or(0),
+                // None = auto-detect (AES-GCM with hardware AES, XChaCha20 otherwise)
+                cipher_suite: cipher_suite.unwrap_or_else(CipherSuite::auto),
+                recipients: recipients.clone(),
+                passphrase: passphrase.clone(),
+                // Folders are archived directly into the encrypted stream
+                symlinks: symlink_policy.unwrap_or_default(),
+                padding: padding.unwrap_or_default(),
+                fixed_size_chunks: fixed_size_chunks.unwrap_or(false),
+                signer: signer.clone(),
+                volume_size,
+            };
+
             let encryption_result = crypto_stream::encrypt_file_stream(
-                &input_path_str,
+                &file_path,
                 &final_path_str,
                 &master_key,
                 keyfile_hash.as_deref(),
                 entropy_seed,
-                level,
+                &options,
                 progress_cb
             );
 
-            if is_temp { let _ = fs::remove_file(&input_path_str); }
+            // Recovery records next to the file (or to each volume)
+            let encryption_result = encryption_result.and_then(|_| {
+                let Some(overhead) = recovery_overhead else { return Ok(()) };
+                utils::emit_progress(&app, &format!("Writing recovery records: {}", filename), 99);
+                let outputs: Vec<PathBuf> = match volume_size {
+                    Some(_) => (1..).map(|i| volumes::volume_path(&final_path, i)).take_while(|p| p.exists()).collect(),
+                    None => vec![final_path.clone()],
+                };
+                for output in &outputs {
+                    parity::write_parity(output, overhead, threads.unwrap_or(0))
+                        .map_err(|e| anyhow::anyhow!("Locked, but writing recovery records failed: {}", e))?;
+                }
+                Ok(())
+            });
+
+            if let Err(e) = encryption_result {
+                // The engine already removed its partial output
+                results.push(BatchItemResult { name: filename.to_string(), success: false, message: e.to_string(), integrity_failed: false });
+                continue;
+            }
+            if !shred_original {
+                results.push(BatchItemResult { name: filename.to_string(), success: true, message: "Locked".into(), integrity_failed: false });
+                continue;
+            }
 
-            match encryption_result {
-                Ok(_) => {
-                    results.push(BatchItemResult { name: filename.to_string(), success: true, message: "Locked".into() });
+            // Lock & Destroy: 1. The locked copy must unlock to exactly the original
+            utils::emit_progress(&app, &format!("Verifying: {}", filename), 0);
+            let locked_path = match volume_size {
+                Some(_) => volumes::volume_path(&final_path, 1),
+                None => final_path.clone(),
+            };
+            let unlock_keys = crypto_stream::UnlockKeys {
+                master_key: &master_key,
+                keyfile_bytes: keyfile_hash.as_deref(),
+                identity: identity.as_ref(),
+                passphrase: passphrase.as_deref(),
+            };
+            let app_handle = app.clone();
+            let f_name_clone = filename.to_string();
+            let verified = crypto_stream::verify_against_source(
+                &locked_path.to_string_lossy(),
+                path,
+                symlink_policy.unwrap_or_default(),
+                &unlock_keys,
+                threads.unwrap_or(0),
+                move |processed, total| {
+                    if total > 0 {
+                        let pct = (processed as f64 / total as f64 * 100.0) as u8;
+                        utils::emit_progress(&app_handle, &format!("Verifying: {}", f_name_clone), pct);
+                    }
                 },
-                Err(e) => {
-                    let _ = fs::remove_file(&final_path);
-                    results.push(BatchItemResult { name: filename.to_string(), success: false, message: e.to_string() });
-                }
+            );
+            if let Err(e) = verified {
+                results.push(BatchItemResult {
+                    name: filename.to_string(),
+                    success: false,
+                    message: format!("Locked, but verification failed ({}). Original kept.", e),
+                    integrity_failed: e.downcast_ref::<crypto_stream::IntegrityError>().is_some(),
+                });
+                continue;
             }
+
+            // 2. Shred the original
+            utils::emit_progress(&app, &format!("Shredding original: {}", filename), 0);
+            results.push(match destroy_original(&app, path) {
+                Ok(_) => BatchItemResult { name: filename.to_string(), success: true, message: "Locked, verified and original shredded".into(), integrity_failed: false },
+                Err(e) => BatchItemResult {
+                    name: filename.to_string(),
+                    success: false,
+                    message: format!("Locked and verified, but shredding the original failed: {}", e),
+                    integrity_failed: false,
+                },
+            });
         }
         Ok(results)
     }).await.map_err(|e| e.to_string())?
@@ -623,7 +805,9 @@ pub async fn unlock_file(
     state: tauri::State<'_, SessionState>,
     file_paths: Vec<String>, 
     keyfile_path: Option<String>, 
-    keyfile_bytes: Option<Vec<u8>>
+    keyfile_bytes: Option<Vec<u8>>,
+    threads: Option<usize>,
+    passphrase: Option<String>
 ) -> CommandResult<Vec<BatchItemResult>> {
     
     let master_key = {
@@ -634,16 +818,22 @@ pub async fn unlock_file(
         }
     };
 
-    let keyfile_hash = if let Some(bytes) = keyfile_bytes {
-         let mut hasher = Sha256::new();
-         hasher.update(&bytes);
-         Some(hasher.finalize().to_vec())
-    } else {
-         utils::process_keyfile(keyfile_path)?
-    };
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+
+    let identity = load_vault_identity(&app, &master_key)?;
+    let signers_path = resolve_keychain_path(&app)?.parent().unwrap().join("signers.qre");
+    let trusted = TrustedSigners::load_for_report(&signers_path, &master_key);
 
     tauri::async_runtime::spawn_blocking(move || {
         let mut results = Vec::new();
+        let unlock_keys = crypto_stream::UnlockKeys {
+            master_key: &master_key,
+            keyfile_bytes: keyfile_hash.as_deref(),
+            identity: identity.as_ref(),
+            passphrase: passphrase.as_deref(),
+        };
+        // Volume sets already unlocked (any volume unlocks the whole set)
+        let mut unlocked_sets = std::collections::HashSet::new();
 
         for file_path in file_paths {
             let path = Path::new(&file_path);
@@ -653,20 +843,20 @@ pub async fn unlock_file(
             let mut file = match fs::File::open(path) {
                 Ok(f) => f,
                 Err(e) => {
-                    results.push(BatchItemResult { name: filename, success: false, message: e.to_string() });
+                    results.push(BatchItemResult { name: filename, success: false, message: e.to_string(), integrity_failed: false });
                     continue;
                 }
             };
             
             let mut ver_buf = [0u8; 4];
             if let Err(_) = file.read_exact(&mut ver_buf) {
-                results.push(BatchItemResult { name: filename, success: false, message: "Invalid file".into() });
+                results.push(BatchItemResult { name: filename, success: false, message: "Invalid file".into(), integrity_failed: false });
                 continue;
             }
             let version = u32::from_le_bytes(ver_buf);
 
             if version == 4 {
-                match crypto::EncryptedFileContainer::load(&file_path) {
+                match crypto::EncryptedFileContainer::load(path) {
                     Ok(container) => {
                         utils::emit_progress(&app, &format!("Decrypting (Legacy V4): {}", filename), 50);
                         match crypto::decrypt_file_with_master_key(&master_key, keyfile_hash.as_deref(), &container) {
@@ -675,19 +865,38 @@ pub async fn unlock_file(
                                 let parent = Path::new(&file_path).parent().unwrap_or(Path::new("."));
                                 let original_path = parent.join(&payload.filename);
                                 let final_path = utils::get_unique_path(&original_path);
-                                if let Err(e) = fs::write(&final_path, &payload.content) {
-                                    let _ = fs::remove_file(&final_path);
-                                    results.push(BatchItemResult { name: filename, success: false, message: e.to_string() });
+                                if let Err(e) = write_atomic(&final_path, &payload.content) {
+                                    results.push(BatchItemResult { name: filename, success: false, message: e.to_string(), integrity_failed: false });
                                 } else {
-                                    results.push(BatchItemResult { name: filename, success: true, message: "Unlocked".into() });
+                                    results.push(BatchItemResult { name: filename, success: true, message: "Unlocked".into(), integrity_failed: false });
                                 }
                             },
-                            Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string() }),
+                            Err(e) => results.push(BatchItemResult {
+                                name: filename,
+                                success: false,
+                                integrity_failed: e.downcast_ref::<crypto_stream::IntegrityError>().is_some(),
+                                message: e.to_string(),
+                            }),
+                        }
+                    },
+                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string(), integrity_failed: false }),
+                }
+            } else if crypto_stream::is_stream_version(version) || version == volumes::VOLUME_VERSION {
+                let set_id = match version {
+                    volumes::VOLUME_VERSION => match volumes::set_id(path) {
+                        Ok(set_id) => Some(set_id),
+                        Err(e) => {
+                            results.push(BatchItemResult { name: filename, success: false, message: e.to_string(), integrity_failed: false });
+                            continue;
                         }
                     },
-                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string() }),
+                    _ => None,
+                };
+                if set_id.is_some_and(|id| unlocked_sets.contains(&id)) {
+                    results.push(BatchItemResult { name: filename, success: true, message: "Unlocked with another volume".into(), integrity_failed: false });
+                    continue;
                 }
-            } else if version == 5 {
+
                 let parent = Path::new(&file_path).parent().unwrap_or(Path::new("."));
                 let output_dir_str = parent.to_string_lossy().to_string();
 
@@ -704,21 +913,661 @@ pub async fn unlock_file(
                 match crypto_stream::decrypt_file_stream(
                     &file_path, 
                     &output_dir_str, 
-                    &master_key, 
-                    keyfile_hash.as_deref(), 
+                    &unlock_keys,
+                    threads.unwrap_or(0),
                     progress_cb
                 ) {
-                    Ok(out_name) => results.push(BatchItemResult { name: filename, success: true, message: format!("Unlocked: {}", out_name) }),
-                    Err(e) => results.push(BatchItemResult { name: filename, success: false, message: e.to_string() }),
+                    Ok(unlocked) => {
+                        unlocked_sets.extend(set_id);
+                        results.push(BatchItemResult {
+                            name: filename,
+                            success: true,
+                            message: format!("Unlocked: {}{}", unlocked.filename, describe_signer(&unlocked.signer, &trusted)),
+                            integrity_failed: false,
+                        })
+                    }
+                    Err(e) => results.push(BatchItemResult {
+                        name: filename,
+                        success: false,
+                        integrity_failed: e.downcast_ref::<crypto_stream::IntegrityError>().is_some(),
+                        message: e.to_string(),
+                    }),
                 }
             } else {
-                results.push(BatchItemResult { name: filename, success: false, message: format!("Unsupported Version: {}", version) });
+                results.push(BatchItemResult { name: filename, success: false, message: format!("Unsupported Version: {}", version), integrity_failed: false });
             }
         }
         Ok(results)
     }).await.map_err(|e| e.to_string())?
 }
 
+#[derive(serde::Serialize)]
+pub struct VerifyItemResult {
+    pub name: String,
+    pub success: bool,
+    pub message: String,
+    // Index of the first chunk that failed, when the damage is in the file body.
+    pub bad_chunk: Option<u64>,
+}
+
+/// Expands folders into the .qre files they contain (recursively). Files are kept as given.
+/// A split file is listed once, by its first volume (or by every volume found if the
+/// first one is missing, so the gap gets reported).
+fn expand_qre_targets(file_paths: Vec<String>) -> Vec<PathBuf> {
+    let is_qre = |p: &Path| p.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("qre"));
+    let is_listed_volume = |p: &Path| match volumes::split_volume_path(p) {
+        Some((base, index)) => is_qre(&base) && (index == 1 || !volumes::volume_path(&base, 1).exists()),
+        None => false,
+    };
+
+    let mut targets = Vec::new();
+    for file_path in file_paths {
+        let path = PathBuf::from(&file_path);
+        if path.is_dir() {
+            targets.extend(
+                walkdir::WalkDir::new(&path)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                    .map(|e| e.into_path())
+                    .filter(|p| is_qre(p) || is_listed_volume(p)),
+            );
+        } else {
+            targets.push(path);
+        }
+    }
+    targets
+}
+
+/// Checks that locked files are intact and unlock with the current keys,
+/// without writing any plaintext. Folders are searched recursively for .qre files,
+/// so a whole backup drive can be checked in one go.
+#[tauri::command]
+pub async fn verify_files(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    file_paths: Vec<String>,
+    keyfile_path: Option<String>,
+    keyfile_bytes: Option<Vec<u8>>,
+    passphrase: Option<String>,
+    threads: Option<usize>
+) -> CommandResult<Vec<VerifyItemResult>> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked.".to_string()),
+        }
+    };
+
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+
+    let identity = load_vault_identity(&app, &master_key)?;
+    let signers_path = resolve_keychain_path(&app)?.parent().unwrap().join("signers.qre");
+    let trusted = TrustedSigners::load_for_report(&signers_path, &master_key);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let unlock_keys = crypto_stream::UnlockKeys {
+            master_key: &master_key,
+            keyfile_bytes: keyfile_hash.as_deref(),
+            identity: identity.as_ref(),
+            passphrase: passphrase.as_deref(),
+        };
+
+        let targets = expand_qre_targets(file_paths);
+
+        let mut results = Vec::new();
+        let total = targets.len();
+        for (i, path) in targets.into_iter().enumerate() {
+            let name = path.to_string_lossy().to_string();
+            let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            utils::emit_progress(&app, &format!("Verifying ({}/{}): {}", i + 1, total, filename), ((i * 100) / total.max(1)) as u8);
+
+            let mut version_buf = [0u8; 4];
+            let version = match fs::File::open(&path).and_then(|mut f| f.read_exact(&mut version_buf)) {
+                Ok(_) => u32::from_le_bytes(version_buf),
+                Err(e) => {
+                    results.push(VerifyItemResult { name, success: false, message: e.to_string(), bad_chunk: None });
+                    continue;
+                }
+            };
+
+            let outcome = if version == 4 {
+                crypto::EncryptedFileContainer::load(&path)
+                    .and_then(|c| crypto::verify_container(&master_key, keyfile_hash.as_deref(), &c))
+                    .map(|filename| format!("OK: {}", filename))
+            } else if crypto_stream::is_stream_version(version) || version == volumes::VOLUME_VERSION {
+                crypto_stream::verify_file_stream(&name, &unlock_keys, threads.unwrap_or(0), |_, _| {})
+                    .map(|r| {
+                        format!(
+                            "OK: {} ({} bytes, {} chunks){}",
+                            r.filename,
+                            r.original_size,
+                            r.chunk_count,
+                            describe_signer(&r.signer, &trusted)
+                        )
+                    })
+            } else {
+                Err(anyhow::anyhow!("Unsupported Version: {}", version))
+            };
+
+            results.push(match outcome {
+                Ok(message) => VerifyItemResult { name, success: true, message, bad_chunk: None },
+                Err(e) => VerifyItemResult {
+                    name,
+                    success: false,
+                    bad_chunk: e.downcast_ref::<crypto_stream::ChunkError>().map(|c| c.index),
+                    message: e.to_string(),
+                },
+            });
+        }
+        utils::emit_progress(&app, "Verification complete", 100);
+        Ok(results)
+    }).await.map_err(|e| e.to_string())?
+}
+
+/// Changes how locked files are protected without re-encrypting them:
+/// adds or removes a Keyfile, or converts them to Post-Quantum / Portable mode.
+/// Only the header is rewritten, and each file is replaced atomically.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn rewrap_file(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    file_paths: Vec<String>,
+    keyfile_path: Option<String>,
+    keyfile_bytes: Option<Vec<u8>>,
+    passphrase: Option<String>,
+    new_keyfile_path: Option<String>,
+    new_keyfile_bytes: Option<Vec<u8>>,
+    new_passphrase: Option<String>,
+    post_quantum: Option<bool>,
+    recipient_ids: Option<Vec<String>>
+) -> CommandResult<Vec<BatchItemResult>> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked.".to_string()),
+        }
+    };
+
+    // Current protection
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+    let identity = load_vault_identity(&app, &master_key)?;
+
+    // New protection
+    let new_has_keyfile = new_keyfile_path.is_some() || new_keyfile_bytes.is_some();
+    let recipients = resolve_recipients(&app, &state, &master_key, post_quantum, recipient_ids, new_has_keyfile)?;
+    check_passphrase(new_passphrase.as_deref(), new_has_keyfile)?;
+    let new_keyfile_hash = hash_keyfile(new_keyfile_path, new_keyfile_bytes)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let unlock_keys = crypto_stream::UnlockKeys {
+            master_key: &master_key,
+            keyfile_bytes: keyfile_hash.as_deref(),
+            identity: identity.as_ref(),
+            passphrase: passphrase.as_deref(),
+        };
+
+        let mut results = Vec::new();
+        // Volume sets already rewrapped (any volume rewraps the whole set)
+        let mut rewrapped_sets = std::collections::HashSet::new();
+        let total = file_paths.len();
+        for (i, file_path) in file_paths.into_iter().enumerate() {
+            let filename = Path::new(&file_path).file_name().unwrap_or_default().to_string_lossy().to_string();
+            utils::emit_progress(&app, &format!("Rewrapping: {}", filename), ((i * 100) / total.max(1)) as u8);
+
+            let set_id = volumes::set_id(Path::new(&file_path)).ok();
+            if set_id.is_some_and(|id| rewrapped_sets.contains(&id)) {
+                results.push(BatchItemResult { name: filename, success: true, message: "Rewrapped with another volume".into(), integrity_failed: false });
+                continue;
+            }
+
+            let mut version_buf = [0u8; 4];
+            let outcome = match fs::File::open(&file_path).and_then(|mut f| f.read_exact(&mut version_buf)) {
+                Err(e) => Err(e.to_string()),
+                Ok(_) if crypto_stream::is_stream_version(u32::from_le_bytes(version_buf))
+                    || u32::from_le_bytes(version_buf) == volumes::VOLUME_VERSION =>
+                {
+                    crypto_stream::rewrap_file_stream(
+                        &file_path,
+                        &unlock_keys,
+                        &master_key,
+                        new_keyfile_hash.as_deref(),
+                        &recipients,
+                        new_passphrase.as_deref(),
+                    )
+                    .map_err(|e| e.to_string())
+                }
+                Ok(_) => Err("Only stream files (V5/V6) can be rewrapped. Unlock and lock this file again.".to_string()),
+            };
+
+            results.push(match outcome {
+                Ok(()) => {
+                    rewrapped_sets.extend(set_id);
+                    BatchItemResult { name: filename, success: true, message: "Rewrapped".into(), integrity_failed: false }
+                }
+                Err(message) => BatchItemResult { name: filename, success: false, message, integrity_failed: false },
+            });
+        }
+        utils::emit_progress(&app, "Rewrap complete", 100);
+        Ok(results)
+    }).await.map_err(|e| e.to_string())?
+}
+
+/// Converts legacy V4 containers to the current stream format, in place.
+/// Each converted file is decrypted and compared with the original before it replaces it.
+/// Folders are searched recursively for .qre files; files already in the stream format are skipped.
+#[tauri::command]
+pub async fn migrate_files(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    file_paths: Vec<String>,
+    keyfile_path: Option<String>,
+    keyfile_bytes: Option<Vec<u8>>,
+) -> CommandResult<Vec<BatchItemResult>> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked.".to_string()),
+        }
+    };
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let targets = expand_qre_targets(file_paths);
+
+        let mut results = Vec::new();
+        let total = targets.len();
+        for (i, path) in targets.into_iter().enumerate() {
+            let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            utils::emit_progress(&app, &format!("Migrating ({}/{}): {}", i + 1, total, filename), ((i * 100) / total.max(1)) as u8);
+
+            results.push(match crypto_stream::migrate_container(&path, &master_key, keyfile_hash.as_deref()) {
+                Ok(true) => BatchItemResult { name: filename, success: true, message: "Migrated".into(), integrity_failed: false },
+                Ok(false) => BatchItemResult { name: filename, success: true, message: "Already up to date".into(), integrity_failed: false },
+                Err(e) => BatchItemResult { name: filename, success: false, message: format!("{:#}", e), integrity_failed: false },
+            });
+        }
+        utils::emit_progress(&app, "Migration complete", 100);
+        Ok(results)
+    }).await.map_err(|e| e.to_string())?
+}
+
+#[derive(serde::Serialize)]
+pub struct RotationReport {
+    // Replaces the old code, which only opens the old Master Key.
+    pub recovery_code: String,
+    pub files: Vec<BatchItemResult>,
+}
+
+/// A rewrap pass that could not run at all (e.g., the journal is unreadable).
+fn rotation_error(e: anyhow::Error) -> BatchItemResult {
+    BatchItemResult { name: "Master Key rotation".into(), success: false, message: format!("{:#}", e), integrity_failed: false }
+}
+
+fn rewrap_results(outcomes: Vec<RewrapOutcome>) -> Vec<BatchItemResult> {
+    outcomes
+        .into_iter()
+        .map(|o| BatchItemResult {
+            name: Path::new(&o.path).file_name().unwrap_or_default().to_string_lossy().to_string(),
+            success: o.success,
+            message: o.message,
+            integrity_failed: false,
+        })
+        .collect()
+}
+
+/// Replaces the Master Key: re-encrypts every vault store, re-seals the keychain
+/// (with a new Recovery Code) and moves the chosen locked files to the new key.
+/// Folders in `file_paths` are searched recursively for .qre files.
+/// The session stays locked for other commands while the vault part runs (a few small
+/// files), so nothing is saved with the old key meanwhile. It is released before the
+/// locked files are rewrapped.
+#[tauri::command]
+pub async fn rotate_master_key(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    password: String,
+    file_paths: Vec<String>,
+    keyfile_path: Option<String>,
+    keyfile_bytes: Option<Vec<u8>>,
+) -> CommandResult<RotationReport> {
+    let session = state.master_key.clone();
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+    let vault_dir = resolve_keychain_path(&app)?.parent().unwrap().to_path_buf();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let files = expand_qre_targets(file_paths);
+
+        // 1. Vault stores & keychain
+        let outcome = {
+            let mut guard = session.lock().unwrap();
+            let master_key = match &*guard {
+                Some(mk) => mk.clone(),
+                None => return Err("Vault is locked.".to_string()),
+            };
+            utils::emit_progress(&app, "Rotating Master Key...", 0);
+            let outcome = rotation::rotate_vault(&vault_dir, &password, &master_key, &files, keyfile_hash.as_deref())
+                .map_err(|e| format!("{:#}", e))?;
+            *guard = Some(outcome.new_master_key.clone());
+            outcome
+        };
+
+        // 2. Locked files. The vault already uses the new key: failures are reported, not returned,
+        // so the new Recovery Code is never lost
+        utils::emit_progress(&app, "Rewrapping locked files...", 10);
+        let files = match rotation::resume_rewrap(&vault_dir, &outcome.new_master_key) {
+            Ok(outcomes) => rewrap_results(outcomes),
+            Err(e) => vec![rotation_error(e)],
+        };
+        utils::emit_progress(&app, "Rotation complete", 100);
+
+        Ok(RotationReport { recovery_code: outcome.recovery_code, files })
+    }).await.map_err(|e| e.to_string())?
+}
+
+/// Retries the locked files the last rotation could not move to the new Master Key
+/// (e.g., once their drive is back). A Keyfile given here replaces the one used by the rotation.
+#[tauri::command]
+pub async fn retry_key_rotation(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    keyfile_path: Option<String>,
+    keyfile_bytes: Option<Vec<u8>>,
+) -> CommandResult<Vec<BatchItemResult>> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked.".to_string()),
+        }
+    };
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+    let vault_dir = resolve_keychain_path(&app)?.parent().unwrap().to_path_buf();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        utils::emit_progress(&app, "Rewrapping locked files...", 0);
+        let results = match rotation::retry_failed(&vault_dir, &master_key, keyfile_hash.as_deref()) {
+            Ok(outcomes) => rewrap_results(outcomes),
+            Err(e) => vec![rotation_error(e)],
+        };
+        utils::emit_progress(&app, "Rewrap complete", 100);
+        Ok(results)
+    }).await.map_err(|e| e.to_string())?
+}
+
+/// Gives up the locked files the last rotation could not move, once the user has confirmed:
+/// the old Master Key is wiped. Returns those files, which this vault can no longer open.
+#[tauri::command]
+pub fn forget_old_master_key(app: AppHandle, state: tauri::State<SessionState>) -> CommandResult<Vec<String>> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked.".to_string()),
+        }
+    };
+    let vault_dir = resolve_keychain_path(&app)?.parent().unwrap().to_path_buf();
+    rotation::forget_old_master_key(&vault_dir, &master_key).map_err(|e| format!("{:#}", e))
+}
+
+/// Reports the version, cipher, key slots and chunk layout of .qre files
+/// from their headers alone. Needs no key, so it works while the vault is locked.
+/// When it is unlocked, signers are also looked up in the trusted signers.
+#[tauri::command]
+pub async fn inspect_qre(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    file_paths: Vec<String>
+) -> CommandResult<Vec<InspectReport>> {
+    let trusted = load_trusted_signers(app, state).ok();
+
+    tauri::async_runtime::spawn_blocking(move || {
+        expand_qre_targets(file_paths)
+            .iter()
+            .map(|path| {
+                let mut report = inspect::inspect_qre(path);
+                if let (Some(fingerprint), Some(trusted)) = (&report.signer, &trusted) {
+                    report.trusted_signer = Some(trusted.entries.iter().any(|s| &s.fingerprint == fingerprint));
+                }
+                report
+            })
+            .collect()
+    }).await.map_err(|e| e.to_string())
+}
+
+/// Rebuilds damaged parts of locked files from their recovery records (`.qre.parity`),
+/// before they are unlocked. Needs no key.
+#[tauri::command]
+pub async fn repair_qre(app: AppHandle, file_paths: Vec<String>, threads: Option<usize>) -> CommandResult<Vec<BatchItemResult>> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let total = file_paths.len();
+        let mut results = Vec::new();
+        for (i, file_path) in file_paths.into_iter().enumerate() {
+            let path = Path::new(&file_path);
+            let filename = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            utils::emit_progress(&app, &format!("Repairing ({}/{}): {}", i + 1, total, filename), ((i * 100) / total.max(1)) as u8);
+
+            results.push(match parity::repair_file(path, threads.unwrap_or(0)) {
+                Ok(r) if r.unrecoverable_groups > 0 => BatchItemResult {
+                    name: filename,
+                    success: false,
+                    message: format!(
+                        "{} damaged parts, too many to repair ({} areas beyond the recovery records). File left untouched.",
+                        r.damaged_shards, r.unrecoverable_groups
+                    ),
+                    integrity_failed: false,
+                },
+                Ok(r) if r.damaged_shards > 0 => {
+                    BatchItemResult { name: filename, success: true, message: format!("Repaired {} damaged parts", r.repaired_shards), integrity_failed: false }
+                }
+                Ok(_) => BatchItemResult { name: filename, success: true, message: "No damage found".into(), integrity_failed: false },
+                Err(e) => BatchItemResult { name: filename, success: false, message: format!("{:#}", e), integrity_failed: false },
+            });
+        }
+        utils::emit_progress(&app, "Repair complete", 100);
+        Ok(results)
+    }).await.map_err(|e| e.to_string())?
+}
+
+#[derive(serde::Serialize)]
+pub struct LockedRange {
+    pub filename: String,
+    pub total_size: u64,
+    pub data: Vec<u8>,
+}
+
+/// Reads `length` bytes starting at `offset` from inside a locked V6 file,
+/// decrypting only the chunks that cover that range.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+pub async fn read_locked_range(
+    app: AppHandle,
+    state: tauri::State<'_, SessionState>,
+    file_path: String,
+    offset: u64,
+    length: u64,
+    keyfile_path: Option<String>,
+    keyfile_bytes: Option<Vec<u8>>,
+    passphrase: Option<String>
+) -> CommandResult<LockedRange> {
+    use std::io::{Seek, SeekFrom};
+
+    if length > MAX_RANGE_READ {
+        return Err(format!("Range too large (max {} bytes).", MAX_RANGE_READ));
+    }
+
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked.".to_string()),
+        }
+    };
+
+    let keyfile_hash = hash_keyfile(keyfile_path, keyfile_bytes)?;
+
+    let identity = load_vault_identity(&app, &master_key)?;
+
+    tauri::async_runtime::spawn_blocking(move || {
+        let unlock_keys = crypto_stream::UnlockKeys {
+            master_key: &master_key,
+            keyfile_bytes: keyfile_hash.as_deref(),
+            identity: identity.as_ref(),
+            passphrase: passphrase.as_deref(),
+        };
+        let mut reader = QreReader::open(Path::new(&file_path), &unlock_keys)
+            .map_err(|e| e.to_string())?;
+        reader.seek(SeekFrom::Start(offset)).map_err(|e| e.to_string())?;
+
+        let filename = reader.metadata().filename.clone();
+        let total_size = reader.total_size();
+        let mut data = Vec::new();
+        reader.take(length).read_to_end(&mut data).map_err(|e| e.to_string())?;
+        Ok(LockedRange { filename, total_size, data })
+    }).await.map_err(|e| e.to_string())?
+}
+
+// --- IDENTITY & CONTACTS ---
+
+#[derive(serde::Serialize)]
+pub struct IdentityExport {
+    pub fingerprint: String,
+    pub identity: String,
+}
+
+/// Returns this vault's public identity as a line of text to share with others.
+#[tauri::command]
+pub fn export_identity(app: AppHandle, state: tauri::State<SessionState>) -> CommandResult<IdentityExport> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked".to_string()),
+        }
+    };
+    let path = resolve_keychain_path(&app)?;
+    let public_key = keychain::ensure_identity(&path, &master_key).map_err(|e| e.to_string())?.public_key();
+    Ok(IdentityExport {
+        fingerprint: public_key.fingerprint_hex(),
+        identity: public_key.to_export_string().map_err(|e| e.to_string())?,
+    })
+}
+
+/// Adds someone's exported identity to our contacts, so files can be locked for them.
+#[tauri::command]
+pub fn import_identity(
+    app: AppHandle,
+    state: tauri::State<SessionState>,
+    name: String,
+    identity: String
+) -> CommandResult<Contact> {
+    let public_key = HybridPublicKey::from_export_string(&identity).map_err(|e| e.to_string())?;
+    let mut vault = load_contacts_vault(app.clone(), state.clone())?;
+    let contact = vault.upsert(name, public_key);
+    save_contacts_vault(app, state, vault)?;
+    Ok(contact)
+}
+
+#[tauri::command]
+pub fn load_contacts_vault(app: AppHandle, state: tauri::State<SessionState>) -> CommandResult<ContactsVault> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked".to_string()),
+        }
+    };
+    let path = resolve_keychain_path(&app)?.parent().unwrap().join("contacts.qre");
+    let vault = load_store(&path, &master_key, "Failed to parse contacts data")?;
+    Ok(vault.unwrap_or_else(ContactsVault::new))
+}
+
+#[tauri::command]
+pub fn save_contacts_vault(app: AppHandle, state: tauri::State<SessionState>, vault: ContactsVault) -> CommandResult<()> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked".to_string()),
+        }
+    };
+    let path = resolve_keychain_path(&app)?.parent().unwrap().join("contacts.qre");
+    save_store(&path, &master_key, "contacts.json", &vault)
+}
+
+// --- SIGNING ---
+
+/// Returns the fingerprint of this vault's signing key, for others to compare
+/// with what their copy of the app reports for our files.
+#[tauri::command]
+pub fn get_signing_fingerprint(app: AppHandle, state: tauri::State<SessionState>) -> CommandResult<String> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked".to_string()),
+        }
+    };
+    let path = resolve_keychain_path(&app)?;
+    let signing_key = keychain::ensure_signing_key(&path, &master_key).map_err(|e| e.to_string())?;
+    Ok(signing::signer_fingerprint(&signing_key.verifying_key().to_bytes()))
+}
+
+/// Adds the signer of a .qre file to our trusted signers, once its signature checks out.
+/// The user is expected to compare the fingerprint with the sender first.
+#[tauri::command]
+pub fn trust_signer(
+    app: AppHandle,
+    state: tauri::State<SessionState>,
+    file_path: String,
+    name: String
+) -> CommandResult<TrustedSigner> {
+    let path = Path::new(&file_path);
+    let report = inspect::inspect_qre(path);
+    if report.signature_valid != Some(true) {
+        return Err("This file has no valid signature.".to_string());
+    }
+    let mut file = std::io::BufReader::new(fs::File::open(path).map_err(|e| e.to_string())?);
+    let raw = crypto_stream::read_stream_header(&mut file).map_err(|e| e.to_string())?;
+    let public_key = raw.header.signer.ok_or("This file is not signed.")?;
+
+    let mut signers = load_trusted_signers(app.clone(), state.clone())?;
+    let signer = signers.upsert(name, public_key);
+    save_trusted_signers(app, state, signers)?;
+    Ok(signer)
+}
+
+#[tauri::command]
+pub fn load_trusted_signers(app: AppHandle, state: tauri::State<SessionState>) -> CommandResult<TrustedSigners> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked".to_string()),
+        }
+    };
+    let path = resolve_keychain_path(&app)?.parent().unwrap().join("signers.qre");
+    let signers = load_store(&path, &master_key, "Failed to parse trusted signers")?;
+    Ok(signers.unwrap_or_else(TrustedSigners::new))
+}
+
+#[tauri::command]
+pub fn save_trusted_signers(app: AppHandle, state: tauri::State<SessionState>, signers: TrustedSigners) -> CommandResult<()> {
+    let master_key = {
+        let guard = state.master_key.lock().unwrap();
+        match &*guard {
+            Some(mk) => mk.clone(),
+            None => return Err("Vault is locked".to_string()),
+        }
+    };
+    let path = resolve_keychain_path(&app)?.parent().unwrap().join("signers.qre");
+    save_store(&path, &master_key, "signers.json", &signers)
+}
+
 // --- VAULT COMMANDS ---
 #[tauri::command]
 pub fn load_password_vault(app: AppHandle, state: tauri::State<SessionState>) -> CommandResult<PasswordVault> {
@@ -730,11 +1579,8 @@ pub fn load_password_vault(app: AppHandle, state: tauri::State<SessionState>) ->
         }
     };
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("passwords.qre");
-    if !path.exists() { return Ok(PasswordVault::new()); }
-    let container = crypto::EncryptedFileContainer::load(path.to_str().unwrap()).map_err(|e| e.to_string())?;
-    let payload = crypto::decrypt_file_with_master_key(&master_key, None, &container).map_err(|e| e.to_string())?;
-    let vault: PasswordVault = serde_json::from_slice(&payload.content).map_err(|_| "Failed to parse vault".to_string())?;
-    Ok(vault)
+    let vault = load_store(&path, &master_key, "Failed to parse vault")?;
+    Ok(vault.unwrap_or_else(PasswordVault::new))
 }
 
 #[tauri::command]
@@ -747,10 +1593,7 @@ pub fn save_password_vault(app: AppHandle, state: tauri::State<SessionState>, va
         }
     };
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("passwords.qre");
-    let json_data = serde_json::to_vec(&vault).map_err(|e| e.to_string())?;
-    let container = crypto::encrypt_file_with_master_key(&master_key, None, "passwords.json", &json_data, None, 3).map_err(|e| e.to_string())?;
-    container.save(path.to_str().unwrap()).map_err(|e| e.to_string())?;
-    Ok(())
+    save_store(&path, &master_key, "passwords.json", &vault)
 }
 
 #[tauri::command]
@@ -763,11 +1606,8 @@ pub fn load_notes_vault(app: AppHandle, state: tauri::State<SessionState>) -> Co
         }
     };
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("notes.qre");
-    if !path.exists() { return Ok(NotesVault::new()); }
-    let container = crypto::EncryptedFileContainer::load(path.to_str().unwrap()).map_err(|e| e.to_string())?;
-    let payload = crypto::decrypt_file_with_master_key(&master_key, None, &container).map_err(|e| e.to_string())?;
-    let vault: NotesVault = serde_json::from_slice(&payload.content).map_err(|_| "Failed to parse notes".to_string())?;
-    Ok(vault)
+    let vault = load_store(&path, &master_key, "Failed to parse notes")?;
+    Ok(vault.unwrap_or_else(NotesVault::new))
 }
 
 #[tauri::command]
@@ -780,8 +1620,5 @@ pub fn save_notes_vault(app: AppHandle, state: tauri::State<SessionState>, vault
         }
     };
     let path = resolve_keychain_path(&app)?.parent().unwrap().join("notes.qre");
-    let json_data = serde_json::to_vec(&vault).map_err(|e| e.to_string())?;
-    let container = crypto::encrypt_file_with_master_key(&master_key, None, "notes.json", &json_data, None, 3).map_err(|e| e.to_string())?;
-    container.save(path.to_str().unwrap()).map_err(|e| e.to_string())?;
-    Ok(())
+    save_store(&path, &master_key, "notes.json", &vault)
 }
\ No newline at end of file
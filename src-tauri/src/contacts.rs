@@ -0,0 +1,42 @@
+use crate::hybrid::HybridPublicKey;
+use serde::{Deserialize, Serialize};
+
+/// Someone else's QRE identity, imported so we can lock files for them.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Contact {
+    pub id: String,
+    pub name: String,
+    // Short fingerprint to compare with the sender out of band.
+    pub fingerprint: String,
+    pub public_key: HybridPublicKey,
+    pub created_at: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ContactsVault {
+    pub entries: Vec<Contact>,
+}
+
+impl ContactsVault {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// Adds a contact, or renames it if the same identity was already imported.
+    pub fn upsert(&mut self, name: String, public_key: HybridPublicKey) -> Contact {
+        if let Some(existing) = self.entries.iter_mut().find(|c| c.public_key == public_key) {
+            existing.name = name;
+            return existing.clone();
+        }
+
+        let contact = Contact {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            fingerprint: public_key.fingerprint_hex(),
+            public_key,
+            created_at: chrono::Utc::now().timestamp_millis(),
+        };
+        self.entries.push(contact.clone());
+        contact
+    }
+}
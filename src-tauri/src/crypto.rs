@@ -1,17 +1,26 @@
+use crate::crypto_stream::{bounded_bincode, check_len, malformed, IntegrityError};
 use crate::keychain::MasterKey;
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
 use anyhow::{anyhow, Context, Result};
+use bincode::Options;
+#[cfg(test)]
 use rand::{rngs::OsRng, RngCore, SeedableRng};
+#[cfg(test)]
 use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::io::{Cursor, Read};
+use std::path::Path;
 use zeroize::{Zeroize, ZeroizeOnDrop};
 
 const AES_NONCE_LEN: usize = 12;
+const AES_TAG_LEN: usize = 16;
+
+// Upper bound for the serialized V4 header. Real headers are about 200 bytes.
+const MAX_HEADER_LEN: usize = 64 * 1024;
 const VALIDATION_MAGIC: &[u8] = b"QRE_VALID";
 
 // --- Data Structures ---
@@ -42,7 +51,8 @@ pub struct EncryptedFileContainer {
 }
 
 impl EncryptedFileContainer {
-    // Restored: Needed to save the Password Vault
+    // V4 is no longer written (see `crypto_stream::migrate_container`). Kept to build test fixtures.
+    #[cfg(test)]
     pub fn save(&self, path: &str) -> Result<()> {
         let file = std::fs::File::create(path).context("Failed to create output file")?;
         let writer = std::io::BufWriter::new(file);
@@ -50,25 +60,62 @@ impl EncryptedFileContainer {
         Ok(())
     }
 
-    pub fn load(path: &str) -> Result<Self> {
-        let mut file = std::fs::File::open(path).context("Failed to open encrypted file")?;
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = std::fs::File::open(path).context("Failed to open encrypted file")?;
+        let file_len = file.metadata()?.len();
+        Self::read_from(std::io::BufReader::new(file), file_len)
+    }
 
+    /// Parses a V4 container. `total_len` (the file size) bounds the ciphertext,
+    /// so its length prefix is checked before the buffer is allocated.
+    pub fn read_from(mut reader: impl Read, total_len: u64) -> Result<Self> {
         let mut ver_buf = [0u8; 4];
-        file.read_exact(&mut ver_buf).context("Failed to read version")?;
+        reader.read_exact(&mut ver_buf).context("Failed to read version")?;
         let version = u32::from_le_bytes(ver_buf);
+        if version != 4 {
+            return Err(anyhow!("Unsupported or legacy file version: {}.", version));
+        }
+
+        let header = read_container_header(&mut reader)?;
 
-        file.seek(SeekFrom::Start(0))?;
-        let reader = std::io::BufReader::new(file);
+        let mut len_buf = [0u8; 8];
+        reader.read_exact(&mut len_buf).context("File is truncated: the ciphertext is missing")?;
+        let ciphertext_len = u64::from_le_bytes(len_buf);
+        let consumed = 4 + bincode::serialized_size(&header)? + 8;
+        if ciphertext_len > total_len.saturating_sub(consumed) {
+            return Err(malformed(format!("Ciphertext length {} exceeds the file size", ciphertext_len)));
+        }
+
+        let mut ciphertext = vec![0u8; ciphertext_len as usize];
+        reader.read_exact(&mut ciphertext).context("File is truncated")?;
+        Ok(Self { version, header, ciphertext })
+    }
+}
 
-        if version == 4 {
-            let container: Self = bincode::deserialize_from(reader).context("Failed to parse V4 file")?;
-            Ok(container)
-        } else {
-            Err(anyhow!("Unsupported or legacy file version: {}.", version))
+impl EncryptedFileHeader {
+    /// Checks the size of every field (AES-256-GCM), so a crafted file cannot make decryption panic.
+    pub fn validate(&self) -> Result<()> {
+        check_len("Validation nonce", &self.validation_nonce, AES_NONCE_LEN)?;
+        check_len("Validation tag", &self.encrypted_validation_tag, VALIDATION_MAGIC.len() + AES_TAG_LEN)?;
+        check_len("Key wrapping nonce", &self.key_wrapping_nonce, AES_NONCE_LEN)?;
+        check_len("Wrapped file key", &self.encrypted_file_key, 32 + AES_TAG_LEN)?;
+        check_len("Body nonce", &self.body_nonce, AES_NONCE_LEN)?;
+        if let Some(hash) = &self.original_hash {
+            check_len("Original hash", hash, 32)?;
         }
+        Ok(())
     }
 }
 
+/// Reads and validates the header of a V4 container (the version bytes already consumed).
+pub fn read_container_header(reader: &mut impl Read) -> Result<EncryptedFileHeader> {
+    let header: EncryptedFileHeader = bounded_bincode(MAX_HEADER_LEN)
+        .deserialize_from(reader)
+        .map_err(|e| malformed(format!("Failed to parse V4 header: {}", e)))?;
+    header.validate()?;
+    Ok(header)
+}
+
 // --- Helper Functions ---
 
 fn derive_wrapping_key(master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) -> [u8; 32] {
@@ -88,7 +135,7 @@ fn derive_wrapping_key(master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) ->
     key
 }
 
-// Restored: Needed for encryption
+#[cfg(test)]
 fn compress_data(data: &[u8], level: i32) -> Result<Vec<u8>> {
     zstd::stream::encode_all(Cursor::new(data), level).map_err(|e| anyhow!("Compression failed: {}", e))
 }
@@ -97,8 +144,11 @@ fn decompress_data(data: &[u8]) -> Result<Vec<u8>> {
     zstd::stream::decode_all(Cursor::new(data)).map_err(|e| anyhow!("Decompression failed: {}", e))
 }
 
-// --- ENCRYPTION (Restored for Password Vault & Folders) ---
+// --- ENCRYPTION ---
+// Vault stores and files are written in the stream format (crypto_stream.rs) now.
+// The V4 writer only remains to build legacy fixtures in tests.
 
+#[cfg(test)]
 pub fn encrypt_file_with_master_key(
     master_key: &MasterKey,
     keyfile_bytes: Option<&[u8]>,
@@ -181,6 +231,7 @@ pub fn decrypt_file_with_master_key(
     container: &EncryptedFileContainer,
 ) -> Result<InnerPayload> {
     let h = &container.header;
+    h.validate()?;
 
     if h.uses_keyfile && keyfile_bytes.is_none() {
         return Err(anyhow!("This file requires a Keyfile. Please select it."));
@@ -220,9 +271,21 @@ pub fn decrypt_file_with_master_key(
     if let Some(expected_hash) = &h.original_hash {
         let actual_hash = Sha256::digest(&payload.content).to_vec();
         if &actual_hash != expected_hash {
-            return Err(anyhow!("INTEGRITY ERROR: Hash mismatch. File is corrupted."));
+            return Err(IntegrityError("Hash mismatch. File is corrupted.".to_string()).into());
         }
     }
 
     Ok(payload)
+}
+
+/// Checks a V4 container (password, body tag and content hash) without keeping
+/// the plaintext: it only lives in RAM and is wiped when the payload drops.
+/// Returns the original filename.
+pub fn verify_container(
+    master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+    container: &EncryptedFileContainer,
+) -> Result<String> {
+    let payload = decrypt_file_with_master_key(master_key, keyfile_bytes, container)?;
+    Ok(payload.filename.clone())
 }
\ No newline at end of file
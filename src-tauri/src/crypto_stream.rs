@@ -1,65 +1,521 @@
+use crate::archive::{self, SymlinkPolicy};
+use crate::cipher::{AeadCipher, CipherSuite};
+use crate::crypto;
+use crate::hybrid::{self, HybridEncapsulation, HybridPublicKey, HybridSecretKey};
 use crate::keychain::MasterKey;
+use crate::parity;
+use crate::pipeline;
+use crate::signing;
 use crate::utils;
-use aes_gcm::{
-    aead::{Aead, KeyInit},
-    Aes256Gcm, Nonce,
-};
+use crate::volumes::{self, VolumeSet};
 use anyhow::{anyhow, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bincode::Options;
+use ed25519_dalek::SigningKey;
+use hmac::{Hmac, Mac};
 use rand::{rngs::OsRng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use zeroize::Zeroize;
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, Write};
+use zeroize::{Zeroize, Zeroizing};
+
+type HmacSha256 = Hmac<Sha256>;
 
 // --- CONSTANTS ---
 
 // The size of data blocks read from disk into RAM.
 // 1MB is a "Sweet Spot": Small enough for low-end phones, large enough for fast I/O.
-const CHUNK_SIZE: usize = 1 * 1024 * 1024; 
+pub(crate) const CHUNK_SIZE: usize = 1 * 1024 * 1024; 
 
-// Standard AES-GCM nonce length (12 bytes).
-const AES_NONCE_LEN: usize = 12;
 
 // The File Encryption Key (FEK) is always 256-bit (32 bytes).
 const FILE_KEY_LEN: usize = 32;
 
-// Protocol Version 5: Denotes the new Streaming Format.
-const CURRENT_VERSION: u32 = 5; 
+// Protocol Version 6: Streaming Format with an authenticated header and a final-chunk marker.
+const CURRENT_VERSION: u32 = 6;
+
+// Protocol Version 5: The original Streaming Format. Still readable, never written.
+const LEGACY_STREAM_VERSION: u32 = 5;
+
+// Upper bound for the serialized V6 header. Real headers are a few hundred bytes.
+const MAX_HEADER_LEN: usize = 64 * 1024;
+
+// Length of the HMAC-SHA256 tag stored right after the V6 header.
+const HEADER_MAC_LEN: usize = 32;
 
 // A magic string encrypted in the header to verify the password quickly.
 const VALIDATION_MAGIC: &[u8] = b"QRE_VALID";
 
+// Argon2id settings for Portable (passphrase) files. Stronger than the keychain's,
+// since the file may travel somewhere an attacker can grind on it offline.
+const PASSPHRASE_KDF_MEMORY: u32 = 65536; // 64 MB of RAM required
+const PASSPHRASE_KDF_ITERATIONS: u32 = 3;
+const PASSPHRASE_KDF_PARALLELISM: u32 = 1;
+
+// Upper bounds accepted from a header, so a crafted file cannot make us allocate gigabytes.
+const MAX_KDF_MEMORY: u32 = 1024 * 1024; // 1 GB
+const MAX_KDF_ITERATIONS: u32 = 64;
+
+// Accepted salt lengths of a Password slot (Argon2 needs at least 8 bytes, we write 16).
+const MIN_SALT_LEN: usize = 8;
+const MAX_SALT_LEN: usize = 64;
+
+// Sealed size of every data chunk with `fixed_size_chunks`: a full 1MB chunk plus
+// room for its coding byte and the 4-byte padding frame.
+const FIXED_CHUNK_BODY: usize = CHUNK_SIZE + 1024;
+
+// First byte of every data chunk body (streams with `adaptive_chunks`).
+const CHUNK_STORED: u8 = 0;
+const CHUNK_ZSTD: u8 = 1;
+
+// Byte entropy (bits per byte, 8.0 = random) above which a chunk is stored without
+// trying Zstd: media, archives and encrypted data. Measured on evenly spread samples.
+const INCOMPRESSIBLE_ENTROPY: f64 = 7.9;
+const ENTROPY_SAMPLES: usize = 64;
+const ENTROPY_SAMPLE_LEN: usize = 1024;
+
+// Zstd window of stream-wide compression (2^27 = 128MB), with long-distance matching:
+// repeats that far apart in the stream are still found. The decoder needs as much RAM.
+const STREAM_WINDOW_LOG: u32 = 27;
+
+// AEAD tag length (16 bytes for both AES-256-GCM and XChaCha20-Poly1305).
+const TAG_LEN: usize = 16;
+
+// --- OPTIONS ---
+
+/// Tuning knobs for the streaming engine. None of them are needed to decrypt.
+#[derive(Clone)]
+pub struct StreamOptions {
+    // Zstd level (0 = Store, 1 = Fast, 19 = Max). Incompressible chunks are stored at any level.
+    pub compression_level: i32,
+    // Worker threads for compression & encryption. 0 = one per CPU core.
+    pub threads: usize,
+    // AEAD used for the key wrap, metadata and every chunk.
+    pub cipher_suite: CipherSuite,
+    // Hybrid public keys to wrap the File Key for (Post-Quantum mode).
+    // Empty (and no passphrase) = a single Master Key (+ Keyfile) slot.
+    pub recipients: Vec<HybridPublicKey>,
+    // Per-file passphrase (Portable mode): the file opens without our vault.
+    pub passphrase: Option<String>,
+    // Symbolic links inside a locked folder.
+    pub symlinks: SymlinkPolicy,
+    // Hides the exact file length.
+    pub padding: PaddingScheme,
+    // Pads every data chunk to the same ciphertext size, hiding how well each compressed.
+    pub fixed_size_chunks: bool,
+    // Compresses the whole stream as one Zstd frame (see `StreamCompressor`) instead of
+    // each chunk on its own. Better ratio on large text and logs, but compression runs
+    // on one thread and the file cannot be read by range (`QreReader`).
+    pub stream_compression: bool,
+    // Signs the file with the vault's Ed25519 key, so recipients can tell who produced it.
+    pub signer: Option<SigningKey>,
+    // Splits the output into `name.qre.001`, `.002`... files of this many bytes
+    // (e.g., for FAT32 drives). Only used by `encrypt_file_stream`.
+    pub volume_size: Option<u64>,
+}
+
+/// Length-hiding padding for the whole file. The padding lives inside sealed
+/// chunks, so it is authenticated like the data and stripped on unlock.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PaddingScheme {
+    #[default]
+    None,
+    /// Rounds the file size up to a Padmé length: only the top bits of the size
+    /// remain visible, for at most ~12% overhead (less on large files).
+    Padme,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            compression_level: 3,
+            threads: 0,
+            cipher_suite: CipherSuite::auto(),
+            recipients: Vec::new(),
+            passphrase: None,
+            symlinks: SymlinkPolicy::default(),
+            padding: PaddingScheme::None,
+            fixed_size_chunks: false,
+            stream_compression: false,
+            signer: None,
+            volume_size: None,
+        }
+    }
+}
+
+/// The secrets the caller holds. Every Key Slot in the header is tried
+/// against the one it applies to.
+pub struct UnlockKeys<'a> {
+    pub master_key: &'a MasterKey,
+    pub keyfile_bytes: Option<&'a [u8]>,
+    // The vault's identity, needed for files locked in Post-Quantum mode.
+    pub identity: Option<&'a HybridSecretKey>,
+    // Only needed for Portable files.
+    pub passphrase: Option<&'a str>,
+}
+
 // --- HEADER STRUCTURE ---
 
-/// The metadata stored at the beginning of a V5 (.qre) file.
+/// The metadata stored at the beginning of a V6 (.qre) file.
 /// It contains everything needed to derive keys and verify the password,
 /// but DOES NOT contain the file data itself.
+/// Nothing in here reveals the file: its name and attributes live in the sealed metadata block.
+///
+/// Fields are encoded by position (bincode), so this layout is frozen for V6: adding,
+/// removing or reordering a field needs a new `CURRENT_VERSION`, with this struct kept
+/// as a legacy header read by its own arm of `read_stream_header` (as V5 is).
+/// `test_v6_header_layout_is_frozen` fails on any change to the encoding.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct StreamHeader {
-    // Used to verify if the entered password is correct before attempting decryption.
-    pub validation_nonce: Vec<u8>,
-    pub encrypted_validation_tag: Vec<u8>,
+    // The AEAD used for everything below. Decides the nonce lengths (12 or 24 bytes).
+    pub cipher_suite: CipherSuite,
 
-    // The random "File Key" is encrypted using the User's "Master Key".
+    // The random "File Key", wrapped once for each way the file can be opened.
     // This allows changing the password without re-encrypting the whole file.
-    pub key_wrapping_nonce: Vec<u8>,
-    pub encrypted_file_key: Vec<u8>,
+    pub key_slots: Vec<KeySlot>,
 
     // The starting nonce for the file body.
     // Individual chunk nonces are derived from this + the chunk index.
     pub base_nonce: Vec<u8>,
 
-    // Preserves the original filename (e.g., "video.mp4") so it can be restored.
+    // The `FileMetadata` block, encrypted with a key derived from the File Key.
+    pub metadata_nonce: Vec<u8>,
+    pub encrypted_metadata: Vec<u8>,
+
+    // Length hiding. When either is set, every sealed record is a padding frame
+    // (see `frame_padded`) and records with an empty body are pure padding.
+    pub padding: PaddingScheme,
+    pub fixed_size_chunks: bool,
+
+    // Ed25519 public key of the signer. When set, the stream ends with a signature
+    // record after the Trailer (see `signature_hasher`).
+    pub signer: Option<[u8; 32]>,
+
+    // Every data chunk body starts with its coding byte (`CHUNK_STORED` or `CHUNK_ZSTD`).
+    // When unset, every chunk is compressed as a whole (V5 streams) or is part of the stream frame below.
+    pub adaptive_chunks: bool,
+
+    // Data chunks are consecutive pieces of a single Zstd frame (see `StreamCompressor`)
+    // and only decode in order.
+    pub stream_compression: bool,
+}
+
+impl StreamHeader {
+    fn is_padded(&self) -> bool {
+        self.padding != PaddingScheme::None || self.fixed_size_chunks
+    }
+
+    /// Starts the digest a signature covers: every header field except the Key Slots
+    /// (so the file can be rewrapped without losing its signature), then every record
+    /// up to the signature record. The Trailer seals the plaintext hash, so this
+    /// covers the content too, and can be checked without any key.
+    pub(crate) fn signature_hasher(&self) -> Result<Sha256> {
+        let signed = (
+            self.cipher_suite,
+            &self.base_nonce,
+            &self.metadata_nonce,
+            &self.encrypted_metadata,
+            self.padding,
+            self.fixed_size_chunks,
+            self.signer,
+            self.adaptive_chunks,
+            self.stream_compression,
+        );
+        let mut hasher = Sha256::new();
+        hasher.update(CURRENT_VERSION.to_le_bytes());
+        hasher.update(bincode::serialize(&signed)?);
+        Ok(hasher)
+    }
+
+    /// Checks every field against the sizes the cipher suite implies, so later code
+    /// can slice nonces and keys without panicking. `sealed_metadata` is false for V5.
+    fn validate(&self, sealed_metadata: bool) -> Result<()> {
+        let nonce_len = self.cipher_suite.nonce_len();
+        let wrapped_key_len = FILE_KEY_LEN + TAG_LEN;
+
+        check_len("Base nonce", &self.base_nonce, nonce_len)?;
+        if sealed_metadata {
+            check_len("Metadata nonce", &self.metadata_nonce, nonce_len)?;
+            if self.encrypted_metadata.len() < TAG_LEN {
+                return Err(malformed("Sealed metadata is shorter than its tag"));
+            }
+        }
+        if self.key_slots.is_empty() {
+            return Err(malformed("No key slots"));
+        }
+
+        for slot in &self.key_slots {
+            match slot {
+                KeySlot::MasterKey { validation_nonce, encrypted_validation_tag, key_wrapping_nonce, encrypted_file_key, .. } => {
+                    check_len("Validation nonce", validation_nonce, nonce_len)?;
+                    check_len("Validation tag", encrypted_validation_tag, VALIDATION_MAGIC.len() + TAG_LEN)?;
+                    check_len("Key wrapping nonce", key_wrapping_nonce, nonce_len)?;
+                    check_len("Wrapped file key", encrypted_file_key, wrapped_key_len)?;
+                }
+                KeySlot::Hybrid { encapsulation, key_wrapping_nonce, encrypted_file_key, .. } => {
+                    check_len("ML-KEM ciphertext", &encapsulation.mlkem_ciphertext, hybrid::MLKEM_CIPHERTEXT_LEN)?;
+                    check_len("Key wrapping nonce", key_wrapping_nonce, nonce_len)?;
+                    check_len("Wrapped file key", encrypted_file_key, wrapped_key_len)?;
+                }
+                KeySlot::Password { kdf, key_wrapping_nonce, encrypted_file_key } => {
+                    if !(MIN_SALT_LEN..=MAX_SALT_LEN).contains(&kdf.salt.len()) {
+                        return Err(malformed(format!("KDF salt is {} bytes", kdf.salt.len())));
+                    }
+                    if kdf.memory_kib > MAX_KDF_MEMORY || kdf.iterations > MAX_KDF_ITERATIONS || kdf.parallelism == 0 {
+                        return Err(malformed("KDF parameters out of range"));
+                    }
+                    check_len("Key wrapping nonce", key_wrapping_nonce, nonce_len)?;
+                    check_len("Wrapped file key", encrypted_file_key, wrapped_key_len)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One wrapped copy of the File Key. Any single slot is enough to unlock the file.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum KeySlot {
+    /// Wrapped with the User's Master Key (+ Keyfile).
+    MasterKey {
+        // Used to verify if the entered password is correct before attempting decryption.
+        validation_nonce: Vec<u8>,
+        encrypted_validation_tag: Vec<u8>,
+        key_wrapping_nonce: Vec<u8>,
+        encrypted_file_key: Vec<u8>,
+        // Same flag as the V4 header: lets us ask for the Keyfile up front.
+        uses_keyfile: bool,
+    },
+    /// Wrapped with a key encapsulated to a Hybrid (ML-KEM-768 + X25519) public key.
+    /// Stays confidential even if the ciphertext is harvested today and attacked
+    /// with a quantum computer later.
+    Hybrid {
+        // Fingerprint of the recipient's public key.
+        recipient: [u8; 32],
+        encapsulation: HybridEncapsulation,
+        key_wrapping_nonce: Vec<u8>,
+        encrypted_file_key: Vec<u8>,
+    },
+    /// Wrapped with a key derived from a per-file passphrase (Portable mode).
+    /// Anyone with the passphrase can open the file, no vault required.
+    Password {
+        kdf: KdfParams,
+        key_wrapping_nonce: Vec<u8>,
+        encrypted_file_key: Vec<u8>,
+    },
+}
+
+/// Argon2id settings of a Password slot, stored so they can be raised later
+/// without breaking existing files.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KdfParams {
+    pub salt: Vec<u8>,
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+/// The header of the original V5 Streaming Format.
+/// Kept only to read (and rewrap) old files; the filename is stored here in plaintext.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LegacyStreamHeader {
+    pub validation_nonce: Vec<u8>,
+    pub encrypted_validation_tag: Vec<u8>,
+    pub key_wrapping_nonce: Vec<u8>,
+    pub encrypted_file_key: Vec<u8>,
+    pub base_nonce: Vec<u8>,
     pub original_filename: String,
-    
-    // Optional integrity hash (reserved for future use).
     pub original_hash: Option<Vec<u8>>,
 }
 
+/// The final record of a V6 stream, encrypted like a chunk and flagged as "last".
+/// Lets the decryptor prove it saw every byte of the original file.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StreamTrailer {
+    // SHA-256 of the complete plaintext.
+    pub plaintext_hash: Vec<u8>,
+    pub total_size: u64,
+    pub chunk_count: u64,
+}
+
+/// Returned when every chunk authenticates but the reassembled file does not match
+/// the trailer. Callers can `downcast_ref` to tell corruption apart from a wrong password.
+#[derive(Debug)]
+pub struct IntegrityError(pub String);
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "INTEGRITY ERROR: {}", self.0)
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+/// Returned when a specific chunk cannot be read or authenticated, so callers
+/// (e.g. `verify_file_stream`) can report which part of the file is damaged.
+#[derive(Debug)]
+pub struct ChunkError {
+    pub index: u64,
+    pub reason: String,
+}
+
+impl std::fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.reason)
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+fn chunk_error(index: u64, reason: String) -> anyhow::Error {
+    ChunkError { index, reason }.into()
+}
+
+/// Returned when a header field has an impossible size or value (crafted or damaged file).
+/// Raised while parsing, before any key is derived or any large buffer is allocated.
+#[derive(Debug)]
+pub struct MalformedHeader(pub String);
+
+impl std::fmt::Display for MalformedHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MALFORMED HEADER: {}", self.0)
+    }
+}
+
+impl std::error::Error for MalformedHeader {}
+
+pub(crate) fn malformed(reason: impl Into<String>) -> anyhow::Error {
+    MalformedHeader(reason.into()).into()
+}
+
+/// Checks the length of a header field.
+pub(crate) fn check_len(field: &str, value: &[u8], expected: usize) -> Result<()> {
+    if value.len() != expected {
+        return Err(malformed(format!("{} is {} bytes, expected {}", field, value.len(), expected)));
+    }
+    Ok(())
+}
+
+/// Returned when a file is protected by a Password slot and no passphrase was given.
+/// The frontend asks the user for it and retries.
+#[derive(Debug)]
+pub struct PassphraseRequired;
+
+impl std::fmt::Display for PassphraseRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PASSPHRASE REQUIRED: This file is protected by its own passphrase.")
+    }
+}
+
+impl std::error::Error for PassphraseRequired {}
+
+/// Per-file attributes sealed inside the V6 header and restored on unlock.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct FileMetadata {
+    // Original filename (e.g., "tax_return_2025.pdf").
+    pub filename: String,
+    // Size of the plaintext in bytes.
+    pub original_size: u64,
+    // Last modification time (seconds since the Unix Epoch).
+    pub modified: Option<u64>,
+    // Unix permission bits. `None` on platforms without them (Windows).
+    pub permissions: Option<u32>,
+    pub readonly: bool,
+    // Guessed from the extension (e.g., "application/pdf").
+    pub mime_type: Option<String>,
+    // The plaintext is a folder archive (see `archive.rs`), restored as a folder.
+    pub archive: bool,
+}
+
+impl FileMetadata {
+    /// Collects the attributes of a file on disk.
+    fn from_path(path: &std::path::Path) -> Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        let filename = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs());
+
+        #[cfg(unix)]
+        let permissions = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(meta.permissions().mode())
+        };
+        #[cfg(not(unix))]
+        let permissions = None;
+
+        Ok(Self {
+            mime_type: mime_guess::from_path(&filename).first_raw().map(String::from),
+            filename,
+            original_size: meta.len(),
+            modified,
+            permissions,
+            readonly: meta.permissions().readonly(),
+            archive: false,
+        })
+    }
+
+    /// Metadata for data that does not come from a file on disk (stdin, a socket...).
+    /// `size_hint` is only used for progress reporting (0 if unknown).
+    pub fn for_stream(filename: &str, size_hint: Option<u64>) -> Self {
+        Self {
+            filename: filename.to_string(),
+            original_size: size_hint.unwrap_or(0),
+            mime_type: mime_guess::from_path(filename).first_raw().map(String::from),
+            ..Default::default()
+        }
+    }
+
+    /// Applies the stored timestamp and permissions to a restored file.
+    /// Failures are ignored: the content is what matters, attributes are best-effort.
+    fn apply_to(&self, file: &File, path: &std::path::Path) {
+        if let Some(secs) = self.modified {
+            let _ = file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs));
+        }
+
+        #[cfg(unix)]
+        if let Some(mode) = self.permissions {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode));
+        }
+        #[cfg(not(unix))]
+        if self.readonly {
+            if let Ok(meta) = std::fs::metadata(path) {
+                let mut perms = meta.permissions();
+                perms.set_readonly(true);
+                let _ = std::fs::set_permissions(path, perms);
+            }
+        }
+    }
+}
+
 // --- HELPER FUNCTIONS ---
 
+/// Returns true if `version` is handled by this engine (rather than the V4 container).
+pub fn is_stream_version(version: u32) -> bool {
+    version == CURRENT_VERSION || version == LEGACY_STREAM_VERSION
+}
+
+/// The encoding of `bincode::serialize` (fixed-width integers), with a cap on the total
+/// number of bytes read. Without it, a crafted length prefix in an untrusted file makes
+/// bincode allocate the announced size up front (gigabytes) before failing.
+pub(crate) fn bounded_bincode(limit: usize) -> impl bincode::Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit as u64)
+}
+
 /// Combines the User's Master Key (Password based) with the Keyfile (if present)
 /// to create the "Wrapping Key". This key is used to encrypt the File Key.
 fn derive_wrapping_key(master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) -> [u8; 32] {
@@ -79,6 +535,135 @@ fn derive_wrapping_key(master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) ->
     key
 }
 
+/// Wraps the File Key with the Master Key (+ Keyfile), including the validation tag
+/// used to report a wrong password before touching the file body.
+fn wrap_for_master_key(
+    suite: CipherSuite,
+    file_key: &[u8],
+    master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+    rng: &mut dyn RngCore,
+) -> Result<KeySlot> {
+    let mut wrapping_key = derive_wrapping_key(master_key, keyfile_bytes);
+    let cipher_wrap = AeadCipher::new(suite, &wrapping_key)?;
+    wrapping_key.zeroize();
+
+    let mut validation_nonce = vec![0u8; suite.nonce_len()];
+    rng.fill_bytes(&mut validation_nonce);
+    let encrypted_validation_tag = cipher_wrap
+        .encrypt(&validation_nonce, VALIDATION_MAGIC, &[])
+        .map_err(|_| anyhow!("Validation failed"))?;
+
+    let mut key_wrapping_nonce = vec![0u8; suite.nonce_len()];
+    rng.fill_bytes(&mut key_wrapping_nonce);
+    let encrypted_file_key = cipher_wrap
+        .encrypt(&key_wrapping_nonce, file_key, &[])
+        .map_err(|_| anyhow!("File Key Wrap failed"))?;
+
+    Ok(KeySlot::MasterKey {
+        validation_nonce,
+        encrypted_validation_tag,
+        key_wrapping_nonce,
+        encrypted_file_key,
+        uses_keyfile: keyfile_bytes.is_some(),
+    })
+}
+
+/// Wraps the File Key once for each Hybrid recipient, plus the passphrase if any.
+/// Falls back to a single Master Key (+ Keyfile) slot when neither is given.
+fn build_key_slots(
+    suite: CipherSuite,
+    file_key: &[u8],
+    master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+    recipients: &[HybridPublicKey],
+    passphrase: Option<&str>,
+    rng: &mut dyn RngCore,
+) -> Result<Vec<KeySlot>> {
+    let mut key_slots = recipients
+        .iter()
+        .map(|recipient| wrap_for_recipient(suite, file_key, recipient, &mut *rng))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(passphrase) = passphrase {
+        key_slots.push(wrap_for_passphrase(suite, file_key, passphrase, &mut *rng)?);
+    }
+    if key_slots.is_empty() {
+        key_slots.push(wrap_for_master_key(suite, file_key, master_key, keyfile_bytes, &mut *rng)?);
+    }
+    Ok(key_slots)
+}
+
+/// Wraps the File Key with a fresh Hybrid (ML-KEM-768 + X25519) encapsulation to `recipient`.
+fn wrap_for_recipient(
+    suite: CipherSuite,
+    file_key: &[u8],
+    recipient: &HybridPublicKey,
+    rng: &mut dyn RngCore,
+) -> Result<KeySlot> {
+    let mut seed = [0u8; 32];
+    rng.fill_bytes(&mut seed);
+    let (encapsulation, mut wrapping_key) = hybrid::encapsulate(recipient, seed)?;
+    seed.zeroize();
+    let cipher_wrap = AeadCipher::new(suite, &wrapping_key)?;
+    wrapping_key.zeroize();
+
+    let mut key_wrapping_nonce = vec![0u8; suite.nonce_len()];
+    rng.fill_bytes(&mut key_wrapping_nonce);
+    let encrypted_file_key = cipher_wrap
+        .encrypt(&key_wrapping_nonce, file_key, &[])
+        .map_err(|_| anyhow!("File Key Wrap failed"))?;
+
+    Ok(KeySlot::Hybrid {
+        recipient: recipient.fingerprint(),
+        encapsulation,
+        key_wrapping_nonce,
+        encrypted_file_key,
+    })
+}
+
+/// Turns a per-file passphrase into a Wrapping Key using Argon2id.
+fn derive_passphrase_key(passphrase: &str, kdf: &KdfParams) -> Result<[u8; 32]> {
+    if kdf.memory_kib > MAX_KDF_MEMORY || kdf.iterations > MAX_KDF_ITERATIONS {
+        return Err(anyhow!("KDF parameters out of range (corrupt file?)"));
+    }
+    let params = Params::new(kdf.memory_kib, kdf.iterations, kdf.parallelism, Some(32))
+        .map_err(|e| anyhow!("Invalid KDF parameters: {}", e))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), &kdf.salt, &mut key)
+        .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+    Ok(key)
+}
+
+/// Wraps the File Key with a passphrase, for files that must open without our vault.
+fn wrap_for_passphrase(
+    suite: CipherSuite,
+    file_key: &[u8],
+    passphrase: &str,
+    rng: &mut dyn RngCore,
+) -> Result<KeySlot> {
+    let mut salt = vec![0u8; 16];
+    rng.fill_bytes(&mut salt);
+    let kdf = KdfParams {
+        salt,
+        memory_kib: PASSPHRASE_KDF_MEMORY,
+        iterations: PASSPHRASE_KDF_ITERATIONS,
+        parallelism: PASSPHRASE_KDF_PARALLELISM,
+    };
+
+    let mut wrapping_key = derive_passphrase_key(passphrase, &kdf)?;
+    let cipher_wrap = AeadCipher::new(suite, &wrapping_key)?;
+    wrapping_key.zeroize();
+
+    let mut key_wrapping_nonce = vec![0u8; suite.nonce_len()];
+    rng.fill_bytes(&mut key_wrapping_nonce);
+    let encrypted_file_key = cipher_wrap
+        .encrypt(&key_wrapping_nonce, file_key, &[])
+        .map_err(|_| anyhow!("File Key Wrap failed"))?;
+
+    Ok(KeySlot::Password { kdf, key_wrapping_nonce, encrypted_file_key })
+}
+
 /// Compresses a single 1MB chunk using Zstd.
 /// The `level` parameter determines the compression strength (1 = Fast, 19 = Max).
 fn compress_chunk(data: &[u8], level: i32) -> Result<Vec<u8>> {
@@ -87,43 +672,357 @@ fn compress_chunk(data: &[u8], level: i32) -> Result<Vec<u8>> {
     Ok(encoder.finish()?)
 }
 
+/// Shannon entropy (bits per byte) of evenly spread samples of `data`.
+fn sampled_entropy(data: &[u8]) -> f64 {
+    let mut counts = [0u64; 256];
+    let stride = (data.len() / ENTROPY_SAMPLES).max(ENTROPY_SAMPLE_LEN);
+    for start in (0..data.len()).step_by(stride) {
+        for &byte in &data[start..(start + ENTROPY_SAMPLE_LEN).min(data.len())] {
+            counts[byte as usize] += 1;
+        }
+    }
+    let total = counts.iter().sum::<u64>() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / total;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Builds the body of a data chunk: `[coding byte][data]`.
+/// Chunks that look random are stored as-is without running Zstd (saves CPU on media),
+/// and so are chunks Zstd could not shrink. Level 0 stores every chunk.
+fn encode_chunk(data: &[u8], level: i32) -> Result<Vec<u8>> {
+    // Small chunks give a noisy estimate: just try them
+    let worth_trying = data.len() < ENTROPY_SAMPLES * ENTROPY_SAMPLE_LEN / 4 || sampled_entropy(data) < INCOMPRESSIBLE_ENTROPY;
+    if level > 0 && worth_trying {
+        let compressed = compress_chunk(data, level)?;
+        if compressed.len() < data.len() {
+            let mut body = Vec::with_capacity(1 + compressed.len());
+            body.push(CHUNK_ZSTD);
+            body.extend_from_slice(&compressed);
+            return Ok(body);
+        }
+    }
+    let mut body = Vec::with_capacity(1 + data.len());
+    body.push(CHUNK_STORED);
+    body.extend_from_slice(data);
+    Ok(body)
+}
+
+/// Stream-wide compression: one Zstd frame over the whole plaintext, flushed at the
+/// end of every chunk. Each record then holds exactly one chunk's worth of plaintext
+/// and decodes as soon as the records before it have been decoded.
+struct StreamCompressor {
+    // `None` once the frame is closed.
+    encoder: Option<zstd::Encoder<'static, Vec<u8>>>,
+}
+
+impl StreamCompressor {
+    fn new(level: i32) -> Result<Self> {
+        let mut encoder = zstd::Encoder::new(Vec::new(), level)?;
+        encoder.long_distance_matching(true)?;
+        encoder.window_log(STREAM_WINDOW_LOG)?;
+        Ok(Self { encoder: Some(encoder) })
+    }
+
+    /// Compresses the next chunk. `last` closes the frame (an empty last chunk
+    /// still gives the closing bytes). Returns `None` once the frame is closed.
+    fn segment(&mut self, chunk: &[u8], last: bool) -> Result<Option<Vec<u8>>> {
+        let Some(encoder) = self.encoder.as_mut() else {
+            return Ok(None);
+        };
+        encoder.write_all(chunk)?;
+        if last {
+            return Ok(self.encoder.take().map(|e| e.finish()).transpose()?);
+        }
+        encoder.flush()?;
+        Ok(Some(std::mem::take(encoder.get_mut())))
+    }
+}
+
+/// Decodes the records of a stream-compressed file, in order (see `StreamCompressor`).
+pub(crate) struct StreamDecompressor {
+    decoder: zstd::stream::write::Decoder<'static, CappedBuffer>,
+}
+
+impl StreamDecompressor {
+    pub(crate) fn new() -> Result<Self> {
+        let mut decoder = zstd::stream::write::Decoder::new(CappedBuffer { data: Vec::new(), limit: CHUNK_SIZE })?;
+        decoder.window_log_max(STREAM_WINDOW_LOG)?;
+        Ok(Self { decoder })
+    }
+
+    /// Plaintext of the next record. Each record holds one chunk, so decoding stops
+    /// as soon as it grows past `CHUNK_SIZE`.
+    pub(crate) fn segment(&mut self, segment: &[u8]) -> Result<Vec<u8>> {
+        self.decoder.write_all(segment)?;
+        self.decoder.flush()?;
+        Ok(std::mem::take(&mut self.decoder.get_mut().data))
+    }
+}
+
+/// Decoder output that refuses to grow past `limit` bytes, so a few bytes of crafted
+/// Zstd cannot expand into gigabytes of RAM.
+struct CappedBuffer {
+    data: Vec<u8>,
+    limit: usize,
+}
+
+impl Write for CappedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.data.len() + buf.len() > self.limit {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("decompresses to more than {} bytes", self.limit),
+            ));
+        }
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reverses `encode_chunk`.
+fn decode_chunk(body: &[u8]) -> Result<Vec<u8>> {
+    match body.split_first() {
+        Some((&CHUNK_STORED, data)) => Ok(data.to_vec()),
+        Some((&CHUNK_ZSTD, compressed)) => decompress_chunk(compressed),
+        Some((coding, _)) => Err(anyhow!("unknown chunk coding {}", coding)),
+        None => Err(anyhow!("chunk coding is missing")),
+    }
+}
+
+/// Derives a purpose-specific key from the File Key (e.g., for the header MAC).
+/// Separate keys keep the HMAC, the metadata block and the chunk cipher domain-separated.
+fn derive_subkey(file_key: &[u8], label: &[u8]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(label);
+    hasher.update(file_key);
+    let res = hasher.finalize();
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&res);
+    key
+}
+
+/// Computes the HMAC over the version bytes and the serialized header.
+fn header_mac(mac_key: &[u8; 32], header_bytes: &[u8]) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(mac_key).expect("HMAC accepts any key length");
+    mac.update(&CURRENT_VERSION.to_le_bytes());
+    mac.update(header_bytes);
+    mac
+}
+
+/// Strips any directory components from a stored filename so a crafted
+/// header cannot write outside the output directory.
+fn sanitize_filename(name: &str) -> String {
+    std::path::Path::new(name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .filter(|n| !n.is_empty())
+        .unwrap_or_else(|| "decrypted_file".to_string())
+}
+
+/// Derives the nonce for a chunk by XORing the chunk index into the last 8 bytes
+/// of the base nonce (bytes 4..12 for AES-GCM, as in V5).
+/// Security Note: We cannot use the same nonce for every chunk.
+fn chunk_nonce(base_nonce: &[u8], chunk_index: u64) -> Vec<u8> {
+    let mut nonce = base_nonce.to_vec();
+    let offset = nonce.len() - 8;
+    for (n, i) in nonce[offset..].iter_mut().zip(chunk_index.to_le_bytes()) {
+        *n ^= i;
+    }
+    nonce
+}
+
+/// Associated data bound to every V6 chunk: its position and whether it is the last one.
+/// This stops chunks from being reordered, dropped from the end, or followed by extra data.
+fn chunk_aad(chunk_index: u64, is_last: bool) -> [u8; 9] {
+    let mut aad = [0u8; 9];
+    aad[..8].copy_from_slice(&chunk_index.to_le_bytes());
+    aad[8] = is_last as u8;
+    aad
+}
+
+/// Fills `buf` from the reader, only returning less than `buf.len()` at End of File.
+/// Every chunk except the last one therefore holds exactly `CHUNK_SIZE` bytes.
+fn read_full_chunk(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(filled)
+}
+
+/// Padmé (Nikitin et al., 2019): rounds `len` up so that only the top
+/// ~log2(log2(len)) bits of its binary representation can vary.
+fn padme(len: u64) -> u64 {
+    if len < 2 {
+        return len;
+    }
+    let exponent = 63 - len.leading_zeros() as u64; // floor(log2(len))
+    let significant_bits = 64 - exponent.leading_zeros() as u64; // floor(log2(exponent)) + 1
+    let mask = (1u64 << (exponent - significant_bits)) - 1;
+    (len + mask) & !mask
+}
+
+/// Builds a padding frame: [u32 body length][body][zeros up to `padded_len`].
+fn frame_padded(body: &[u8], padded_len: usize) -> Result<Vec<u8>> {
+    if body.len() + 4 > padded_len {
+        return Err(anyhow!("Chunk does not fit in its padded size"));
+    }
+    let mut frame = Vec::with_capacity(padded_len);
+    frame.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    frame.extend_from_slice(body);
+    frame.resize(padded_len, 0);
+    Ok(frame)
+}
+
+/// Returns the body of a padding frame. The padding itself must be zeros.
+fn unframe_padded(frame: &[u8]) -> Result<&[u8]> {
+    let (len_bytes, rest) = frame
+        .split_first_chunk::<4>()
+        .ok_or_else(|| anyhow!("Padding frame is too short"))?;
+    let body_len = u32::from_le_bytes(*len_bytes) as usize;
+    if body_len > rest.len() || rest[body_len..].iter().any(|&b| b != 0) {
+        return Err(anyhow!("Invalid padding frame"));
+    }
+    Ok(&rest[..body_len])
+}
+
+/// Compares what the decryptor actually produced with the sealed Trailer.
+fn verify_trailer(trailer: &StreamTrailer, chunk_count: u64, total_size: u64, hash: &[u8]) -> Result<()> {
+    if trailer.chunk_count != chunk_count || trailer.total_size != total_size {
+        return Err(IntegrityError(format!(
+            "Expected {} bytes in {} chunks, got {} bytes in {} chunks.",
+            trailer.total_size, trailer.chunk_count, total_size, chunk_count
+        ))
+        .into());
+    }
+    if trailer.plaintext_hash != hash {
+        return Err(IntegrityError("Hash mismatch. File is corrupted.".to_string()).into());
+    }
+    Ok(())
+}
+
 /// Decompresses a chunk back to its original state.
+/// A chunk never holds more than `CHUNK_SIZE` bytes: anything larger is rejected.
 fn decompress_chunk(data: &[u8]) -> Result<Vec<u8>> {
-    let mut decoder = zstd::Decoder::new(std::io::Cursor::new(data))?;
+    let decoder = zstd::Decoder::new(std::io::Cursor::new(data))?;
     let mut out = Vec::new();
-    decoder.read_to_end(&mut out)?;
+    decoder.take(CHUNK_SIZE as u64 + 1).read_to_end(&mut out)?;
+    if out.len() > CHUNK_SIZE {
+        return Err(anyhow!("decompresses to more than {} bytes", CHUNK_SIZE));
+    }
     Ok(out)
 }
 
 // --- STREAM ENCRYPTOR ---
 
-/// Encrypts a file using the V5 Streaming Engine.
+/// Encrypts a file using the V6 Streaming Engine.
 /// 
 /// This function reads the input file in small chunks (1MB), compresses them,
 /// encrypts them, and writes them to the output file immediately.
 /// This ensures RAM usage stays constant (~50MB) even for files sized 10GB+.
+/// Compression and encryption run on `options.threads` worker threads.
+///
+/// Folders are locked as a single archive stream (see `archive.rs`):
+/// no plaintext copy of the folder is ever written to disk.
+///
+/// With `options.volume_size`, `output_path` is the base name of the volumes
+/// (`output_path.001`, `.002`...), see `volumes.rs`.
 pub fn encrypt_file_stream(
     input_path: &str,
     output_path: &str,
     master_key: &MasterKey,
     keyfile_bytes: Option<&[u8]>,
     entropy_seed: Option<[u8; 32]>,
-    compression_level: i32, 
+    options: &StreamOptions,
     callback: impl Fn(u64, u64), // Progress update function
 ) -> Result<()> {
     // Open streams
-    let mut input_file = BufReader::new(File::open(input_path)?);
-    let total_size = std::fs::metadata(input_path)?.len();
-    let original_filename = std::path::Path::new(input_path)
-        .file_name()
-        .unwrap_or_default()
-        .to_string_lossy()
-        .to_string();
+    let input = std::path::Path::new(input_path);
+    let mut metadata = FileMetadata::from_path(input)?;
+    let input_file: Box<dyn Read> = if input.is_dir() {
+        let folder = archive::ArchiveReader::new(input, options.symlinks)?;
+        metadata.original_size = folder.stream_len();
+        metadata.mime_type = None;
+        metadata.archive = true;
+        Box::new(folder)
+    } else {
+        Box::new(BufReader::new(File::open(input_path)?))
+    };
+
+    // Volumes: sealed and renamed into place once the whole stream is written
+    if let Some(volume_size) = options.volume_size {
+        let volumes = volumes::VolumeWriter::new(std::path::Path::new(output_path), volume_size)?;
+        let mut output_file = BufWriter::new(volumes);
+        let volume_key =
+            seal_stream(input_file, &mut output_file, metadata, master_key, keyfile_bytes, entropy_seed, options, callback)?;
+        let volumes = output_file.into_inner().map_err(|e| e.into_error())?;
+        volumes.finish(&volume_key)?;
+        return Ok(());
+    }
+
+    // Written to a hidden temporary file, renamed into place only once complete
+    let output = utils::AtomicFile::create(std::path::Path::new(output_path))?;
+    let mut output_file = BufWriter::new(output.file());
 
-    let mut output_file = BufWriter::new(File::create(output_path)?);
+    encrypt_stream(input_file, &mut output_file, metadata, master_key, keyfile_bytes, entropy_seed, options, callback)?;
+
+    output_file.flush()?; // Ensure all data is written to disk
+    drop(output_file);
+    output.commit(std::path::Path::new(output_path))?;
+    Ok(())
+}
+
+/// Encrypts any byte stream (a pipe, a socket, an in-memory buffer...) into `output`.
+///
+/// `metadata` is sealed into the header as-is. Its `original_size` is only a hint
+/// for progress reporting: use 0 when the length is not known in advance
+/// (e.g., `tar` output on stdin). The Trailer always records the real size.
+#[allow(clippy::too_many_arguments)]
+pub fn encrypt_stream(
+    input_file: impl Read,
+    output_file: impl Write,
+    metadata: FileMetadata,
+    master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+    entropy_seed: Option<[u8; 32]>,
+    options: &StreamOptions,
+    callback: impl Fn(u64, u64),
+) -> Result<()> {
+    seal_stream(input_file, output_file, metadata, master_key, keyfile_bytes, entropy_seed, options, callback)?;
+    Ok(())
+}
+
+/// `encrypt_stream`, returning the key that seals volume headers (derived from the File Key).
+#[allow(clippy::too_many_arguments)]
+fn seal_stream(
+    mut input_file: impl Read,
+    mut output_file: impl Write,
+    metadata: FileMetadata,
+    master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+    entropy_seed: Option<[u8; 32]>,
+    options: &StreamOptions,
+    callback: impl Fn(u64, u64),
+) -> Result<Zeroizing<[u8; 32]>> {
+    let total_size = metadata.original_size;
 
     // 1. Write the Protocol Version (4 bytes)
-    // This allows the decryptor to know which engine to use (V4 vs V5).
+    // This allows the decryptor to know which engine to use (V4 vs V5/V6).
     output_file.write_all(&CURRENT_VERSION.to_le_bytes())?;
 
     // 2. Setup Random Number Generator (RNG)
@@ -135,150 +1034,936 @@ pub fn encrypt_file_stream(
 
     // 3. Generate the File Encryption Key (FEK)
     // This random 32-byte key is unique to this specific file.
+    let suite = options.cipher_suite;
+    let nonce_len = suite.nonce_len();
     let mut file_key = [0u8; FILE_KEY_LEN];
     rng.fill_bytes(&mut file_key);
-    let cipher_file = Aes256Gcm::new_from_slice(&file_key).unwrap();
+    let cipher_file = AeadCipher::new(suite, &file_key)?;
 
-    // 4. Derive Wrapping Key (From Password)
-    let mut wrapping_key = derive_wrapping_key(master_key, keyfile_bytes);
-    let cipher_wrap = Aes256Gcm::new_from_slice(&wrapping_key).unwrap();
-
-    // 5. Create Header Data
-    
-    // A. Validation Tag (To check password correctness)
-    let mut validation_nonce = [0u8; AES_NONCE_LEN];
-    rng.fill_bytes(&mut validation_nonce);
-    let encrypted_validation = cipher_wrap
-        .encrypt(Nonce::from_slice(&validation_nonce), VALIDATION_MAGIC)
-        .map_err(|_| anyhow!("Validation failed"))?;
+    // 4. Create Header Data
 
-    // B. Encrypted File Key (Key Wrapping)
-    let mut key_wrapping_nonce = [0u8; AES_NONCE_LEN];
-    rng.fill_bytes(&mut key_wrapping_nonce);
-    let encrypted_file_key = cipher_wrap
-        .encrypt(Nonce::from_slice(&key_wrapping_nonce), file_key.as_ref())
-        .map_err(|_| anyhow!("File Key Wrap failed"))?;
+    // A. Key Slots (one per Hybrid recipient, plus the passphrase if any;
+    //    the Master Key + Keyfile when neither is requested)
+    let key_slots = build_key_slots(
+        suite,
+        &file_key,
+        master_key,
+        keyfile_bytes,
+        &options.recipients,
+        options.passphrase.as_deref(),
+        &mut *rng,
+    )?;
 
-    // C. Base Nonce for the stream
-    let mut base_nonce = [0u8; AES_NONCE_LEN];
+    // B. Base Nonce for the stream
+    let mut base_nonce = vec![0u8; nonce_len];
     rng.fill_bytes(&mut base_nonce);
 
+    // C. Encrypted Metadata (Filename, Size, Timestamps...)
+    let mut metadata_key = derive_subkey(&file_key, b"QRE_METADATA");
+    let cipher_meta = AeadCipher::new(suite, &metadata_key)?;
+    metadata_key.zeroize();
+
+    let mut metadata_nonce = vec![0u8; nonce_len];
+    rng.fill_bytes(&mut metadata_nonce);
+    let encrypted_metadata = cipher_meta
+        .encrypt(&metadata_nonce, &bincode::serialize(&metadata)?, &[])
+        .map_err(|_| anyhow!("Metadata encryption failed"))?;
+
+    // "Store" (level 0) has nothing to compress stream-wide
+    let stream_compression = options.stream_compression && options.compression_level > 0;
     let header = StreamHeader {
-        validation_nonce: validation_nonce.to_vec(),
-        encrypted_validation_tag: encrypted_validation,
-        key_wrapping_nonce: key_wrapping_nonce.to_vec(),
-        encrypted_file_key,
-        base_nonce: base_nonce.to_vec(),
-        original_filename,
-        original_hash: None,
+        cipher_suite: suite,
+        key_slots,
+        base_nonce: base_nonce.clone(),
+        metadata_nonce,
+        encrypted_metadata,
+        padding: options.padding,
+        fixed_size_chunks: options.fixed_size_chunks,
+        signer: options.signer.as_ref().map(|key| key.verifying_key().to_bytes()),
+        adaptive_chunks: !stream_compression,
+        stream_compression,
     };
+    let padded = header.is_padded();
+
+    // 5. Write Header to disk
+    // Format: [Header Length (4 bytes)] + [Header] + [HMAC-SHA256 of Version + Header]
+    // The MAC is keyed from the File Key, so any modification is detected on unlock.
+    let header_bytes = bincode::serialize(&header)?;
+    let mut mac_key = derive_subkey(&file_key, b"QRE_HEADER_MAC");
+    let mac_tag = header_mac(&mac_key, &header_bytes).finalize().into_bytes();
+    mac_key.zeroize();
 
-    // 6. Write Header to disk
-    bincode::serialize_into(&mut output_file, &header)?;
+    output_file.write_all(&(header_bytes.len() as u32).to_le_bytes())?;
+    output_file.write_all(&header_bytes)?;
+    output_file.write_all(&mac_tag)?;
+    let mut bytes_written = (4 + 4 + header_bytes.len() + HEADER_MAC_LEN) as u64;
 
-    // 7. Start Streaming Loop
-    let mut buffer = vec![0u8; CHUNK_SIZE];
-    let mut chunk_index: u64 = 0;
+    // Signed files: everything from here on is hashed for the signature
+    let signature_hasher = match options.signer {
+        Some(_) => Some(header.signature_hasher()?),
+        None => None,
+    };
+    let mut output_file = signing::HashingWriter::new(output_file, signature_hasher);
+
+    // 6. Start Streaming Pipeline
+    // The calling thread reads chunks and writes results in order, while worker
+    // threads compress and encrypt in between. Nonces depend only on the chunk
+    // index, so the output is identical whatever the thread count.
+    // A running SHA-256 of the plaintext is sealed into the trailer at the end.
+    let mut hasher = Sha256::new();
+    let mut chunk_count: u64 = 0;
     let mut processed_bytes: u64 = 0;
+    let mut reached_eof = false;
+    // Stream-wide compression runs here, on the calling thread (it is sequential by nature)
+    let mut compressor = match stream_compression {
+        true => Some(StreamCompressor::new(options.compression_level)?),
+        false => None,
+    };
 
-    loop {
-        // Read a chunk from source
-        let bytes_read = input_file.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break; // End of File
-        }
+    pipeline::run_ordered(
+        pipeline::resolve_threads(options.threads),
+        // Reader: read a full chunk from source
+        || {
+            if reached_eof {
+                return Ok(None);
+            }
+            let mut buffer = vec![0u8; CHUNK_SIZE];
+            let bytes_read = read_full_chunk(&mut input_file, &mut buffer)?;
+            reached_eof = bytes_read < CHUNK_SIZE; // A short read means End of File
+            buffer.truncate(bytes_read);
+            hasher.update(&buffer);
+            match &mut compressor {
+                Some(compressor) => Ok(compressor.segment(&buffer, reached_eof)?.map(|segment| (bytes_read, segment))),
+                None if bytes_read == 0 => Ok(None),
+                None => Ok(Some((bytes_read, buffer))),
+            }
+        },
+        // Workers: compress (or store), then encrypt binding the index (data chunks are never "last")
+        |chunk_index, (bytes_read, chunk_data): (usize, Vec<u8>)| {
+            let mut body = match stream_compression {
+                true => chunk_data, // Already compressed by the reader
+                false => encode_chunk(&chunk_data, options.compression_level)?,
+            };
+            if options.fixed_size_chunks {
+                body = frame_padded(&body, FIXED_CHUNK_BODY)?;
+            } else if padded {
+                body = frame_padded(&body, body.len() + 4)?;
+            }
+            let chunk_nonce_bytes = chunk_nonce(&base_nonce, chunk_index);
+            let aad = chunk_aad(chunk_index, false);
+            let ciphertext = cipher_file
+                .encrypt(&chunk_nonce_bytes, &body, &aad)
+                .map_err(|_| anyhow!("Chunk encryption failed"))?;
+            Ok((bytes_read, ciphertext))
+        },
+        // Writer
+        |_, (bytes_read, ciphertext): (usize, Vec<u8>)| {
+            // Write Format: [Size (4 bytes)] + [Encrypted Data]
+            // We must write the size because compression makes chunks variable length.
+            let size = (ciphertext.len() as u32).to_le_bytes();
+            output_file.write_all(&size)?;
+            output_file.write_all(&ciphertext)?;
+            bytes_written += 4 + ciphertext.len() as u64;
 
-        let chunk_data = &buffer[..bytes_read];
+            // Update progress
+            processed_bytes += bytes_read as u64;
+            chunk_count += 1;
+            callback(processed_bytes, total_size);
+            Ok(())
+        },
+    )?;
 
-        // Compress the chunk
-        let compressed = compress_chunk(chunk_data, compression_level)?;
+    let mut trailer = StreamTrailer {
+        plaintext_hash: hasher.finalize().to_vec(),
+        total_size: processed_bytes,
+        chunk_count,
+    };
+    let mut trailer_bytes = bincode::serialize(&trailer)?;
+
+    // 7. Length-hiding padding (Padmé)
+    // The file is grown to the padded length with records holding no data
+    // (empty padding frames), the remainder goes inside the Trailer itself.
+    // Their count does not change the Trailer's serialized size (fixed-width integers).
+    if padded {
+        let mut trailer_padding = 0;
+        if options.padding == PaddingScheme::Padme {
+            let trailer_record = (4 + TAG_LEN + 4 + trailer_bytes.len()) as u64;
+            let signature_record = if options.signer.is_some() { signing::SIGNATURE_RECORD_LEN as u64 } else { 0 };
+            let unpadded_len = bytes_written + trailer_record + signature_record;
+            let mut remaining = padme(unpadded_len) - unpadded_len;
 
-        // Calculate Rolling Nonce
-        // Security Note: We cannot use the same nonce for every chunk.
-        // We derive a new nonce by XORing the chunk index into the base nonce.
-        let mut chunk_nonce_bytes = base_nonce;
-        let index_bytes = chunk_index.to_le_bytes();
-        for i in 0..8 {
-            chunk_nonce_bytes[4 + i] ^= index_bytes[i];
+            let full_record = (4 + TAG_LEN + FIXED_CHUNK_BODY) as u64;
+            let trailer_room = (FIXED_CHUNK_BODY - 4 - trailer_bytes.len()) as u64;
+            while remaining > trailer_room {
+                // Halving keeps every record above its 24-byte minimum
+                // and leaves what fits in the Trailer
+                let record = if remaining > full_record { full_record } else { remaining / 2 };
+                let frame = frame_padded(&[], (record as usize) - 4 - TAG_LEN)?;
+                let ciphertext = cipher_file
+                    .encrypt(&chunk_nonce(&base_nonce, chunk_count), &frame, &chunk_aad(chunk_count, false))
+                    .map_err(|_| anyhow!("Padding encryption failed"))?;
+                output_file.write_all(&(ciphertext.len() as u32).to_le_bytes())?;
+                output_file.write_all(&ciphertext)?;
+                chunk_count += 1;
+                remaining -= record;
+            }
+            trailer_padding = remaining as usize;
         }
-        
-        let nonce = Nonce::from_slice(&chunk_nonce_bytes);
 
-        // Encrypt the compressed chunk
-        let ciphertext = cipher_file.encrypt(nonce, compressed.as_ref())
-            .map_err(|_| anyhow!("Chunk encryption failed"))?;
+        trailer.chunk_count = chunk_count;
+        let body = bincode::serialize(&trailer)?;
+        trailer_bytes = frame_padded(&body, body.len() + 4 + trailer_padding)?;
+    }
 
-        // Write Format: [Size (4 bytes)] + [Encrypted Data]
-        // We must write the size because compression makes chunks variable length.
-        let size = (ciphertext.len() as u32).to_le_bytes();
-        output_file.write_all(&size)?;
-        output_file.write_all(&ciphertext)?;
+    // 8. Write the Trailer
+    // It is sealed as the final chunk, so it doubles as the end-of-stream marker.
+    let trailer_nonce = chunk_nonce(&base_nonce, chunk_count);
+    let aad = chunk_aad(chunk_count, true);
+    let sealed_trailer = cipher_file
+        .encrypt(&trailer_nonce, &trailer_bytes, &aad)
+        .map_err(|_| anyhow!("Trailer encryption failed"))?;
+    output_file.write_all(&(sealed_trailer.len() as u32).to_le_bytes())?;
+    output_file.write_all(&sealed_trailer)?;
 
-        // Update progress
-        processed_bytes += bytes_read as u64;
-        chunk_index += 1;
-        callback(processed_bytes, total_size);
+    // 9. Signature (signed files only), after the Trailer
+    let (mut output_file, digest) = output_file.finish();
+    if let (Some(signing_key), Some(digest)) = (&options.signer, digest) {
+        output_file.write_all(&signing::sign_record(signing_key, &digest))?;
     }
 
-    // 8. Cleanup
-    output_file.flush()?; // Ensure all data is written to disk
+    // 10. Cleanup
+    output_file.flush()?;
     
-    // Wipe keys from RAM
+    // Wipe keys from RAM (keeping only the key of the volume headers, for split files)
+    let volume_key = Zeroizing::new(derive_subkey(&file_key, b"QRE_VOLUME_MAC"));
     file_key.zeroize();
-    wrapping_key.zeroize();
 
-    Ok(())
+    Ok(volume_key)
 }
 
-// --- STREAM DECRYPTOR ---
+// --- HEADER PARSING & KEY UNWRAPPING ---
 
-/// Decrypts a V5 (.qre) stream file.
-pub fn decrypt_file_stream(
-    input_path: &str,
-    output_dir: &str,
-    master_key: &MasterKey,
+/// A stream header as read from disk, before any key has been applied.
+pub(crate) struct RawStreamHeader {
+    pub version: u32,
+    pub header: StreamHeader,
+    // Exact serialized bytes covered by the MAC (empty for V5).
+    pub header_bytes: Vec<u8>,
+    pub stored_mac: [u8; HEADER_MAC_LEN],
+    // V5 only: the plaintext filename and hash from the legacy header.
+    pub legacy_filename: Option<String>,
+    pub legacy_hash: Option<Vec<u8>>,
+}
+
+impl RawStreamHeader {
+    /// True for V6 files, whose header and chunk order are authenticated.
+    pub fn is_authenticated(&self) -> bool {
+        self.version == CURRENT_VERSION
+    }
+}
+
+/// Everything needed to decrypt the body once the File Key has been unwrapped.
+pub(crate) struct StreamKeys {
+    cipher_file: AeadCipher,
+    base_nonce: Vec<u8>,
+    authenticated: bool,
+    padded: bool,
+    adaptive: bool,
+    stream_compressed: bool,
+    pub metadata: FileMetadata,
+    // Signed files: the signer's public key (authenticated by the header MAC),
+    // and the signature digest seeded with the header.
+    pub signer: Option<[u8; 32]>,
+    signature_hasher: Option<Sha256>,
+    // Seals the volume headers of a split file (see `volumes.rs`).
+    volume_key: Zeroizing<[u8; 32]>,
+}
+
+/// Reads the version bytes and the header, leaving `reader` at the first chunk.
+pub(crate) fn read_stream_header(reader: &mut impl Read) -> Result<RawStreamHeader> {
+    let mut ver_buf = [0u8; 4];
+    reader.read_exact(&mut ver_buf).context("Failed to read version bytes")?;
+    let version = u32::from_le_bytes(ver_buf);
+
+    match version {
+        // V6: [Header Length] + [Header] + [HMAC]
+        CURRENT_VERSION => {
+            let mut len_buf = [0u8; 4];
+            reader.read_exact(&mut len_buf).context("Failed to read V6 Header length")?;
+            let header_len = u32::from_le_bytes(len_buf) as usize;
+            if header_len > MAX_HEADER_LEN {
+                return Err(anyhow!("Header size too large (corrupt file?)"));
+            }
+            let mut header_bytes = vec![0u8; header_len];
+            reader.read_exact(&mut header_bytes).context("Failed to read V6 Header")?;
+            let mut stored_mac = [0u8; HEADER_MAC_LEN];
+            reader.read_exact(&mut stored_mac).context("Failed to read V6 Header MAC")?;
+
+            // Every byte must belong to a known field: a header with more fields than
+            // this layout comes from a format we cannot read correctly.
+            let header: StreamHeader = bounded_bincode(MAX_HEADER_LEN)
+                .reject_trailing_bytes()
+                .deserialize(&header_bytes)
+                .map_err(|e| malformed(format!("Failed to parse V6 Header: {}", e)))?;
+            header.validate(true)?;
+            Ok(RawStreamHeader {
+                version,
+                header,
+                header_bytes,
+                stored_mac,
+                legacy_filename: None,
+                legacy_hash: None,
+            })
+        }
+        // V5: bare bincode header
+        LEGACY_STREAM_VERSION => {
+            let legacy: LegacyStreamHeader = bounded_bincode(MAX_HEADER_LEN)
+                .deserialize_from(reader)
+                .map_err(|e| malformed(format!("Failed to read V5 Header: {}", e)))?;
+            if legacy.original_hash.as_ref().is_some_and(|hash| hash.len() != 32) {
+                return Err(malformed("Original hash is not a SHA-256 digest"));
+            }
+            let header = StreamHeader {
+                cipher_suite: CipherSuite::Aes256Gcm,
+                key_slots: vec![KeySlot::MasterKey {
+                    validation_nonce: legacy.validation_nonce,
+                    encrypted_validation_tag: legacy.encrypted_validation_tag,
+                    key_wrapping_nonce: legacy.key_wrapping_nonce,
+                    encrypted_file_key: legacy.encrypted_file_key,
+                    // Not recorded by V5
+                    uses_keyfile: false,
+                }],
+                base_nonce: legacy.base_nonce,
+                metadata_nonce: Vec::new(),
+                encrypted_metadata: Vec::new(),
+                padding: PaddingScheme::None,
+                fixed_size_chunks: false,
+                signer: None,
+                adaptive_chunks: false,
+                stream_compression: false,
+            };
+            header.validate(false)?;
+            Ok(RawStreamHeader {
+                version,
+                header,
+                header_bytes: Vec::new(),
+                stored_mac: [0u8; HEADER_MAC_LEN],
+                legacy_filename: Some(legacy.original_filename),
+                legacy_hash: legacy.original_hash,
+            })
+        }
+        // Split files are read as a whole set (see `open_input`)
+        volumes::VOLUME_VERSION => Err(anyhow!(
+            "This is one volume of a split file: its header can only be read through the whole set."
+        )),
+        v => Err(anyhow!("Unsupported stream version: {}", v)),
+    }
+}
+
+/// Unwraps the File Key from the first Key Slot `keys` can open.
+/// Returns `Ok(None)` when the slot is not meant for these keys.
+fn unwrap_slot(slot: &KeySlot, suite: CipherSuite, keys: &UnlockKeys) -> Result<Option<Vec<u8>>> {
+    match slot {
+        KeySlot::MasterKey { validation_nonce, encrypted_validation_tag, key_wrapping_nonce, encrypted_file_key, uses_keyfile } => {
+            if *uses_keyfile && keys.keyfile_bytes.is_none() {
+                return Err(anyhow!("This file requires a Keyfile. Please select it."));
+            }
+
+            let mut wrapping_key = derive_wrapping_key(keys.master_key, keys.keyfile_bytes);
+            let cipher_wrap = AeadCipher::new(suite, &wrapping_key)?;
+            wrapping_key.zeroize();
+
+            // Verify Password (Validation Tag)
+            match cipher_wrap.decrypt(validation_nonce, encrypted_validation_tag, &[]) {
+                Ok(bytes) => {
+                    if bytes != VALIDATION_MAGIC {
+                        return Err(anyhow!("Validation tag mismatch."));
+                    }
+                }
+                Err(_) => return Err(anyhow!("Decryption Denied. Check password.")),
+            }
+
+            // Decrypt the File Key (FEK)
+            cipher_wrap
+                .decrypt(key_wrapping_nonce, encrypted_file_key, &[])
+                .map(Some)
+                .map_err(|_| anyhow!("Failed to unwrap file key"))
+        }
+        KeySlot::Hybrid { recipient, encapsulation, key_wrapping_nonce, encrypted_file_key } => {
+            let identity = match keys.identity {
+                Some(identity) if identity.public_key().fingerprint() == *recipient => identity,
+                _ => return Ok(None),
+            };
+            let mut wrapping_key = hybrid::decapsulate(identity, encapsulation)?;
+            let cipher_wrap = AeadCipher::new(suite, &wrapping_key)?;
+            wrapping_key.zeroize();
+
+            cipher_wrap
+                .decrypt(key_wrapping_nonce, encrypted_file_key, &[])
+                .map(Some)
+                .map_err(|_| anyhow!("Failed to unwrap file key (identity mismatch)"))
+        }
+        KeySlot::Password { kdf, key_wrapping_nonce, encrypted_file_key } => {
+            let passphrase = match keys.passphrase {
+                Some(passphrase) => passphrase,
+                None => return Ok(None),
+            };
+            let mut wrapping_key = derive_passphrase_key(passphrase, kdf)?;
+            let cipher_wrap = AeadCipher::new(suite, &wrapping_key)?;
+            wrapping_key.zeroize();
+
+            cipher_wrap
+                .decrypt(key_wrapping_nonce, encrypted_file_key, &[])
+                .map(Some)
+                .map_err(|_| anyhow!("Decryption Denied. Incorrect passphrase."))
+        }
+    }
+}
+
+/// Checks the password, unwraps the File Key, authenticates the header (V6)
+/// and opens the sealed metadata block.
+pub(crate) fn unlock_stream_header(raw: &RawStreamHeader, keys: &UnlockKeys) -> Result<StreamKeys> {
+    let mut file_key = unwrap_file_key(raw, keys)?;
+    let result = open_with_file_key(raw, &file_key);
+    file_key.zeroize();
+    result
+}
+
+/// Unwraps the File Key from the first Key Slot `keys` can open.
+/// The header itself is not authenticated yet (see `open_with_file_key`).
+fn unwrap_file_key(raw: &RawStreamHeader, keys: &UnlockKeys) -> Result<Vec<u8>> {
+    let header = &raw.header;
+
+    // Try every slot; report the first real failure if none of them opens
+    let mut first_error = None;
+    for slot in &header.key_slots {
+        match unwrap_slot(slot, header.cipher_suite, keys) {
+            Ok(Some(file_key)) => return Ok(file_key),
+            Ok(None) => {}
+            Err(e) => {
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+    let has_password_slot = header.key_slots.iter().any(|slot| matches!(slot, KeySlot::Password { .. }));
+    if has_password_slot {
+        return Err(PassphraseRequired.into());
+    }
+    Err(anyhow!("This file was locked for another identity. None of its key slots match this vault."))
+}
+
+/// Second half of `unlock_stream_header`, once the File Key is known.
+fn open_with_file_key(raw: &RawStreamHeader, file_key: &[u8]) -> Result<StreamKeys> {
+    let header = &raw.header;
+    let cipher_file = AeadCipher::new(header.cipher_suite, file_key)
+        .map_err(|_| anyhow!("Invalid file key length"))?;
+
+    // Verify the Header MAC before trusting anything else in the header (V6 only)
+    if raw.is_authenticated() {
+        let mut mac_key = derive_subkey(file_key, b"QRE_HEADER_MAC");
+        let verified = header_mac(&mac_key, &raw.header_bytes).verify_slice(&raw.stored_mac);
+        mac_key.zeroize();
+        if verified.is_err() {
+            return Err(anyhow!("Header authentication failed. The file has been tampered with."));
+        }
+    }
+
+    // Decrypt the Metadata block (V6) or fall back to the plaintext V5 filename
+    let metadata = match &raw.legacy_filename {
+        Some(filename) => FileMetadata { filename: filename.clone(), ..Default::default() },
+        None => {
+            let mut metadata_key = derive_subkey(file_key, b"QRE_METADATA");
+            let cipher_meta = AeadCipher::new(header.cipher_suite, &metadata_key)?;
+            metadata_key.zeroize();
+            let metadata_bytes = cipher_meta
+                .decrypt(&header.metadata_nonce, &header.encrypted_metadata, &[])
+                .map_err(|_| anyhow!("Metadata decryption failed"))?;
+            bincode::deserialize(&metadata_bytes).context("Failed to parse file metadata")?
+        }
+    };
+
+    if header.base_nonce.len() != header.cipher_suite.nonce_len() {
+        return Err(anyhow!("Invalid base nonce length"));
+    }
+    let base_nonce = header.base_nonce.clone();
+
+    let signature_hasher = match header.signer {
+        Some(_) => Some(header.signature_hasher()?),
+        None => None,
+    };
+
+    Ok(StreamKeys {
+        cipher_file,
+        base_nonce,
+        authenticated: raw.is_authenticated(),
+        padded: header.is_padded(),
+        adaptive: header.adaptive_chunks,
+        stream_compressed: header.stream_compression,
+        metadata,
+        signer: header.signer,
+        signature_hasher,
+        volume_key: Zeroizing::new(derive_subkey(file_key, b"QRE_VOLUME_MAC")),
+    })
+}
+
+impl StreamKeys {
+    /// Decrypts one chunk record (or the Trailer, when `is_last` is set).
+    ///
+    /// V6: the chunk must be flagged as last exactly when no data follows it.
+    /// On failure, we find out whether the chunk is intact but sits in the wrong
+    /// place, so the user gets a precise error instead of a generic failure.
+    pub(crate) fn open_chunk(&self, chunk_index: u64, is_last: bool, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = chunk_nonce(&self.base_nonce, chunk_index);
+
+        if !self.authenticated {
+            return self.cipher_file.decrypt(&nonce, ciphertext, &[])
+                .map_err(|_| chunk_error(chunk_index, format!("Chunk {} decryption failed", chunk_index)));
+        }
+
+        let aad = chunk_aad(chunk_index, is_last);
+        match self.cipher_file.decrypt(&nonce, ciphertext, &aad) {
+            Ok(data) => Ok(data),
+            Err(_) => {
+                let flipped = chunk_aad(chunk_index, !is_last);
+                let misplaced = self.cipher_file.decrypt(&nonce, ciphertext, &flipped).is_ok();
+                let reason = match (misplaced, is_last) {
+                    (true, true) => "File is truncated: the final chunk is missing.".to_string(),
+                    (true, false) => "Unexpected data after the final chunk.".to_string(),
+                    _ => format!("Chunk {} failed authentication (corrupted or reordered)", chunk_index),
+                };
+                Err(chunk_error(chunk_index, reason))
+            }
+        }
+    }
+
+    /// True if the body uses padding frames (and may end with padding-only records).
+    pub(crate) fn is_padded(&self) -> bool {
+        self.padded
+    }
+
+    /// True if the chunks only decode in order (see `StreamCompressor`).
+    pub(crate) fn is_stream_compressed(&self) -> bool {
+        self.stream_compressed
+    }
+
+    /// Decrypts a data chunk and decompresses it back to plaintext (if it was compressed).
+    /// Padding-only records give an empty chunk. Stream-compressed chunks are returned
+    /// still compressed: they go through a `StreamDecompressor`, in order.
+    pub(crate) fn open_data_chunk(&self, chunk_index: u64, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let sealed = self.open_chunk(chunk_index, false, ciphertext)?;
+        let compressed = if self.padded {
+            unframe_padded(&sealed).map_err(|e| chunk_error(chunk_index, format!("Chunk {}: {}", chunk_index, e)))?
+        } else {
+            &sealed[..]
+        };
+        if (compressed.is_empty() && self.padded) || self.stream_compressed {
+            return Ok(compressed.to_vec());
+        }
+        let plaintext = if self.adaptive { decode_chunk(compressed) } else { decompress_chunk(compressed) };
+        plaintext.map_err(|e| chunk_error(chunk_index, format!("Chunk {} failed to decompress: {}", chunk_index, e)))
+    }
+
+    /// Decrypts and parses the Trailer record.
+    pub(crate) fn open_trailer(&self, chunk_index: u64, ciphertext: &[u8]) -> Result<StreamTrailer> {
+        let sealed = self.open_chunk(chunk_index, true, ciphertext)?;
+        let bytes = if self.padded { unframe_padded(&sealed)? } else { &sealed[..] };
+        bincode::deserialize(bytes).context("Failed to parse V6 Trailer")
+    }
+}
+
+/// Reads the size prefix of the next chunk record. Returns `None` at End of File.
+pub(crate) fn read_chunk_len(reader: &mut impl Read) -> Result<Option<usize>> {
+    let mut size_buf = [0u8; 4];
+    match reader.read_exact(&mut size_buf) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(anyhow!("Read error: {}", e)),
+    }
+
+    let chunk_len = u32::from_le_bytes(size_buf) as usize;
+
+    // Safety check to prevent Out-Of-Memory attacks
+    if chunk_len > CHUNK_SIZE + 4096 {
+        return Err(anyhow!("Chunk size too large (corrupt file?)"));
+    }
+    Ok(Some(chunk_len))
+}
+
+// --- REWRAP ---
+
+/// Replaces the Key Slots of a V5 or V6 (.qre) file without touching its body.
+///
+/// The body is encrypted with the random File Key, so changing the protection
+/// (adding or removing a Keyfile, moving to another Master Key, going Portable
+/// or Post-Quantum) only means wrapping that key again. The file is unlocked with
+/// `old_keys` first (header MAC included), then the new header and the original
+/// body go to a temporary file that replaces the original once complete.
+/// New slots follow the same rules as `StreamOptions` (`recipients`, `passphrase`).
+/// V5 files have a single key wrap: they can only move to another Master Key / Keyfile.
+pub fn rewrap_file_stream(
+    path: &str,
+    old_keys: &UnlockKeys,
+    new_master_key: &MasterKey,
+    new_keyfile_bytes: Option<&[u8]>,
+    recipients: &[HybridPublicKey],
+    passphrase: Option<&str>,
+) -> Result<()> {
+    replace_header(std::path::Path::new(path), old_keys, |raw, file_key| {
+        if raw.is_authenticated() {
+            let suite = raw.header.cipher_suite;
+            let key_slots =
+                build_key_slots(suite, file_key, new_master_key, new_keyfile_bytes, recipients, passphrase, &mut OsRng)?;
+            seal_rewrapped_header(raw, file_key, key_slots)
+        } else {
+            rewrap_legacy_header(raw, file_key, new_master_key, new_keyfile_bytes, recipients, passphrase)
+        }
+    })
+}
+
+/// Moves the Master Key slot of a V5 or V6 (.qre) file to `new_master_key`,
+/// keeping the Keyfile requirement and every other slot (recipients, passphrase) as is.
+/// Used by the Master Key rotation. `keyfile_bytes` is only applied when the slot needs it
+/// (V5 files do not record it: the Keyfile is used whenever one is given).
+/// Returns `false`, without touching the file, when it has no Master Key slot.
+pub fn rotate_master_key_stream(
+    path: &str,
+    old_master_key: &MasterKey,
+    new_master_key: &MasterKey,
     keyfile_bytes: Option<&[u8]>,
-    callback: impl Fn(u64, u64),
-) -> Result<String> {
-    let mut input_file = BufReader::new(File::open(input_path)?);
-    let file_size = std::fs::metadata(input_path)?.len();
-    
-    // 1. Skip Version Bytes
-    // The command handler already checked these to route to V5 logic.
+) -> Result<bool> {
+    let path = std::path::Path::new(path);
+    let Some(old_keys) = master_key_slot_keys(path, old_master_key, keyfile_bytes)? else {
+        return Ok(false);
+    };
+    let keyfile_bytes = old_keys.keyfile_bytes;
+
+    replace_header(path, &old_keys, |raw, file_key| {
+        if raw.is_authenticated() {
+            let suite = raw.header.cipher_suite;
+            let key_slots = raw
+                .header
+                .key_slots
+                .iter()
+                .map(|slot| match slot {
+                    KeySlot::MasterKey { .. } => wrap_for_master_key(suite, file_key, new_master_key, keyfile_bytes, &mut OsRng),
+                    other => Ok(other.clone()),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            seal_rewrapped_header(raw, file_key, key_slots)
+        } else {
+            rewrap_legacy_header(raw, file_key, new_master_key, keyfile_bytes, &[], None)
+        }
+    })?;
+    Ok(true)
+}
+
+/// Checks, without changing anything, that `rotate_master_key_stream` can move `path`
+/// to another Master Key: the Master Key slot opens with `master_key` (+ Keyfile), and the
+/// header (and every volume of a split file) authenticates. V4 containers are decrypted
+/// in full, as `migrate_container` will. Files without a Master Key slot pass.
+pub fn check_master_key_slot(path: &std::path::Path, master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) -> Result<()> {
     let mut ver_buf = [0u8; 4];
-    input_file.read_exact(&mut ver_buf).context("Failed to skip version bytes")?;
+    File::open(path)?.read_exact(&mut ver_buf).context("Failed to read version")?;
+    if u32::from_le_bytes(ver_buf) == 4 {
+        let container = crypto::EncryptedFileContainer::load(path)?;
+        let keyfile_bytes = if container.header.uses_keyfile { keyfile_bytes } else { None };
+        crypto::decrypt_file_with_master_key(master_key, keyfile_bytes, &container)?;
+        return Ok(());
+    }
+
+    let Some(keys) = master_key_slot_keys(path, master_key, keyfile_bytes)? else {
+        return Ok(());
+    };
+    let (mut input, _, volumes) = open_input(path)?;
+    let raw = read_stream_header(&mut input)?;
+    let mut file_key = unwrap_file_key(&raw, &keys)?;
+    let result = open_with_file_key(&raw, &file_key).and_then(|stream_keys| check_volumes(volumes.as_ref(), &stream_keys));
+    file_key.zeroize();
+    result
+}
 
-    // 2. Read and Parse Header
-    let header: StreamHeader = bincode::deserialize_from(&mut input_file)
-        .context("Failed to read V5 Header")?;
+/// The keys that open the Master Key slot of `path`, or `None` if it has none.
+/// Only that slot is tried, so a file shared with this vault's identity
+/// cannot be opened through its Hybrid slot by mistake.
+fn master_key_slot_keys<'a>(
+    path: &std::path::Path,
+    master_key: &'a MasterKey,
+    keyfile_bytes: Option<&'a [u8]>,
+) -> Result<Option<UnlockKeys<'a>>> {
+    let (mut input, _, _) = open_input(path)?;
+    let raw = read_stream_header(&mut input)?;
+    let uses_keyfile = if raw.is_authenticated() {
+        let master_key_slot = raw.header.key_slots.iter().find_map(|slot| match slot {
+            KeySlot::MasterKey { uses_keyfile, .. } => Some(*uses_keyfile),
+            _ => None,
+        });
+        match master_key_slot {
+            Some(uses_keyfile) => uses_keyfile,
+            None => return Ok(None),
+        }
+    } else {
+        keyfile_bytes.is_some()
+    };
+    let keyfile_bytes = if uses_keyfile { keyfile_bytes } else { None };
+    Ok(Some(UnlockKeys { master_key, keyfile_bytes, identity: None, passphrase: None }))
+}
 
-    // 3. Unwrap Keys
-    let mut wrapping_key = derive_wrapping_key(master_key, keyfile_bytes);
-    let cipher_wrap = Aes256Gcm::new_from_slice(&wrapping_key).unwrap();
-
-    // Verify Password (Validation Tag)
-    let val_nonce = Nonce::from_slice(&header.validation_nonce);
-    match cipher_wrap.decrypt(val_nonce, header.encrypted_validation_tag.as_ref()) {
-        Ok(bytes) => {
-            if bytes != VALIDATION_MAGIC {
-                return Err(anyhow!("Validation tag mismatch."));
+/// Unlocks `path` with `old_keys` (header MAC included), then writes the header built by
+/// `new_header` followed by the original body to a temporary file that replaces the original.
+/// For a split file (any volume), only volume 1 is rewritten (see `VolumeSet::replace_start`).
+fn replace_header(
+    path: &std::path::Path,
+    old_keys: &UnlockKeys,
+    new_header: impl FnOnce(RawStreamHeader, &[u8]) -> Result<Vec<u8>>,
+) -> Result<()> {
+    let (mut input, _, volumes) = open_input(path)?;
+
+    // 1. Unlock with the current protection (and check the whole set of a split file)
+    let raw = read_stream_header(&mut input)?;
+    let mut file_key = unwrap_file_key(&raw, old_keys)?;
+    let result = open_with_file_key(&raw, &file_key).and_then(|keys| {
+        check_volumes(volumes.as_ref(), &keys)?;
+        let old_len = (8 + raw.header_bytes.len() + HEADER_MAC_LEN) as u64;
+        if volumes.is_some() && !raw.is_authenticated() {
+            return Err(anyhow!("Split files are always V6: this set is damaged."));
+        }
+
+        // 2. Wrap the same File Key for the new protection
+        let new_header = new_header(raw, &file_key)?;
+
+        // 3. New header + the untouched body, swapped in atomically
+        let replaced = match &volumes {
+            Some(set) => set.replace_start(old_len, &new_header, &keys.volume_key)?,
+            None => {
+                let output = utils::AtomicFile::create(path)?;
+                let mut output_file = BufWriter::new(output.file());
+                output_file.write_all(&new_header)?;
+                std::io::copy(&mut input, &mut output_file)?;
+                output_file.flush()?;
+                drop(output_file);
+
+                std::fs::set_permissions(output.temp_path(), std::fs::metadata(path)?.permissions())?;
+                output.commit(path)?;
+                path.to_path_buf()
             }
+        };
+
+        // Recovery records of the old header would "repair" the file back to it.
+        // Stale records are worse than none.
+        if parity::refresh_parity(&replaced).is_err() {
+            let _ = std::fs::remove_file(parity::parity_path(&replaced));
         }
-        Err(_) => return Err(anyhow!("Decryption Denied. Check password.")),
+        Ok(())
+    });
+    file_key.zeroize();
+    result
+}
+
+/// V6: same header with new Key Slots, MAC recomputed (the MAC key comes from the File Key).
+/// Returns the version bytes, length, header and MAC, ready to write.
+fn seal_rewrapped_header(raw: RawStreamHeader, file_key: &[u8], key_slots: Vec<KeySlot>) -> Result<Vec<u8>> {
+    let header = StreamHeader { key_slots, ..raw.header };
+
+    let header_bytes = bincode::serialize(&header)?;
+    let mut mac_key = derive_subkey(file_key, b"QRE_HEADER_MAC");
+    let mac_tag = header_mac(&mac_key, &header_bytes).finalize().into_bytes();
+    mac_key.zeroize();
+
+    let mut out = Vec::with_capacity(8 + header_bytes.len() + HEADER_MAC_LEN);
+    out.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    out.extend_from_slice(&(header_bytes.len() as u32).to_le_bytes());
+    out.extend_from_slice(&header_bytes);
+    out.extend_from_slice(&mac_tag);
+    Ok(out)
+}
+
+/// V5: a fresh legacy header (AES-256-GCM, Master Key + Keyfile only).
+fn rewrap_legacy_header(
+    raw: RawStreamHeader,
+    file_key: &[u8],
+    master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+    recipients: &[HybridPublicKey],
+    passphrase: Option<&str>,
+) -> Result<Vec<u8>> {
+    if !recipients.is_empty() || passphrase.is_some() {
+        return Err(anyhow!("V5 files can only be rewrapped for a Master Key. Unlock and lock the file again to upgrade it."));
+    }
+
+    let slot = wrap_for_master_key(CipherSuite::Aes256Gcm, file_key, master_key, keyfile_bytes, &mut OsRng)?;
+    let KeySlot::MasterKey { validation_nonce, encrypted_validation_tag, key_wrapping_nonce, encrypted_file_key, .. } = slot else {
+        unreachable!("wrap_for_master_key returns a MasterKey slot");
+    };
+    let legacy = LegacyStreamHeader {
+        validation_nonce,
+        encrypted_validation_tag,
+        key_wrapping_nonce,
+        encrypted_file_key,
+        base_nonce: raw.header.base_nonce,
+        original_filename: raw.legacy_filename.unwrap_or_default(),
+        original_hash: raw.legacy_hash,
+    };
+
+    let mut out = LEGACY_STREAM_VERSION.to_le_bytes().to_vec();
+    out.extend_from_slice(&bincode::serialize(&legacy)?);
+    Ok(out)
+}
+
+// --- VAULT STORES ---
+
+/// Seals a vault store (`passwords.qre`, `notes.qre`...) with the Master Key and
+/// writes it to `path` through a temporary file, so a crash never leaves half a store.
+/// `filename` is the name sealed in the metadata (e.g., "notes.json").
+pub fn save_store(path: &std::path::Path, master_key: &MasterKey, filename: &str, data: &[u8]) -> Result<()> {
+    let output = utils::AtomicFile::create(path)?;
+    let mut output_file = BufWriter::new(output.file());
+    let options = StreamOptions { compression_level: 3, threads: 1, ..Default::default() };
+    encrypt_stream(
+        data,
+        &mut output_file,
+        FileMetadata::for_stream(filename, Some(data.len() as u64)),
+        master_key,
+        None,
+        None,
+        &options,
+        |_, _| {},
+    )?;
+    output_file.flush()?;
+    drop(output_file);
+    output.commit(path)?;
+    Ok(())
+}
+
+/// Opens a vault store. Stores written by older versions (V4 containers) are still read;
+/// they move to the stream format on their next save.
+pub fn load_store(path: &std::path::Path, master_key: &MasterKey) -> Result<crypto::InnerPayload> {
+    let mut file = File::open(path)?;
+    let mut ver_buf = [0u8; 4];
+    file.read_exact(&mut ver_buf).context("Failed to read version")?;
+
+    if u32::from_le_bytes(ver_buf) == 4 {
+        let container = crypto::EncryptedFileContainer::load(path)?;
+        return crypto::decrypt_file_with_master_key(master_key, None, &container);
+    }
+
+    file.rewind()?;
+    let keys = UnlockKeys { master_key, keyfile_bytes: None, identity: None, passphrase: None };
+    let mut content = Vec::new();
+    let metadata = decrypt_stream(file, &mut content, &keys, 1, None, |_, _| {})?;
+    Ok(crypto::InnerPayload { filename: metadata.filename, content })
+}
+
+// --- V4 MIGRATION ---
+
+/// Converts a V4 container (.qre) to the current stream format, in place.
+///
+/// 1. Decrypts the container (V4 files are small enough to fit in RAM, that is how they were written).
+/// 2. Encrypts the content to a temporary file with the same Master Key (+ Keyfile).
+/// 3. Decrypts the new file and compares it with the original content.
+/// 4. Replaces the original only if both match.
+///
+/// Returns `false`, without touching the file, when it is not a V4 container.
+pub fn migrate_container(path: &std::path::Path, master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) -> Result<bool> {
+    let mut ver_buf = [0u8; 4];
+    File::open(path)?.read_exact(&mut ver_buf).context("Failed to read version")?;
+    if u32::from_le_bytes(ver_buf) != 4 {
+        return Ok(false);
     }
 
-    // Decrypt the File Key (FEK)
-    let file_key_vec = cipher_wrap.decrypt(
-        Nonce::from_slice(&header.key_wrapping_nonce), 
-        header.encrypted_file_key.as_ref()
-    ).map_err(|_| anyhow!("Failed to unwrap file key"))?;
+    // 1. Decrypt
+    let container = crypto::EncryptedFileContainer::load(path)?;
+    let keyfile_bytes = if container.header.uses_keyfile { keyfile_bytes } else { None };
+    // The payload is wiped from RAM when dropped
+    let payload = crypto::decrypt_file_with_master_key(master_key, keyfile_bytes, &container)?;
+    drop(container);
+
+    // 2. Encrypt
+    let output = utils::AtomicFile::create(path)?;
+    let mut output_file = BufWriter::new(output.file());
+    let metadata = FileMetadata::for_stream(&payload.filename, Some(payload.content.len() as u64));
+    encrypt_stream(
+        payload.content.as_slice(),
+        &mut output_file,
+        metadata,
+        master_key,
+        keyfile_bytes,
+        None,
+        &StreamOptions::default(),
+        |_, _| {},
+    )?;
+    output_file.flush()?;
+    drop(output_file);
+
+    // 3. Verify
+    let keys = UnlockKeys { master_key, keyfile_bytes, identity: None, passphrase: None };
+    let mut check = Vec::with_capacity(payload.content.len());
+    decrypt_stream(File::open(output.temp_path())?, &mut check, &keys, 0, None, |_, _| {})
+        .context("Verification of the migrated file failed")?;
+    let matches = check == payload.content;
+    check.zeroize();
+    if !matches {
+        return Err(anyhow!("Verification of the migrated file failed: content mismatch"));
+    }
+
+    // 4. Replace
+    std::fs::set_permissions(output.temp_path(), std::fs::metadata(path)?.permissions())?;
+    output.commit(path)?;
+    Ok(true)
+}
+
+// --- STREAM DECRYPTOR ---
+
+/// Summary of a successful `verify_file_stream`.
+#[derive(Debug, Clone, Serialize)]
+pub struct VerifyReport {
+    pub filename: String,
+    pub original_size: u64,
+    pub chunk_count: u64,
+    // Public key of the signer, when the file is signed (the signature was checked).
+    pub signer: Option<[u8; 32]>,
+}
+
+/// Result of a successful `decrypt_file_stream`.
+#[derive(Debug, Clone)]
+pub struct UnlockedFile {
+    // The name actually used (e.g., "video (1).mp4" if "video.mp4" already existed).
+    pub filename: String,
+    // Public key of the signer, when the file is signed (the signature was checked).
+    pub signer: Option<[u8; 32]>,
+}
+
+/// Decrypts a V5 or V6 (.qre) stream file.
+///
+/// V6 files are fully authenticated: a modified header, reordered chunks,
+/// a missing final chunk or data appended after it all abort the decryption.
+/// V5 files carry no such protection and are only accepted for compatibility.
+/// Signed files are also rejected if their signature does not match.
+/// Decryption runs on `threads` worker threads (0 = one per CPU core).
+pub fn decrypt_file_stream(
+    input_path: &str,
+    output_dir: &str,
+    keys: &UnlockKeys,
+    threads: usize,
+    callback: impl Fn(u64, u64),
+) -> Result<UnlockedFile> {
+    let (mut input_file, file_size, volumes) = open_input(std::path::Path::new(input_path))?;
     
-    let cipher_file = Aes256Gcm::new_from_slice(&file_key_vec).unwrap();
-    wrapping_key.zeroize();
+    // 1. Read Version Bytes and Header
+    // The command handler already checked the version to route to the streaming logic.
+    let raw_header = read_stream_header(&mut input_file)?;
 
-    // 4. Prepare Output File
+    // 2. Unwrap Keys and open the Metadata block
+    let stream_keys = unlock_stream_header(&raw_header, keys)?;
+    check_volumes(volumes.as_ref(), &stream_keys)?;
+    let metadata = &stream_keys.metadata;
+
+    // 3. Prepare Output File
     // Ensures we don't overwrite existing files (e.g., "video (1).mp4")
-    let output_filename = header.original_filename;
+    let output_filename = sanitize_filename(&metadata.filename);
     let raw_output_path = std::path::Path::new(output_dir).join(&output_filename);
     let final_output_path = utils::get_unique_path(&raw_output_path);
     
@@ -288,60 +1973,297 @@ pub fn decrypt_file_stream(
         .to_string_lossy()
         .to_string();
 
-    let mut output_file = BufWriter::new(File::create(&final_output_path)?);
+    // Plaintext goes to a hidden temporary file (or folder), which is wiped if anything fails
+    if metadata.archive {
+        let mut output = archive::ArchiveExtractor::create(&final_output_path)?;
 
-    // 5. Decrypt Loop
-    let mut chunk_index: u64 = 0;
-    let mut size_buf = [0u8; 4];
+        // 4. Decrypt Pipeline, rebuilding the folder tree as entries arrive
+        decrypt_body(
+            &mut input_file,
+            &stream_keys,
+            raw_header.is_authenticated(),
+            threads,
+            file_size,
+            &mut output,
+            &callback,
+        )?;
+
+        // 5. Restore links and folder attributes, then move the folder into place
+        output.commit(&final_output_path)?;
+    } else {
+        let output = utils::AtomicFile::create(&final_output_path)?;
+        let mut output_file = BufWriter::new(output.file());
+
+        // 4. Decrypt Pipeline
+        decrypt_body(
+            &mut input_file,
+            &stream_keys,
+            raw_header.is_authenticated(),
+            threads,
+            file_size,
+            &mut output_file,
+            &callback,
+        )?;
+
+        // 5. Restore timestamps and permissions, then move the file into place
+        output_file.flush()?;
+        drop(output_file);
+        metadata.apply_to(output.file(), output.temp_path());
+        output.commit(&final_output_path)?;
+    }
+
+    Ok(UnlockedFile { filename: final_filename, signer: stream_keys.signer })
+}
+
+/// The stream to decrypt, its length (for progress reporting) and the volume set of split files.
+type StreamInput = (BufReader<Box<dyn Read>>, u64, Option<VolumeSet>);
+
+/// Opens a stream file, or the whole set when `path` is a volume (`.qre.001`...).
+fn open_input(path: &std::path::Path) -> Result<StreamInput> {
+    if volumes::is_volume(path)? {
+        let set = VolumeSet::open(path)?;
+        let reader: Box<dyn Read> = Box::new(set.reader());
+        return Ok((BufReader::new(reader), set.stream_len(), Some(set)));
+    }
+    let file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    Ok((BufReader::new(Box::new(file)), file_size, None))
+}
+
+/// Split files: checks that every volume belongs to this file, in order (before any plaintext is written).
+pub(crate) fn check_volumes(volumes: Option<&VolumeSet>, keys: &StreamKeys) -> Result<()> {
+    match volumes {
+        Some(set) => set.verify(&keys.volume_key),
+        None => Ok(()),
+    }
+}
+
+/// Decrypts a V5 or V6 (.qre) stream from any reader into any writer
+/// (e.g., straight to a socket). Same checks as `decrypt_file_stream`.
+///
+/// Plaintext reaches `output` before the Trailer is checked, so on error the
+/// caller must discard whatever was written. `size_hint` is the encrypted length,
+/// used only for progress reporting. Returns the sealed metadata (filename, etc.).
+/// For a locked folder (`metadata.archive`), `output` receives the archive stream:
+/// write it into an `ArchiveExtractor` to rebuild the tree.
+pub fn decrypt_stream(
+    input: impl Read,
+    mut output: impl Write,
+    keys: &UnlockKeys,
+    threads: usize,
+    size_hint: Option<u64>,
+    callback: impl Fn(u64, u64),
+) -> Result<FileMetadata> {
+    let mut input = BufReader::new(input);
+    let raw_header = read_stream_header(&mut input)?;
+    let stream_keys = unlock_stream_header(&raw_header, keys)?;
+
+    decrypt_body(
+        &mut input,
+        &stream_keys,
+        raw_header.is_authenticated(),
+        threads,
+        size_hint.unwrap_or(0),
+        &mut output,
+        &callback,
+    )?;
+    output.flush()?;
+
+    Ok(stream_keys.metadata)
+}
+
+/// Checks a V5 or V6 (.qre) stream file without writing any plaintext.
+///
+/// Runs the exact same checks as `decrypt_file_stream` (password, header MAC,
+/// every chunk tag, chunk order and the Trailer) but discards the output.
+/// On failure, a `ChunkError` in the error chain tells which chunk is damaged.
+pub fn verify_file_stream(
+    input_path: &str,
+    keys: &UnlockKeys,
+    threads: usize,
+    callback: impl Fn(u64, u64),
+) -> Result<VerifyReport> {
+    verify_into(input_path, keys, threads, &mut std::io::sink(), callback)
+}
+
+/// Checks that a freshly locked file unlocks to exactly `source`: a file,
+/// or a folder read again through the same archive stream it was locked as.
+/// Run before the original is destroyed, so a file that changed while being
+/// locked (or a bad write) never costs the only copy.
+pub fn verify_against_source(
+    input_path: &str,
+    source: &std::path::Path,
+    symlinks: SymlinkPolicy,
+    keys: &UnlockKeys,
+    threads: usize,
+    callback: impl Fn(u64, u64),
+) -> Result<VerifyReport> {
+    // 1. What the locked file decrypts to
+    let mut unlocked = signing::HashingWriter::new(std::io::sink(), Some(Sha256::new()));
+    let report = verify_into(input_path, keys, threads, &mut unlocked, callback)?;
+    let (_, unlocked_digest) = unlocked.finish();
+
+    // 2. What the original reads as now
+    let mut original: Box<dyn Read> = if source.is_dir() {
+        Box::new(archive::ArchiveReader::new(source, symlinks)?)
+    } else {
+        Box::new(File::open(source)?)
+    };
+    let mut hasher = signing::HashingWriter::new(std::io::sink(), Some(Sha256::new()));
+    std::io::copy(&mut original, &mut hasher)?;
+    let (_, original_digest) = hasher.finish();
+
+    if unlocked_digest != original_digest {
+        return Err(anyhow!("The locked file does not match the original (was it modified while being locked?)."));
+    }
+    Ok(report)
+}
+
+/// Decrypts a whole locked file into `output`, checking every chunk, the Trailer and the signature.
+fn verify_into(
+    input_path: &str,
+    keys: &UnlockKeys,
+    threads: usize,
+    output: &mut impl Write,
+    callback: impl Fn(u64, u64),
+) -> Result<VerifyReport> {
+    let (mut input_file, file_size, volumes) = open_input(std::path::Path::new(input_path))?;
+
+    let raw_header = read_stream_header(&mut input_file)?;
+    let stream_keys = unlock_stream_header(&raw_header, keys)?;
+    check_volumes(volumes.as_ref(), &stream_keys)?;
+
+    let chunk_count = decrypt_body(
+        &mut input_file,
+        &stream_keys,
+        raw_header.is_authenticated(),
+        threads,
+        file_size,
+        output,
+        &callback,
+    )?;
+
+    Ok(VerifyReport {
+        filename: stream_keys.metadata.filename.clone(),
+        original_size: stream_keys.metadata.original_size,
+        chunk_count,
+        signer: stream_keys.signer,
+    })
+}
+
+/// Decrypts every chunk after the header into `output`, checks the Trailer (V6)
+/// and, for signed files, the signature.
+/// Returns the number of data chunks.
+fn decrypt_body(
+    input_file: &mut impl BufRead,
+    keys: &StreamKeys,
+    authenticated: bool,
+    threads: usize,
+    file_size: u64,
+    output: &mut impl Write,
+    callback: &impl Fn(u64, u64),
+) -> Result<u64> {
+    let (Some(signer), Some(hasher)) = (&keys.signer, &keys.signature_hasher) else {
+        return decrypt_records(input_file, keys, authenticated, threads, file_size, output, callback);
+    };
+
+    // The signature record is held back, so the Trailer is still the last record the decoder sees
+    let mut records = BufReader::new(signing::TailReader::new(&mut *input_file, signing::SIGNATURE_RECORD_LEN, hasher.clone()));
+    let chunk_count = decrypt_records(&mut records, keys, authenticated, threads, file_size, output, callback)?;
+    let (digest, signature) = records.into_inner().finish()?;
+    signing::verify_record(&signature, &digest, signer)?;
+    Ok(chunk_count)
+}
+
+/// Decrypts every chunk record into `output` and checks the Trailer (V6).
+/// Records are read and plaintext is written in order on this thread,
+/// decryption & decompression run on worker threads.
+fn decrypt_records(
+    input_file: &mut impl BufRead,
+    keys: &StreamKeys,
+    authenticated: bool,
+    threads: usize,
+    file_size: u64,
+    output: &mut impl Write,
+    callback: &impl Fn(u64, u64),
+) -> Result<u64> {
+    let mut read_index: u64 = 0;
+    let mut sealed_trailer: Option<Vec<u8>> = None;
+    let mut chunk_count: u64 = 0;
     let mut processed_file_bytes = 0;
+    let mut plaintext_bytes: u64 = 0;
+    let mut hasher = Sha256::new();
+    let mut decompressor = match keys.is_stream_compressed() {
+        true => Some(StreamDecompressor::new()?),
+        false => None,
+    };
 
-    loop {
-        // Read Chunk Size (4 bytes)
-        match input_file.read_exact(&mut size_buf) {
-            Ok(_) => {},
-            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break, // Clean EOF
-            Err(e) => return Err(anyhow!("Read error: {}", e)),
-        };
-        
-        let chunk_len = u32::from_le_bytes(size_buf) as usize;
-        
-        // Safety check to prevent Out-Of-Memory attacks
-        if chunk_len > CHUNK_SIZE + 4096 { 
-             return Err(anyhow!("Chunk size too large (corrupt file?)"));
-        }
+    pipeline::run_ordered(
+        pipeline::resolve_threads(threads),
+        // Reader
+        || {
+            if sealed_trailer.is_some() {
+                return Ok(None);
+            }
 
-        // Read Encrypted Chunk
-        let mut ciphertext = vec![0u8; chunk_len];
-        input_file.read_exact(&mut ciphertext)?;
+            // Read Chunk Size (4 bytes)
+            let chunk_len = match read_chunk_len(&mut *input_file)? {
+                Some(len) => len,
+                // V6 streams end right after the Trailer (handled below),
+                // so running out of data here means the file was cut short.
+                None if authenticated => {
+                    return Err(chunk_error(read_index, "File is truncated: the final chunk is missing.".to_string()))
+                }
+                None => return Ok(None), // Clean EOF (V5)
+            };
 
-        // Re-calculate the Nonce for this chunk
-        let mut chunk_nonce_bytes = [0u8; AES_NONCE_LEN];
-        chunk_nonce_bytes.copy_from_slice(&header.base_nonce);
-        let index_bytes = chunk_index.to_le_bytes();
-        for i in 0..8 {
-            chunk_nonce_bytes[4 + i] ^= index_bytes[i];
-        }
-        let nonce = Nonce::from_slice(&chunk_nonce_bytes);
-
-        // Decrypt
-        let compressed = cipher_file.decrypt(nonce, ciphertext.as_ref())
-            .map_err(|_| anyhow!("Chunk {} decryption failed", chunk_index))?;
-
-        // Decompress
-        let plaintext = decompress_chunk(&compressed)?;
-
-        // Write to disk
-        output_file.write_all(&plaintext)?;
-        
-        chunk_index += 1;
-        processed_file_bytes += chunk_len as u64; 
-        
-        // Update UI every 5 chunks to reduce overhead
-        if chunk_index % 5 == 0 {
-            callback(processed_file_bytes, file_size);
-        }
+            // Read Encrypted Chunk
+            let mut ciphertext = vec![0u8; chunk_len];
+            input_file
+                .read_exact(&mut ciphertext)
+                .map_err(|_| chunk_error(read_index, format!("File is truncated inside chunk {}.", read_index)))?;
+            read_index += 1;
+
+            // The last record is the Trailer: keep it aside for the final check.
+            if authenticated && input_file.fill_buf()?.is_empty() {
+                sealed_trailer = Some(ciphertext);
+                return Ok(None);
+            }
+            Ok(Some(ciphertext))
+        },
+        // Workers: Decrypt and Decompress
+        |chunk_index, ciphertext: Vec<u8>| {
+            let plaintext = keys.open_data_chunk(chunk_index, &ciphertext)?;
+            Ok((ciphertext.len(), plaintext))
+        },
+        // Writer (stream-compressed chunks are decompressed here, in order)
+        |chunk_index, (chunk_len, plaintext): (usize, Vec<u8>)| {
+            let plaintext = match &mut decompressor {
+                Some(decompressor) => decompressor.segment(&plaintext).map_err(|e| {
+                    chunk_error(chunk_index, format!("Chunk {} failed to decompress: {}", chunk_index, e))
+                })?,
+                None => plaintext,
+            };
+            hasher.update(&plaintext);
+            output.write_all(&plaintext)?;
+
+            chunk_count += 1;
+            processed_file_bytes += chunk_len as u64;
+            plaintext_bytes += plaintext.len() as u64;
+
+            // Update UI every 5 chunks to reduce overhead
+            if chunk_count % 5 == 0 {
+                callback(processed_file_bytes, file_size);
+            }
+            Ok(())
+        },
+    )?;
+
+    // Check the stream against the Trailer (V6)
+    if let Some(sealed) = sealed_trailer {
+        let trailer = keys.open_trailer(chunk_count, &sealed)?;
+        verify_trailer(&trailer, chunk_count, plaintext_bytes, &hasher.finalize())?;
     }
 
-    output_file.flush()?;
-    Ok(final_filename) // Return the actual filename used
-}
\ No newline at end of file
+    Ok(chunk_count)
+}
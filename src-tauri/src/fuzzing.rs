@@ -0,0 +1,25 @@
+// Entry points for the cargo-fuzz targets in `fuzz/`.
+// Each one feeds untrusted bytes to a parser the way a file from disk would be,
+// then tries to unlock the result with a fixed key. None of them may panic.
+
+use crate::crypto;
+use crate::crypto_stream::{self, UnlockKeys};
+use crate::keychain::MasterKey;
+
+/// V4 container: parsing, then a decryption attempt.
+pub fn v4_container(data: &[u8]) {
+    if let Ok(container) = crypto::EncryptedFileContainer::read_from(data, data.len() as u64) {
+        let _ = crypto::decrypt_file_with_master_key(&MasterKey([0u8; 32]), None, &container);
+    }
+}
+
+/// V5 / V6 stream header: parsing, then an unlock attempt with a Master Key.
+/// Password and Hybrid slots are skipped (no passphrase, no identity), so no Argon2 run
+/// slows the fuzzer down.
+pub fn stream_header(data: &[u8]) {
+    if let Ok(raw) = crypto_stream::read_stream_header(&mut &data[..]) {
+        let master_key = MasterKey([0u8; 32]);
+        let keys = UnlockKeys { master_key: &master_key, keyfile_bytes: None, identity: None, passphrase: None };
+        let _ = crypto_stream::unlock_stream_header(&raw, &keys);
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
+use ml_kem::kem::{Decapsulate, Encapsulate};
+use ml_kem::{array::Array, Ciphertext, EncodedSizeUser, KemCore, MlKem768};
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+// --- CONSTANTS ---
+
+// Secret key material: X25519 scalar (32) + ML-KEM seeds d (32) and z (32).
+// ML-KEM keys are re-expanded from their seeds (FIPS 203, Algorithm 16) instead of storing 2400 bytes.
+const SECRET_LEN: usize = 96;
+
+// Size of an ML-KEM-768 ciphertext.
+pub const MLKEM_CIPHERTEXT_LEN: usize = 1088;
+
+// Domain separation for the key combiner and the fingerprint.
+const COMBINER_LABEL: &[u8] = b"QRE_HYBRID_KEM_V1";
+const FINGERPRINT_LABEL: &[u8] = b"QRE_IDENTITY_V1";
+
+// Prefix of an exported public identity, so a pasted string is easy to recognize.
+const EXPORT_PREFIX: &str = "qre-identity-v1:";
+
+type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+
+// --- DATA STRUCTURES ---
+
+/// The public half of a vault identity. Safe to share and to store in plaintext.
+/// Anyone holding it can lock files that only the matching vault can open.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HybridPublicKey {
+    pub x25519: [u8; 32],
+    // ML-KEM-768 Encapsulation Key (1184 bytes).
+    pub mlkem: Vec<u8>,
+}
+
+/// The secret half of a vault identity. Never written to disk unencrypted:
+/// the keychain stores it sealed with the Master Key.
+#[derive(Clone, Zeroize, ZeroizeOnDrop)]
+pub struct HybridSecretKey([u8; SECRET_LEN]);
+
+/// What the sender stores next to a wrapped key so the recipient can
+/// recompute the shared secret.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HybridEncapsulation {
+    pub x25519_ephemeral: [u8; 32],
+    // ML-KEM-768 Ciphertext (1088 bytes).
+    pub mlkem_ciphertext: Vec<u8>,
+}
+
+// --- KEYS ---
+
+impl HybridPublicKey {
+    /// A stable identifier for this key, used to find "our" slot in a header.
+    pub fn fingerprint(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(FINGERPRINT_LABEL);
+        hasher.update(self.x25519);
+        hasher.update(&self.mlkem);
+        hasher.finalize().into()
+    }
+
+    /// Short human-readable fingerprint for comparing keys out of band
+    /// (e.g., "3F2A-9C41-07BE-D215").
+    pub fn fingerprint_hex(&self) -> String {
+        format_fingerprint(&self.fingerprint())
+    }
+
+    /// Encodes the public key as a single line of text to share with others.
+    pub fn to_export_string(&self) -> Result<String> {
+        Ok(format!("{}{}", EXPORT_PREFIX, BASE64.encode(bincode::serialize(self)?)))
+    }
+
+    /// Parses a string produced by `to_export_string` on another QRE install.
+    pub fn from_export_string(text: &str) -> Result<Self> {
+        let encoded = text
+            .trim()
+            .strip_prefix(EXPORT_PREFIX)
+            .ok_or_else(|| anyhow!("Not a QRE identity"))?;
+        let bytes = BASE64.decode(encoded).context("Identity is not valid Base64")?;
+        let key: HybridPublicKey = bincode::deserialize(&bytes).context("Corrupted identity")?;
+        // Reject malformed keys now rather than when locking a file
+        key.mlkem_key()?;
+        Ok(key)
+    }
+
+    fn mlkem_key(&self) -> Result<EncapsulationKey> {
+        let encoded = Array::try_from(self.mlkem.as_slice())
+            .map_err(|_| anyhow!("Invalid ML-KEM public key length"))?;
+        Ok(EncapsulationKey::from_bytes(&encoded))
+    }
+}
+
+impl HybridSecretKey {
+    /// Generates a fresh identity.
+    pub fn generate(rng: &mut (impl RngCore + CryptoRng)) -> Self {
+        let mut bytes = [0u8; SECRET_LEN];
+        rng.fill_bytes(&mut bytes);
+        HybridSecretKey(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let arr: [u8; SECRET_LEN] = bytes
+            .try_into()
+            .map_err(|_| anyhow!("Invalid identity secret length"))?;
+        Ok(HybridSecretKey(arr))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn public_key(&self) -> HybridPublicKey {
+        let (_, ek) = self.mlkem_keys();
+        HybridPublicKey {
+            x25519: PublicKey::from(&self.x25519_secret()).to_bytes(),
+            mlkem: ek.as_bytes().to_vec(),
+        }
+    }
+
+    fn x25519_secret(&self) -> StaticSecret {
+        let mut scalar = [0u8; 32];
+        scalar.copy_from_slice(&self.0[..32]);
+        StaticSecret::from(scalar)
+    }
+
+    fn mlkem_keys(&self) -> (DecapsulationKey, EncapsulationKey) {
+        let d = Array::try_from(&self.0[32..64]).unwrap();
+        let z = Array::try_from(&self.0[64..96]).unwrap();
+        MlKem768::generate_deterministic(&d, &z)
+    }
+}
+
+/// Formats a fingerprint the way `fingerprint_hex` does (e.g., one read from a file header).
+pub fn format_fingerprint(fingerprint: &[u8; 32]) -> String {
+    fingerprint[..8]
+        .chunks(2)
+        .map(|pair| format!("{:02X}{:02X}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+// --- KEM ---
+
+/// Combines both shared secrets into one 256-bit key.
+/// The transcript (ephemeral and recipient X25519 keys) is mixed in so the result
+/// stays secure as long as EITHER ML-KEM or X25519 is unbroken.
+fn combine(mlkem_ss: &[u8], x25519_ss: &[u8], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(COMBINER_LABEL);
+    hasher.update(mlkem_ss);
+    hasher.update(x25519_ss);
+    hasher.update(ephemeral);
+    hasher.update(recipient);
+    hasher.finalize().into()
+}
+
+/// Creates a shared key for `recipient`. Only the holder of the matching
+/// `HybridSecretKey` can recover it from the returned encapsulation.
+/// `seed` drives all the randomness (so "Paranoid Mode" entropy flows in here too).
+pub fn encapsulate(recipient: &HybridPublicKey, seed: [u8; 32]) -> Result<(HybridEncapsulation, [u8; 32])> {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+
+    // 1. Post-Quantum half: ML-KEM-768
+    let (ciphertext, mlkem_ss) = recipient
+        .mlkem_key()?
+        .encapsulate(&mut rng)
+        .map_err(|_| anyhow!("ML-KEM encapsulation failed"))?;
+
+    // 2. Classical half: ephemeral X25519
+    let ephemeral = StaticSecret::random_from_rng(&mut rng);
+    let ephemeral_public = PublicKey::from(&ephemeral).to_bytes();
+    let x25519_ss = ephemeral.diffie_hellman(&PublicKey::from(recipient.x25519));
+    if !x25519_ss.was_contributory() {
+        return Err(anyhow!("Invalid X25519 public key"));
+    }
+
+    let key = combine(&mlkem_ss, x25519_ss.as_bytes(), &ephemeral_public, &recipient.x25519);
+    let encapsulation = HybridEncapsulation {
+        x25519_ephemeral: ephemeral_public,
+        mlkem_ciphertext: ciphertext.to_vec(),
+    };
+    Ok((encapsulation, key))
+}
+
+/// Recovers the shared key created by `encapsulate`.
+pub fn decapsulate(secret: &HybridSecretKey, encapsulation: &HybridEncapsulation) -> Result<[u8; 32]> {
+    let ciphertext = Ciphertext::<MlKem768>::try_from(encapsulation.mlkem_ciphertext.as_slice())
+        .map_err(|_| anyhow!("Invalid ML-KEM ciphertext length"))?;
+    let (dk, _) = secret.mlkem_keys();
+    let mlkem_ss = dk
+        .decapsulate(&ciphertext)
+        .map_err(|_| anyhow!("ML-KEM decapsulation failed"))?;
+
+    let x25519_ss = secret
+        .x25519_secret()
+        .diffie_hellman(&PublicKey::from(encapsulation.x25519_ephemeral));
+    if !x25519_ss.was_contributory() {
+        return Err(anyhow!("Invalid X25519 ephemeral key"));
+    }
+
+    let recipient = PublicKey::from(&secret.x25519_secret()).to_bytes();
+    Ok(combine(&mlkem_ss, x25519_ss.as_bytes(), &encapsulation.x25519_ephemeral, &recipient))
+}
@@ -0,0 +1,241 @@
+use crate::cipher::CipherSuite;
+use crate::crypto;
+use crate::crypto_stream::{self, KeySlot, PaddingScheme};
+use crate::hybrid;
+use crate::signing;
+use crate::volumes;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use sha2::Digest;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+// --- DATA STRUCTURES ---
+
+/// What can be learned about a .qre file without any key.
+/// Everything here comes from the plaintext part of the headers and the record layout.
+#[derive(Serialize, Debug, Default)]
+pub struct InspectReport {
+    pub path: String,
+    pub file_size: u64,
+    // "v4-container", "v5-stream", "v6-stream", or "unknown" (not a .qre file).
+    pub format: String,
+    pub version: Option<u32>,
+    // False for foreign files and for damaged headers or chunk layouts (see `problems`).
+    pub well_formed: bool,
+    pub problems: Vec<String>,
+    pub cipher: Option<CipherSuite>,
+    pub compression: Option<String>,
+    // `None` when the format does not record it (V5).
+    pub uses_keyfile: Option<bool>,
+    pub key_slots: Vec<SlotInfo>,
+    // Data chunks (streams only). Padded files also count their padding records.
+    pub chunk_count: Option<u64>,
+    pub padding: Option<PaddingScheme>,
+    pub fixed_size_chunks: Option<bool>,
+    // V5 only: the original filename was stored in plaintext.
+    pub plaintext_filename: Option<String>,
+    // Last modification of the .qre file (seconds since the Unix Epoch).
+    // The creation time is only kept inside the encrypted metadata.
+    pub modified: Option<u64>,
+    // Signed files: fingerprint of the signer's key, and whether the signature matches.
+    // Signatures are public-key checks, so no key is needed (but the whole file is read).
+    pub signer: Option<String>,
+    pub signature_valid: Option<bool>,
+    // Whether the signer is in the vault's trusted signers (only when the vault is unlocked).
+    pub trusted_signer: Option<bool>,
+}
+
+/// One way the file can be opened.
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SlotInfo {
+    // `uses_keyfile` is unknown for V5 files.
+    MasterKey { uses_keyfile: Option<bool> },
+    // Short fingerprint of the recipient's identity (e.g., "3F2A-9C41-07BE-D215").
+    Hybrid { recipient: String },
+    Password { memory_kib: u32, iterations: u32, parallelism: u32 },
+}
+
+// --- INSPECTION ---
+
+/// Parses the headers of a .qre file and walks its chunk records, without unlocking it.
+/// Never fails: unreadable, foreign or damaged files are reported through `problems`.
+pub fn inspect_qre(path: &Path) -> InspectReport {
+    let mut report = InspectReport {
+        path: path.to_string_lossy().to_string(),
+        format: "unknown".to_string(),
+        ..Default::default()
+    };
+
+    if let Err(e) = inspect_into(path, &mut report) {
+        report.problems.push(format!("{:#}", e));
+    }
+    report.well_formed = report.version.is_some() && report.problems.is_empty();
+    report
+}
+
+fn inspect_into(path: &Path, report: &mut InspectReport) -> Result<()> {
+    let meta = std::fs::metadata(path)?;
+    report.file_size = meta.len();
+    report.modified = meta
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+
+    let mut file = BufReader::new(File::open(path)?);
+    let mut ver_buf = [0u8; 4];
+    file.read_exact(&mut ver_buf).context("File is too short to be a .qre file")?;
+    let version = u32::from_le_bytes(ver_buf);
+
+    if version == 4 {
+        inspect_container(&mut file, report)
+    } else if crypto_stream::is_stream_version(version) {
+        file.rewind()?;
+        inspect_stream(&mut file, report)
+    } else if version == volumes::VOLUME_VERSION {
+        report.version = Some(version);
+        report.format = "v6-volume".to_string();
+        Err(anyhow!("One volume of a split file: its headers can only be checked by verifying the whole set"))
+    } else {
+        Err(anyhow!("Not a .qre file (unknown version {})", version))
+    }
+}
+
+/// V4: a single AES-GCM container, `[u32 4][EncryptedFileHeader][u64 length][ciphertext]`.
+fn inspect_container(file: &mut BufReader<File>, report: &mut InspectReport) -> Result<()> {
+    report.version = Some(4);
+    report.format = "v4-container".to_string();
+    report.cipher = Some(CipherSuite::Aes256Gcm);
+    report.compression = Some("zstd".to_string());
+
+    let header = crypto::read_container_header(file)?;
+    report.uses_keyfile = Some(header.uses_keyfile);
+    report.key_slots.push(SlotInfo::MasterKey { uses_keyfile: Some(header.uses_keyfile) });
+
+    let mut len_buf = [0u8; 8];
+    file.read_exact(&mut len_buf).context("File is truncated: the ciphertext is missing")?;
+    let ciphertext_len = u64::from_le_bytes(len_buf);
+    let remaining = report.file_size - file.stream_position()?;
+    if ciphertext_len != remaining {
+        report.problems.push(format!(
+            "Ciphertext should be {} bytes, the file holds {}",
+            ciphertext_len, remaining
+        ));
+    }
+    Ok(())
+}
+
+/// V5 / V6: the stream header, then one `[u32 length][ciphertext]` record per chunk.
+fn inspect_stream(file: &mut BufReader<File>, report: &mut InspectReport) -> Result<()> {
+    let raw = crypto_stream::read_stream_header(file)?;
+    let header = &raw.header;
+    let authenticated = raw.is_authenticated();
+
+    report.version = Some(raw.version);
+    report.format = if authenticated { "v6-stream" } else { "v5-stream" }.to_string();
+    report.cipher = Some(header.cipher_suite);
+    // Adaptive streams store incompressible chunks as-is
+    report.compression = Some(
+        if header.stream_compression {
+            "zstd (stream-wide)"
+        } else if header.adaptive_chunks {
+            "zstd (adaptive)"
+        } else {
+            "zstd"
+        }
+        .to_string(),
+    );
+    report.plaintext_filename = raw.legacy_filename.clone();
+
+    if authenticated {
+        report.padding = Some(header.padding);
+        report.fixed_size_chunks = Some(header.fixed_size_chunks);
+        report.key_slots = header.key_slots.iter().map(slot_info).collect();
+        report.uses_keyfile = Some(
+            header
+                .key_slots
+                .iter()
+                .any(|slot| matches!(slot, KeySlot::MasterKey { uses_keyfile: true, .. })),
+        );
+    } else {
+        report.key_slots.push(SlotInfo::MasterKey { uses_keyfile: None });
+    }
+
+    // Walk the size prefixes. The chunks themselves cannot be checked without the key.
+    let body_start = file.stream_position()?;
+    let mut records: u64 = 0;
+    loop {
+        let record_start = file.stream_position()?;
+        let Some(len) = crypto_stream::read_chunk_len(file).with_context(|| format!("Chunk {}", records))? else {
+            if record_start != report.file_size {
+                report.problems.push(format!("{} stray bytes after the last chunk", report.file_size - record_start));
+            }
+            break;
+        };
+        file.seek_relative(len as i64)?;
+        if file.stream_position()? > report.file_size {
+            report.problems.push(format!("File is truncated inside chunk {}", records));
+            break;
+        }
+        records += 1;
+    }
+
+    // The last V6 record is the Trailer, followed by the signature on signed files
+    let tail_records = if header.signer.is_some() { 2 } else { 1 };
+    report.chunk_count = if authenticated {
+        if records < tail_records {
+            report.problems.push("File is truncated: the final chunk is missing".to_string());
+        }
+        Some(records.saturating_sub(tail_records))
+    } else {
+        Some(records)
+    };
+
+    if let Some(signer) = &header.signer {
+        report.signer = Some(signing::signer_fingerprint(signer));
+        match check_signature(file, header, body_start, report.file_size) {
+            Ok(()) => report.signature_valid = Some(true),
+            Err(e) => {
+                report.signature_valid = Some(false);
+                report.problems.push(format!("{:#}", e));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Hashes every record after the header, up to the signature record, and checks the signature.
+fn check_signature(
+    file: &mut BufReader<File>,
+    header: &crypto_stream::StreamHeader,
+    body_start: u64,
+    file_size: u64,
+) -> Result<()> {
+    let signer = header.signer.as_ref().ok_or_else(|| anyhow!("File is not signed"))?;
+    let signed_len = file_size
+        .checked_sub(body_start + signing::SIGNATURE_RECORD_LEN as u64)
+        .ok_or_else(|| anyhow!("File is truncated: the signature is missing."))?;
+
+    let mut hasher = header.signature_hasher()?;
+    file.seek(SeekFrom::Start(body_start))?;
+    std::io::copy(&mut file.by_ref().take(signed_len), &mut hasher)?;
+
+    let mut record = [0u8; signing::SIGNATURE_RECORD_LEN];
+    file.read_exact(&mut record)?;
+    signing::verify_record(&record, &hasher.finalize(), signer)
+}
+
+fn slot_info(slot: &KeySlot) -> SlotInfo {
+    match slot {
+        KeySlot::MasterKey { uses_keyfile, .. } => SlotInfo::MasterKey { uses_keyfile: Some(*uses_keyfile) },
+        KeySlot::Hybrid { recipient, .. } => SlotInfo::Hybrid { recipient: hybrid::format_fingerprint(recipient) },
+        KeySlot::Password { kdf, .. } => SlotInfo::Password {
+            memory_kib: kdf.memory_kib,
+            iterations: kdf.iterations,
+            parallelism: kdf.parallelism,
+        },
+    }
+}
@@ -2,9 +2,13 @@ use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Nonce,
 };
+use crate::hybrid::{HybridPublicKey, HybridSecretKey};
+use crate::rotation;
+use crate::utils;
 use anyhow::{anyhow, Context, Result};
 use argon2::password_hash::{rand_core::OsRng, SaltString};
 use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use ed25519_dalek::SigningKey;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use std::fs;
@@ -68,6 +72,28 @@ pub struct KeychainStore {
     pub recovery_nonce: Vec<u8>,
     // The Master Key encrypted with the recovery code (QRE-XXXX...).
     pub encrypted_master_key_recovery: Vec<u8>,
+
+    // --- Identity: Hybrid Keypair (ML-KEM-768 + X25519) ---
+    // Used for post-quantum file locking. Vaults created before this existed
+    // get one on first use, hence the defaults.
+    #[serde(default)]
+    pub identity_public: Option<HybridPublicKey>,
+    #[serde(default)]
+    pub identity_nonce: Vec<u8>,
+    // The identity secret encrypted with the Master Key.
+    #[serde(default)]
+    pub encrypted_identity_secret: Vec<u8>,
+
+    // --- Signing Key: Ed25519 ---
+    // Signs locked files so recipients can tell who produced them.
+    // Created on first use for older vaults, like the identity.
+    #[serde(default)]
+    pub signing_public: Option<[u8; 32]>,
+    #[serde(default)]
+    pub signing_nonce: Vec<u8>,
+    // The signing key encrypted with the Master Key.
+    #[serde(default)]
+    pub encrypted_signing_secret: Vec<u8>,
 }
 
 // --- Internal Logic ---
@@ -89,6 +115,82 @@ fn derive_kek(secret: &str, salt_str: &str, mem: u32, iter: u32, par: u32) -> [u
     key
 }
 
+/// Generates a new identity keypair and seals its secret half into `store`.
+fn seal_new_identity(store: &mut KeychainStore, master_key: &MasterKey) -> Result<HybridSecretKey> {
+    let secret = HybridSecretKey::generate(&mut OsRng);
+    seal_identity(store, &secret, master_key)?;
+    Ok(secret)
+}
+
+/// Seals the identity secret into `store` with the Master Key.
+fn seal_identity(store: &mut KeychainStore, secret: &HybridSecretKey, master_key: &MasterKey) -> Result<()> {
+    let cipher = Aes256Gcm::new_from_slice(&master_key.0).unwrap();
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), secret.as_bytes())
+        .map_err(|_| anyhow!("Failed to encrypt identity"))?;
+
+    store.identity_public = Some(secret.public_key());
+    store.identity_nonce = nonce_bytes.to_vec();
+    store.encrypted_identity_secret = encrypted;
+    Ok(())
+}
+
+/// Generates a new Ed25519 signing key and seals it into `store`.
+fn seal_new_signing_key(store: &mut KeychainStore, master_key: &MasterKey) -> Result<SigningKey> {
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    let signing_key = SigningKey::from_bytes(&seed);
+    seed.zeroize();
+    seal_signing_key(store, &signing_key, master_key)?;
+    Ok(signing_key)
+}
+
+/// Seals the signing key into `store` with the Master Key.
+fn seal_signing_key(store: &mut KeychainStore, signing_key: &SigningKey, master_key: &MasterKey) -> Result<()> {
+    let cipher = Aes256Gcm::new_from_slice(&master_key.0).unwrap();
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), signing_key.as_bytes().as_ref())
+        .map_err(|_| anyhow!("Failed to encrypt signing key"))?;
+
+    store.signing_public = Some(signing_key.verifying_key().to_bytes());
+    store.signing_nonce = nonce_bytes.to_vec();
+    store.encrypted_signing_secret = encrypted;
+    Ok(())
+}
+
+/// Generates a Recovery Code (QRE-XXXX-XXXX-XXXX-XXXX).
+fn new_recovery_code() -> String {
+    let raw_recovery: String = (0..4)
+        .map(|_| {
+            let n: u16 = rand::random();
+            format!("{:04X}", n)
+        })
+        .collect::<Vec<String>>()
+        .join("-");
+    format!("QRE-{}", raw_recovery)
+}
+
+/// Encrypts the Master Key with a KEK derived from `secret` (password or Recovery Code).
+/// Returns the new salt, nonce and ciphertext of the slot.
+fn seal_master_key(store: &KeychainStore, secret: &str, master_key: &MasterKey) -> Result<(String, Vec<u8>, Vec<u8>)> {
+    let salt = SaltString::generate(&mut OsRng).as_str().to_string();
+    let kek = derive_kek(secret, &salt, store.kdf_memory, store.kdf_iterations, store.kdf_parallelism);
+    let cipher = Aes256Gcm::new_from_slice(&kek).unwrap();
+
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce_bytes);
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), master_key.0.as_ref())
+        .map_err(|_| anyhow!("Failed to encrypt master key"))?;
+    Ok((salt, nonce_bytes.to_vec(), encrypted))
+}
+
 // --- Public API ---
 
 /// Initializes a NEW vault (Onboarding).
@@ -125,14 +227,7 @@ pub fn init_keychain(path: &Path, password: &str) -> Result<(String, MasterKey)>
         .map_err(|e| anyhow!("Failed to encrypt master key: {}", e))?;
 
     // 4. Prepare Recovery Slot
-    let raw_recovery: String = (0..4)
-        .map(|_| {
-            let n: u16 = rand::random();
-            format!("{:04X}", n)
-        })
-        .collect::<Vec<String>>()
-        .join("-");
-    let recovery_code = format!("QRE-{}", raw_recovery);
+    let recovery_code = new_recovery_code();
 
     let rec_salt = SaltString::generate(&mut OsRng).as_str().to_string();
     let rec_kek = derive_kek(&recovery_code, &rec_salt, mem, iter, par);
@@ -146,7 +241,7 @@ pub fn init_keychain(path: &Path, password: &str) -> Result<(String, MasterKey)>
         .map_err(|_| anyhow!("Failed to encrypt recovery slot"))?;
 
     // 5. Save to Disk
-    let store = KeychainStore {
+    let mut store = KeychainStore {
         vault_id: uuid::Uuid::new_v4().to_string(),
         kdf_memory: mem,
         kdf_iterations: iter,
@@ -157,7 +252,15 @@ pub fn init_keychain(path: &Path, password: &str) -> Result<(String, MasterKey)>
         recovery_salt: rec_salt,
         recovery_nonce: rec_nonce_bytes.to_vec(),
         encrypted_master_key_recovery: enc_mk_rec,
+        identity_public: None,
+        identity_nonce: Vec::new(),
+        encrypted_identity_secret: Vec::new(),
+        signing_public: None,
+        signing_nonce: Vec::new(),
+        encrypted_signing_secret: Vec::new(),
     };
+    seal_new_identity(&mut store, &master_key)?;
+    seal_new_signing_key(&mut store, &master_key)?;
 
     let file = fs::File::create(path)?;
     serde_json::to_writer_pretty(file, &store)?;
@@ -265,14 +368,7 @@ pub fn reset_recovery_code(path: &Path, master_key: &MasterKey) -> Result<String
     let mut store: KeychainStore = serde_json::from_reader(file)?;
 
     // 1. Generate NEW Recovery Code string
-    let raw_recovery: String = (0..4)
-        .map(|_| {
-            let n: u16 = rand::random();
-            format!("{:04X}", n)
-        })
-        .collect::<Vec<String>>()
-        .join("-");
-    let recovery_code = format!("QRE-{}", raw_recovery);
+    let recovery_code = new_recovery_code();
 
     // 2. Encrypt Master Key with new code
     let rec_salt = SaltString::generate(&mut OsRng).as_str().to_string();
@@ -345,6 +441,135 @@ pub fn change_password(path: &Path, master_key: &MasterKey, new_password: &str)
     Ok(())
 }
 
+/// Re-seals every slot of the keychain for `new_master_key` (Master Key rotation).
+/// 1. Checks `password` against the current keychain.
+/// 2. Re-encrypts the identity secret and the signing key (both are kept, so contacts
+///    and trusted signers stay valid).
+/// 3. Seals the new Master Key with the password (Slot 1) and a NEW Recovery Code (Slot 2):
+///    the old code opens the old Master Key, so it cannot be kept.
+/// 4. Writes the result to `output` (the current keychain is left untouched).
+///
+/// Returns the new Recovery Code.
+pub fn rotate_keychain(
+    path: &Path,
+    password: &str,
+    old_master_key: &MasterKey,
+    new_master_key: &MasterKey,
+    output: &Path,
+) -> Result<String> {
+    // 1. Verify Password
+    let current = unlock_keychain(path, password)?;
+    if current.0 != old_master_key.0 {
+        return Err(anyhow!("Incorrect Password"));
+    }
+
+    let file = fs::File::open(path)?;
+    let mut store: KeychainStore = serde_json::from_reader(file)?;
+
+    // 2. Identity and Signing Key
+    if let Some(secret) = load_identity(path, old_master_key)? {
+        seal_identity(&mut store, &secret, new_master_key)?;
+    }
+    if let Some(signing_key) = load_signing_key(path, old_master_key)? {
+        seal_signing_key(&mut store, &signing_key, new_master_key)?;
+    }
+
+    // 3. Password and Recovery slots
+    let (pass_salt, pass_nonce, enc_mk_pass) = seal_master_key(&store, password, new_master_key)?;
+    store.password_salt = pass_salt;
+    store.password_nonce = pass_nonce;
+    store.encrypted_master_key_pass = enc_mk_pass;
+
+    let recovery_code = new_recovery_code();
+    let (rec_salt, rec_nonce, enc_mk_rec) = seal_master_key(&store, &recovery_code, new_master_key)?;
+    store.recovery_salt = rec_salt;
+    store.recovery_nonce = rec_nonce;
+    store.encrypted_master_key_recovery = enc_mk_rec;
+
+    // 4. Save (flushed to disk: the rotation journal relies on it)
+    let outfile = fs::File::create(output)?;
+    serde_json::to_writer_pretty(&outfile, &store)?;
+    outfile.sync_all()?;
+
+    Ok(recovery_code)
+}
+
+/// Returns the vault's identity secret, or `None` if this vault has none yet.
+pub fn load_identity(path: &Path, master_key: &MasterKey) -> Result<Option<HybridSecretKey>> {
+    let file = fs::File::open(path)?;
+    let store: KeychainStore = serde_json::from_reader(file)?;
+    if store.identity_public.is_none() {
+        return Ok(None);
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(&master_key.0).unwrap();
+    let mut secret_bytes = cipher
+        .decrypt(Nonce::from_slice(&store.identity_nonce), store.encrypted_identity_secret.as_ref())
+        .map_err(|_| anyhow!("Failed to decrypt identity"))?;
+    let secret = HybridSecretKey::from_bytes(&secret_bytes);
+    secret_bytes.zeroize();
+    secret.map(Some)
+}
+
+/// Like `load_identity`, but creates (and saves) the identity if it is missing.
+pub fn ensure_identity(path: &Path, master_key: &MasterKey) -> Result<HybridSecretKey> {
+    // Not while a rotation copies the keychain
+    let _journal_lock = rotation::JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(secret) = load_identity(path, master_key)? {
+        return Ok(secret);
+    }
+
+    let file = fs::File::open(path)?;
+    let mut store: KeychainStore = serde_json::from_reader(file)?;
+    let secret = seal_new_identity(&mut store, master_key)?;
+
+    // The keychain is the vault's only key store: never leave it half-written.
+    let output = utils::AtomicFile::create(path)?;
+    serde_json::to_writer_pretty(output.file(), &store)?;
+    output.commit(path)?;
+
+    Ok(secret)
+}
+
+/// Returns the vault's signing key, or `None` if this vault has none yet.
+pub fn load_signing_key(path: &Path, master_key: &MasterKey) -> Result<Option<SigningKey>> {
+    let file = fs::File::open(path)?;
+    let store: KeychainStore = serde_json::from_reader(file)?;
+    if store.signing_public.is_none() {
+        return Ok(None);
+    }
+
+    let cipher = Aes256Gcm::new_from_slice(&master_key.0).unwrap();
+    let mut secret_bytes = cipher
+        .decrypt(Nonce::from_slice(&store.signing_nonce), store.encrypted_signing_secret.as_ref())
+        .map_err(|_| anyhow!("Failed to decrypt signing key"))?;
+    let seed: Result<[u8; 32]> = secret_bytes.as_slice().try_into().map_err(|_| anyhow!("Invalid signing key length"));
+    secret_bytes.zeroize();
+    let mut seed = seed?;
+    let signing_key = SigningKey::from_bytes(&seed);
+    seed.zeroize();
+    Ok(Some(signing_key))
+}
+
+/// Like `load_signing_key`, but creates (and saves) the signing key if it is missing.
+pub fn ensure_signing_key(path: &Path, master_key: &MasterKey) -> Result<SigningKey> {
+    // Not while a rotation copies the keychain
+    let _journal_lock = rotation::JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(signing_key) = load_signing_key(path, master_key)? {
+        return Ok(signing_key);
+    }
+
+    let file = fs::File::open(path)?;
+    let mut store: KeychainStore = serde_json::from_reader(file)?;
+    let signing_key = seal_new_signing_key(&mut store, master_key)?;
+
+    let output = utils::AtomicFile::create(path)?;
+    serde_json::to_writer_pretty(output.file(), &store)?;
+    output.commit(path)?;
+
+    Ok(signing_key)
+}
+
 /// Simple check to see if a vault file exists.
 pub fn keychain_exists(path: &Path) -> bool {
     path.exists()
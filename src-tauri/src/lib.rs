@@ -1,7 +1,19 @@
+mod archive;
 mod commands;
+mod cipher;
 mod crypto;
 mod crypto_stream;
+mod hybrid;
+mod inspect;
+mod parity;
+mod pipeline;
+mod qre_reader;
+mod rotation;
+mod signing;
+mod volumes;
 mod entropy;
+#[doc(hidden)]
+pub mod fuzzing;
 mod keychain;
 mod notes;
 mod clipboard_store;
@@ -15,6 +27,14 @@ mod breach;
 mod cleaner;
 mod qr;
 mod bookmarks;
+mod contacts;
+
+// Streaming engine over any `Read` / `Write` (stdin, pipes, sockets, buffers),
+// for callers embedding the engine without going through the Tauri commands.
+pub use archive::{ArchiveExtractor, ArchiveReader, SymlinkPolicy};
+pub use crypto_stream::{decrypt_stream, encrypt_stream, FileMetadata, PaddingScheme, StreamOptions, UnlockKeys};
+pub use inspect::{inspect_qre, InspectReport};
+pub use keychain::MasterKey;
 
 use state::SessionState;
 use std::sync::{Arc, Mutex};
@@ -87,6 +107,25 @@ pub fn run() {
             // Crypto
             commands::lock_file,
             commands::unlock_file,
+            commands::read_locked_range,
+            commands::verify_files,
+            commands::inspect_qre,
+            commands::rewrap_file,
+            commands::rotate_master_key,
+            commands::retry_key_rotation,
+            commands::forget_old_master_key,
+            commands::migrate_files,
+            commands::repair_qre,
+            // Identity & Contacts
+            commands::export_identity,
+            commands::import_identity,
+            commands::load_contacts_vault,
+            commands::save_contacts_vault,
+            // Signing
+            commands::get_signing_fingerprint,
+            commands::trust_signer,
+            commands::load_trusted_signers,
+            commands::save_trusted_signers,
             // Vaults
             commands::load_password_vault,
             commands::save_password_vault,
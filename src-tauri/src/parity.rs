@@ -0,0 +1,374 @@
+use crate::pipeline;
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// --- CONSTANTS ---
+
+// Version bytes of a recovery file.
+const PARITY_VERSION: u32 = 1;
+
+// The locked file is cut into fixed-size shards (whatever records they hold),
+// and every group of DATA_SHARDS shards gets its own parity shards.
+// A flipped bit damages one shard; a group survives as many damaged shards as it has parity shards.
+const SHARD_SIZE: usize = 64 * 1024;
+const DATA_SHARDS: usize = 64;
+
+// [Version (4 bytes)] + [ParityHeader (52 bytes, fixed-width)] + [SHA-256 of both (32 bytes)].
+// Written at both ends of the recovery file, so one damaged copy does not make it useless.
+const HEADER_BLOCK_LEN: usize = 4 + 52 + 32;
+
+const DIGEST_LEN: usize = 32;
+
+// Overhead accepted by `write_parity` (parity size, as a percentage of the locked file).
+pub const MAX_OVERHEAD_PERCENT: u32 = 100;
+
+// --- DATA STRUCTURES ---
+
+/// Describes the layout of a recovery file (`name.qre.parity`).
+///
+/// After the header, one fixed-size record per group:
+/// the SHA-256 of each data shard, then each parity shard preceded by its SHA-256.
+/// The digests locate the damaged shards, so no key is needed to repair.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+struct ParityHeader {
+    // Length and SHA-256 of the intact locked file. A repair is only written
+    // if it gives back exactly this file (so stale recovery data never does harm).
+    file_len: u64,
+    file_hash: [u8; 32],
+    shard_size: u32,
+    data_shards: u32,
+    parity_shards: u32,
+}
+
+impl ParityHeader {
+    fn group_count(&self) -> u64 {
+        self.file_len.div_ceil(self.shard_size as u64 * self.data_shards as u64).max(1)
+    }
+
+    fn group_record_len(&self) -> u64 {
+        (self.data_shards as usize * DIGEST_LEN + self.parity_shards as usize * (DIGEST_LEN + self.shard_size as usize)) as u64
+    }
+
+    /// Bytes of the locked file covered by one group.
+    fn group_span(&self) -> u64 {
+        self.shard_size as u64 * self.data_shards as u64
+    }
+
+    fn to_block(self) -> Result<Vec<u8>> {
+        let mut block = PARITY_VERSION.to_le_bytes().to_vec();
+        block.extend_from_slice(&bincode::serialize(&self)?);
+        let digest = Sha256::digest(&block);
+        block.extend_from_slice(&digest);
+        Ok(block)
+    }
+
+    fn from_block(block: &[u8]) -> Result<Self> {
+        let (body, digest) = block.split_at(HEADER_BLOCK_LEN - DIGEST_LEN);
+        if Sha256::digest(body).as_slice() != digest {
+            return Err(anyhow!("Recovery file header is damaged"));
+        }
+        if u32::from_le_bytes(body[..4].try_into().unwrap()) != PARITY_VERSION {
+            return Err(anyhow!("Unsupported recovery file version"));
+        }
+        let header: ParityHeader = bincode::deserialize(&body[4..])?;
+        if header.shard_size == 0
+            || header.shard_size as usize > 4 * SHARD_SIZE
+            || header.data_shards == 0
+            || header.parity_shards == 0
+            || header.data_shards + header.parity_shards > 256
+        {
+            return Err(anyhow!("Recovery file header is out of range"));
+        }
+        Ok(header)
+    }
+}
+
+/// Outcome of `repair_file`.
+#[derive(Serialize, Debug, Default)]
+pub struct RepairReport {
+    // Shards of the locked file that did not match their digest (or were missing).
+    pub damaged_shards: u64,
+    // Damaged shards rebuilt from parity and written back.
+    pub repaired_shards: u64,
+    // Groups with more damage than parity shards (nothing is written then).
+    pub unrecoverable_groups: u64,
+}
+
+/// Where the recovery records of `path` are kept: "report.pdf.qre" -> "report.pdf.qre.parity".
+pub fn parity_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".parity");
+    PathBuf::from(name)
+}
+
+fn digest(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Reads up to `len` bytes at `offset`, zero-padded to `padded_len` (past the End of File too).
+fn read_padded(file: &mut BufReader<File>, offset: u64, len: usize, padded_len: usize) -> Result<Vec<u8>> {
+    let mut buf = vec![0u8; padded_len];
+    file.seek(SeekFrom::Start(offset))?;
+    let mut filled = 0;
+    while filled < len {
+        match file.read(&mut buf[filled..len])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(buf)
+}
+
+// --- WRITING ---
+
+/// Computes the recovery records of a locked file and writes them next to it
+/// (`name.qre.parity`), replacing any previous ones.
+/// `overhead_percent` is the parity size relative to the file (e.g., 10 = survives
+/// up to ~10% damaged shards in every 4MB stretch of the file).
+pub fn write_parity(path: &Path, overhead_percent: u32, threads: usize) -> Result<PathBuf> {
+    if overhead_percent == 0 || overhead_percent > MAX_OVERHEAD_PERCENT {
+        return Err(anyhow!("Recovery overhead must be between 1% and {}%.", MAX_OVERHEAD_PERCENT));
+    }
+    let parity_shards = (DATA_SHARDS * overhead_percent as usize).div_ceil(100);
+    write_parity_with(path, parity_shards as u32, threads)
+}
+
+/// Recomputes the recovery records of `path` with the same overhead, if it has any.
+/// Called whenever a locked file is rewritten in place (e.g., rewrapped).
+pub fn refresh_parity(path: &Path) -> Result<()> {
+    let parity = parity_path(path);
+    if !parity.exists() {
+        return Ok(());
+    }
+    let parity_shards = match read_parity_header(&parity) {
+        Ok(header) => header.parity_shards,
+        Err(_) => (DATA_SHARDS as u32).div_ceil(10),
+    };
+    write_parity_with(path, parity_shards, 0)?;
+    Ok(())
+}
+
+fn write_parity_with(path: &Path, parity_shards: u32, threads: usize) -> Result<PathBuf> {
+    let file_len = std::fs::metadata(path)?.len();
+    let mut header = ParityHeader {
+        file_len,
+        file_hash: [0u8; 32],
+        shard_size: SHARD_SIZE as u32,
+        data_shards: DATA_SHARDS as u32,
+        parity_shards,
+    };
+    let codec = ReedSolomon::new(DATA_SHARDS, parity_shards as usize).map_err(|e| anyhow!("Reed-Solomon: {:?}", e))?;
+
+    let parity = parity_path(path);
+    let output = utils::AtomicFile::create(&parity)?;
+    let mut output_file = BufWriter::new(output.file());
+    // The leading header is filled in at the end, once the file hash is known
+    output_file.write_all(&[0u8; HEADER_BLOCK_LEN])?;
+
+    let mut input = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut remaining = header.group_count();
+
+    pipeline::run_ordered(
+        pipeline::resolve_threads(threads),
+        // Reader: one group of data shards (zero-padded past the End of File)
+        || {
+            if remaining == 0 {
+                return Ok(None);
+            }
+            remaining -= 1;
+            let mut group = vec![0u8; SHARD_SIZE * DATA_SHARDS];
+            let mut filled = 0;
+            while filled < group.len() {
+                match input.read(&mut group[filled..])? {
+                    0 => break,
+                    n => filled += n,
+                }
+            }
+            hasher.update(&group[..filled]);
+            Ok(Some(group))
+        },
+        // Workers: digests and parity shards
+        |_, group: Vec<u8>| {
+            let mut shards: Vec<Vec<u8>> = group.chunks(SHARD_SIZE).map(|s| s.to_vec()).collect();
+            shards.resize(DATA_SHARDS + parity_shards as usize, vec![0u8; SHARD_SIZE]);
+            codec.encode(&mut shards).map_err(|e| anyhow!("Reed-Solomon: {:?}", e))?;
+
+            let mut record = Vec::with_capacity(header.group_record_len() as usize);
+            for shard in &shards[..DATA_SHARDS] {
+                record.extend_from_slice(&digest(shard));
+            }
+            for shard in &shards[DATA_SHARDS..] {
+                record.extend_from_slice(&digest(shard));
+                record.extend_from_slice(shard);
+            }
+            Ok(record)
+        },
+        // Writer
+        |_, record: Vec<u8>| {
+            output_file.write_all(&record)?;
+            Ok(())
+        },
+    )?;
+
+    // Trailing header, then the leading one
+    header.file_hash = hasher.finalize().into();
+    let block = header.to_block()?;
+    output_file.write_all(&block)?;
+    output_file.flush()?;
+    let mut file = output_file.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&block)?;
+
+    output.commit(&parity)?;
+    Ok(parity)
+}
+
+// --- REPAIR ---
+
+/// Reads the header of a recovery file, falling back to its trailing copy.
+fn read_parity_header(parity: &Path) -> Result<ParityHeader> {
+    let mut file = File::open(parity)?;
+    let len = file.metadata()?.len();
+    if len < 2 * HEADER_BLOCK_LEN as u64 {
+        return Err(anyhow!("Recovery file is truncated"));
+    }
+
+    let mut block = [0u8; HEADER_BLOCK_LEN];
+    file.read_exact(&mut block)?;
+    match ParityHeader::from_block(&block) {
+        Ok(header) => Ok(header),
+        Err(_) => {
+            file.seek(SeekFrom::End(-(HEADER_BLOCK_LEN as i64)))?;
+            file.read_exact(&mut block)?;
+            ParityHeader::from_block(&block)
+        }
+    }
+}
+
+/// Finds the shards of a locked file that no longer match their digest and rebuilds
+/// them from its recovery records (`name.qre.parity`). Needs no key.
+///
+/// Repairs are checked against the hash of the intact file before anything is written:
+/// if some group is beyond repair (or the recovery data is out of date), the file
+/// is left untouched. Otherwise the rebuilt shards are written in place and the
+/// file is cut back to its original length.
+pub fn repair_file(path: &Path, threads: usize) -> Result<RepairReport> {
+    let parity = parity_path(path);
+    if !parity.exists() {
+        return Err(anyhow!("No recovery records for this file ({} is missing).", parity.display()));
+    }
+    let header = read_parity_header(&parity)?;
+    if std::fs::metadata(&parity)?.len() != 2 * HEADER_BLOCK_LEN as u64 + header.group_count() * header.group_record_len() {
+        return Err(anyhow!("Recovery file is truncated"));
+    }
+
+    let shard_size = header.shard_size as usize;
+    let data_shards = header.data_shards as usize;
+    let parity_shards = header.parity_shards as usize;
+    let codec = ReedSolomon::new(data_shards, parity_shards).map_err(|e| anyhow!("Reed-Solomon: {:?}", e))?;
+
+    let mut input = BufReader::new(File::open(path).context("Cannot open the locked file")?);
+    let mut records = BufReader::new(File::open(&parity)?);
+    records.seek(SeekFrom::Start(HEADER_BLOCK_LEN as u64))?;
+
+    let mut report = RepairReport::default();
+    let mut hasher = Sha256::new();
+    let mut repairs: Vec<(u64, Vec<u8>)> = Vec::new();
+    let mut group_index: u64 = 0;
+    let group_count = header.group_count();
+
+    pipeline::run_ordered(
+        pipeline::resolve_threads(threads),
+        // Reader: a group of the locked file and its record
+        || {
+            if group_index == group_count {
+                return Ok(None);
+            }
+            let offset = group_index * header.group_span();
+            group_index += 1;
+            // Bytes past the original length (appended junk) are not part of any shard
+            let in_file = (header.file_len - offset).min(header.group_span()) as usize;
+            let data = read_padded(&mut input, offset, in_file, shard_size * data_shards)?;
+            let mut record = vec![0u8; header.group_record_len() as usize];
+            records.read_exact(&mut record)?;
+            Ok(Some((offset, data, record)))
+        },
+        // Workers: locate damaged shards and rebuild them
+        |_, (offset, data, record): (u64, Vec<u8>, Vec<u8>)| {
+            // Shards past the original End of File are zeros by construction, never damaged
+            let live_shards = (header.file_len.saturating_sub(offset)).div_ceil(shard_size as u64) as usize;
+            let mut shards: Vec<Option<Vec<u8>>> = Vec::with_capacity(data_shards + parity_shards);
+            let mut damaged = 0u64;
+            for (i, shard) in data.chunks(shard_size).enumerate() {
+                let expected = &record[i * DIGEST_LEN..(i + 1) * DIGEST_LEN];
+                if i < live_shards && digest(shard) != expected {
+                    damaged += 1;
+                    shards.push(None);
+                } else {
+                    shards.push(Some(shard.to_vec()));
+                }
+            }
+            let parity_start = data_shards * DIGEST_LEN;
+            for entry in record[parity_start..].chunks(DIGEST_LEN + shard_size) {
+                let (expected, shard) = entry.split_at(DIGEST_LEN);
+                // A damaged parity shard is simply not used
+                shards.push((digest(shard) == expected).then(|| shard.to_vec()));
+            }
+
+            if damaged == 0 {
+                return Ok((offset, damaged, Some(data), false));
+            }
+            if codec.reconstruct_data(&mut shards).is_err() {
+                return Ok((offset, damaged, None, false));
+            }
+            let rebuilt: Vec<u8> = shards.into_iter().take(data_shards).flatten().flatten().collect();
+            Ok((offset, damaged, Some(rebuilt), true))
+        },
+        // Writer: hash the (repaired) file in order, keep the rebuilt groups aside
+        |_, (offset, damaged, data, rebuilt): (u64, u64, Option<Vec<u8>>, bool)| {
+            report.damaged_shards += damaged;
+            match data {
+                Some(data) => {
+                    let len = (header.file_len - offset).min(data.len() as u64) as usize;
+                    hasher.update(&data[..len]);
+                    if rebuilt {
+                        report.repaired_shards += damaged;
+                        repairs.push((offset, data[..len].to_vec()));
+                    }
+                }
+                None => report.unrecoverable_groups += 1,
+            }
+            Ok(())
+        },
+    )?;
+    drop(input);
+
+    let file_len = std::fs::metadata(path)?.len();
+    if report.damaged_shards == 0 && file_len == header.file_len {
+        return Ok(report);
+    }
+    if report.unrecoverable_groups > 0 {
+        report.repaired_shards = 0;
+        return Ok(report);
+    }
+    if hasher.finalize().as_slice() != header.file_hash {
+        return Err(anyhow!("Repair failed: the recovery records do not match this file (out of date?). Nothing was written."));
+    }
+
+    // Write the rebuilt groups back, then drop anything appended after the original end
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    for (offset, data) in &repairs {
+        file.seek(SeekFrom::Start(*offset))?;
+        file.write_all(data)?;
+    }
+    file.set_len(header.file_len)?;
+    file.sync_all()?;
+    Ok(report)
+}
@@ -0,0 +1,102 @@
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+
+/// Resolves a requested thread count. `0` means "one worker per CPU core".
+pub fn resolve_threads(requested: usize) -> usize {
+    if requested > 0 {
+        return requested;
+    }
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Runs an ordered Reader -> Workers -> Writer pipeline.
+///
+/// The calling thread pulls jobs from `produce` and hands results to `consume`
+/// strictly in the order the jobs were produced. `work` runs on `threads` worker
+/// threads in between. At most `2 * threads` jobs are in flight at any time, so
+/// memory use stays constant no matter how large the input is.
+///
+/// Because `produce` and `consume` stay on the calling thread, the input/output
+/// streams and progress callbacks do not need to be `Send`.
+/// The first error (in job order) stops the pipeline and is returned.
+pub fn run_ordered<J, R>(
+    threads: usize,
+    mut produce: impl FnMut() -> Result<Option<J>>,
+    work: impl Fn(u64, J) -> Result<R> + Sync,
+    mut consume: impl FnMut(u64, R) -> Result<()>,
+) -> Result<()>
+where
+    J: Send,
+    R: Send,
+{
+    let threads = threads.max(1);
+    let max_in_flight = threads * 2;
+
+    let (job_tx, job_rx) = mpsc::sync_channel::<(u64, J)>(max_in_flight);
+    let (done_tx, done_rx) = mpsc::channel::<(u64, Result<R>)>();
+    let job_rx = Mutex::new(job_rx);
+
+    thread::scope(|scope| {
+        // Owned by this closure, so returning early (on error) disconnects the workers.
+        let job_tx = job_tx;
+        let done_rx = done_rx;
+
+        // 1. Workers
+        for _ in 0..threads {
+            let job_rx = &job_rx;
+            let done_tx = done_tx.clone();
+            let work = &work;
+            scope.spawn(move || loop {
+                // The lock is only held while waiting for the next job.
+                let job = job_rx.lock().unwrap().recv();
+                match job {
+                    Ok((index, input)) => {
+                        if done_tx.send((index, work(index, input))).is_err() {
+                            break; // The consumer gave up
+                        }
+                    }
+                    Err(_) => break, // No more jobs
+                }
+            });
+        }
+        drop(done_tx);
+
+        // 2. Reorders results and hands them to `consume` in job order.
+        let mut pending: BTreeMap<u64, Result<R>> = BTreeMap::new();
+        let mut next_index: u64 = 0;
+        let mut collect_one = |pending: &mut BTreeMap<u64, Result<R>>, next_index: &mut u64| -> Result<()> {
+            let (index, result) = done_rx
+                .recv()
+                .map_err(|_| anyhow!("Worker thread stopped unexpectedly"))?;
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(next_index) {
+                consume(*next_index, result?)?;
+                *next_index += 1;
+            }
+            Ok(())
+        };
+
+        // 3. Feed the workers, never letting more than `max_in_flight` jobs pile up.
+        let mut submitted: u64 = 0;
+        while let Some(job) = produce()? {
+            job_tx
+                .send((submitted, job))
+                .map_err(|_| anyhow!("Worker thread stopped unexpectedly"))?;
+            submitted += 1;
+
+            while submitted - next_index >= max_in_flight as u64 {
+                collect_one(&mut pending, &mut next_index)?;
+            }
+        }
+        drop(job_tx);
+
+        // 4. Drain whatever is still being processed
+        while next_index < submitted {
+            collect_one(&mut pending, &mut next_index)?;
+        }
+        Ok(())
+    })
+}
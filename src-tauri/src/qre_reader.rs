@@ -0,0 +1,167 @@
+use crate::crypto_stream::{self, FileMetadata, StreamKeys, UnlockKeys, CHUNK_SIZE};
+use crate::signing;
+use crate::volumes::{self, VolumeSet};
+use anyhow::{anyhow, Result};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Random-access view of the plaintext inside a V6 (.qre) file.
+///
+/// Every chunk is an independent AEAD unit holding exactly 1MB of plaintext
+/// (only the last one is shorter), so any byte offset maps to a single chunk.
+/// On open, the file is scanned once to record where each chunk starts; reads
+/// then decrypt only the chunks they touch. This lets us read the tail of a log
+/// or a slice of a 50GB video without decrypting everything.
+///
+/// Each chunk is still authenticated before use, and the Trailer is checked on
+/// open so a truncated or extended file is rejected. The whole-file hash and the
+/// signature (signed files) are only verified by a full `decrypt_file_stream`.
+/// A split file is opened from any of its volumes and read across the whole set.
+pub struct QreReader {
+    file: BufReader<Box<dyn Source>>,
+    keys: StreamKeys,
+    // File offset of each data chunk's ciphertext, and its length.
+    chunks: Vec<(u64, usize)>,
+    total_size: u64,
+    position: u64,
+    // The most recently decrypted chunk, so sequential reads don't re-decrypt it.
+    cached: Option<(u64, Vec<u8>)>,
+}
+
+/// What the chunks are read from: a file, or the volumes of a split file.
+trait Source: Read + Seek {}
+
+impl<T: Read + Seek> Source for T {}
+
+impl QreReader {
+    /// Opens a V6 file, unlocks it and builds the chunk offset index.
+    pub fn open(path: &Path, unlock_keys: &UnlockKeys) -> Result<Self> {
+        let volumes = if volumes::is_volume(path)? { Some(VolumeSet::open(path)?) } else { None };
+        let source: Box<dyn Source> = match &volumes {
+            Some(set) => Box::new(set.reader()),
+            None => Box::new(File::open(path)?),
+        };
+        let mut file = BufReader::new(source);
+
+        // 1. Header & Keys
+        let raw_header = crypto_stream::read_stream_header(&mut file)?;
+        if !raw_header.is_authenticated() {
+            return Err(anyhow!("Random access needs a V6 file. Re-lock this file to upgrade it."));
+        }
+        let keys = crypto_stream::unlock_stream_header(&raw_header, unlock_keys)?;
+        crypto_stream::check_volumes(volumes.as_ref(), &keys)?;
+        if keys.is_stream_compressed() {
+            return Err(anyhow!("Random access is not available for files locked with stream-wide compression."));
+        }
+
+        // 2. Scan the size prefixes to build the index
+        let mut chunks = Vec::new();
+        let mut offset = file.stream_position()?;
+        while let Some(len) = crypto_stream::read_chunk_len(&mut file)? {
+            offset += 4;
+            chunks.push((offset, len));
+            offset += len as u64;
+            file.seek(SeekFrom::Start(offset))?;
+        }
+
+        // Signed files end with the signature record. It is only checked by a full decryption.
+        if keys.signer.is_some() && !matches!(chunks.pop(), Some((_, len)) if len == signing::SIGNATURE_LEN) {
+            return Err(anyhow!("File is truncated: the signature is missing."));
+        }
+
+        // 3. The last record is the Trailer. It must describe exactly the chunks we found.
+        let (trailer_offset, trailer_len) = chunks
+            .pop()
+            .ok_or_else(|| anyhow!("File is truncated: the final chunk is missing."))?;
+        let chunk_count = chunks.len() as u64;
+
+        let mut sealed = vec![0u8; trailer_len];
+        file.seek(SeekFrom::Start(trailer_offset))?;
+        file.read_exact(&mut sealed)?;
+        let trailer = keys.open_trailer(chunk_count, &sealed)?;
+
+        // Data chunks come first. Padded files may end with padding-only records.
+        let data_chunks = trailer.total_size.div_ceil(CHUNK_SIZE as u64);
+        if trailer.chunk_count != chunk_count
+            || data_chunks > chunk_count
+            || (data_chunks < chunk_count && !keys.is_padded())
+        {
+            return Err(anyhow!("Chunk index does not match the trailer (corrupt file?)"));
+        }
+        chunks.truncate(data_chunks as usize);
+
+        Ok(Self {
+            file,
+            keys,
+            chunks,
+            total_size: trailer.total_size,
+            position: 0,
+            cached: None,
+        })
+    }
+
+    /// Size of the original plaintext in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// The sealed metadata (filename, timestamps...) of the locked file.
+    pub fn metadata(&self) -> &FileMetadata {
+        &self.keys.metadata
+    }
+
+    /// Returns the plaintext of chunk `index`, decrypting it if it is not cached.
+    fn load_chunk(&mut self, index: u64) -> io::Result<&[u8]> {
+        if self.cached.as_ref().map(|(i, _)| *i) != Some(index) {
+            let (offset, len) = self.chunks[index as usize];
+            let mut ciphertext = vec![0u8; len];
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut ciphertext)?;
+
+            let plaintext = self
+                .keys
+                .open_data_chunk(index, &ciphertext)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            self.cached = Some((index, plaintext));
+        }
+        Ok(&self.cached.as_ref().unwrap().1)
+    }
+}
+
+impl Read for QreReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.total_size {
+            return Ok(0);
+        }
+
+        let index = self.position / CHUNK_SIZE as u64;
+        let within = (self.position % CHUNK_SIZE as u64) as usize;
+        let chunk = self.load_chunk(index)?;
+        if within >= chunk.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Chunk shorter than expected"));
+        }
+
+        let count = buf.len().min(chunk.len() - within);
+        buf[..count].copy_from_slice(&chunk[within..within + count]);
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl Seek for QreReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(delta) => self.total_size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        match target {
+            Some(p) => {
+                self.position = p;
+                Ok(p)
+            }
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of file")),
+        }
+    }
+}
@@ -0,0 +1,472 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit},
+    Aes256Gcm, Nonce,
+};
+use crate::crypto_stream;
+use crate::keychain::{self, MasterKey};
+use crate::utils;
+use crate::volumes;
+use anyhow::{anyhow, Context, Result};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zeroize::Zeroize;
+
+// --- CONSTANTS ---
+
+// Every store sealed with the Master Key, next to `keychain.json`.
+const VAULT_STORES: [&str; 6] =
+    ["passwords.qre", "notes.qre", "bookmarks.qre", "clipboard.qre", "contacts.qre", "signers.qre"];
+
+const KEYCHAIN_FILE: &str = "keychain.json";
+const JOURNAL_FILE: &str = "rotation.journal";
+
+// Suffix of the re-encrypted copy of a vault file, waiting to replace the original.
+const STAGED_SUFFIX: &str = ".rotating";
+
+// Rewrap attempts (one per login) before a locked file stops being retried on its own.
+// It then waits for the user (see `retry_failed` and `forget_old_master_key`).
+const MAX_REWRAP_ATTEMPTS: u32 = 3;
+
+// One rotation or rewrap at a time: the login resumes rewraps in the background.
+// Other writes to the keychain take it too, so a rotation never stages a stale copy.
+pub(crate) static JOURNAL_LOCK: Mutex<()> = Mutex::new(());
+
+// --- JOURNAL ---
+
+/// How far a rotation got. Written (atomically) before each phase starts.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum RotationStage {
+    // Staged copies are being written. The originals still use the old key:
+    // an interrupted rotation is rolled back.
+    Staging,
+    // Every staged copy is complete and flushed: an interrupted rotation is rolled forward.
+    Committing,
+    // The vault uses the new key. Locked files are still being rewrapped.
+    Rewrapping,
+}
+
+/// `rotation.journal`, next to `keychain.json`. Present only while a rotation is unfinished.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct RotationJournal {
+    pub stage: RotationStage,
+    // Vault files (names relative to the vault folder) replaced by their staged copy.
+    pub vault_files: Vec<String>,
+    // Locked files still waiting for their new Master Key slot.
+    pub pending_files: Vec<String>,
+    // Failed rewrap attempts of pending files, so they are not retried forever.
+    #[serde(default)]
+    pub attempts: BTreeMap<String, u32>,
+    // Locked files whose rewrap failed for good. They still need the old Master Key,
+    // so the journal is kept until the user retries them or gives them up.
+    #[serde(default)]
+    pub failed_files: Vec<String>,
+    // The old Master Key (+ Keyfile) sealed with the NEW Master Key, so the rewrap
+    // can resume after the next login. Wiped with the journal.
+    pub secrets_nonce: Vec<u8>,
+    pub encrypted_secrets: Vec<u8>,
+}
+
+/// What the journal keeps sealed.
+#[derive(Serialize, Deserialize)]
+struct RotationSecrets {
+    old_master_key: [u8; 32],
+    keyfile_bytes: Option<Vec<u8>>,
+}
+
+impl Drop for RotationSecrets {
+    fn drop(&mut self) {
+        self.old_master_key.zeroize();
+        self.keyfile_bytes.zeroize();
+    }
+}
+
+/// Result of one locked file's rewrap.
+#[derive(Debug, Clone)]
+pub struct RewrapOutcome {
+    pub path: String,
+    pub success: bool,
+    pub message: String,
+}
+
+/// Result of the vault part of a rotation.
+pub struct RotationOutcome {
+    pub new_master_key: MasterKey,
+    // The old Recovery Code opens the old Master Key, so a new one is issued.
+    pub recovery_code: String,
+}
+
+fn journal_path(vault_dir: &Path) -> PathBuf {
+    vault_dir.join(JOURNAL_FILE)
+}
+
+fn staged_path(vault_dir: &Path, name: &str) -> PathBuf {
+    vault_dir.join(format!("{}{}", name, STAGED_SUFFIX))
+}
+
+pub(crate) fn write_journal(vault_dir: &Path, journal: &RotationJournal) -> Result<()> {
+    let path = journal_path(vault_dir);
+    let output = utils::AtomicFile::create(&path)?;
+    serde_json::to_writer_pretty(output.file(), journal)?;
+    output.commit(&path)?;
+    Ok(())
+}
+
+pub(crate) fn read_journal(vault_dir: &Path) -> Result<Option<RotationJournal>> {
+    let path = journal_path(vault_dir);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let file = fs::File::open(&path)?;
+    let journal = serde_json::from_reader(file).context("Corrupted rotation journal")?;
+    Ok(Some(journal))
+}
+
+fn remove_journal(vault_dir: &Path) -> Result<()> {
+    utils::wipe_and_remove(&journal_path(vault_dir))?;
+    Ok(())
+}
+
+fn seal_secrets(new_master_key: &MasterKey, secrets: &RotationSecrets) -> Result<(Vec<u8>, Vec<u8>)> {
+    let cipher = Aes256Gcm::new_from_slice(&new_master_key.0).unwrap();
+    let mut nonce_bytes = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce_bytes);
+
+    let mut plain = bincode::serialize(secrets)?;
+    let encrypted = cipher
+        .encrypt(Nonce::from_slice(&nonce_bytes), plain.as_ref())
+        .map_err(|_| anyhow!("Failed to seal the rotation journal"));
+    plain.zeroize();
+    Ok((nonce_bytes.to_vec(), encrypted?))
+}
+
+fn open_secrets(new_master_key: &MasterKey, journal: &RotationJournal) -> Result<RotationSecrets> {
+    let cipher = Aes256Gcm::new_from_slice(&new_master_key.0).unwrap();
+    let mut plain = cipher
+        .decrypt(Nonce::from_slice(&journal.secrets_nonce), journal.encrypted_secrets.as_ref())
+        .map_err(|_| anyhow!("The rotation journal does not belong to this Master Key"))?;
+    let secrets = bincode::deserialize(&plain);
+    plain.zeroize();
+    Ok(secrets?)
+}
+
+// --- ROTATION ---
+
+/// Replaces the Master Key of the vault in `vault_dir` (Master Key rotation).
+///
+/// 1. Writes the journal (stage `Staging`).
+/// 2. Re-encrypts every vault store and the keychain slots into staged copies.
+/// 3. Switches the journal to `Committing`, then renames each staged copy over its original.
+///
+/// The locked files of `files` are checked first: if one cannot be moved to the new key
+/// (damaged, wrong Keyfile, locked with another vault...), nothing is rotated. They are then
+/// left in the journal (stage `Rewrapping`): call `resume_rewrap` with the new key to give
+/// each one a new Master Key slot. That part is slow (every file is rewritten), so it can
+/// run once the new key is in use.
+///
+/// An interruption never leaves a half-rotated vault: `recover_interrupted` rolls back
+/// (stage `Staging`) or forward (later stages) before the next login, and
+/// `resume_rewrap` finishes the locked files after it.
+pub fn rotate_vault(
+    vault_dir: &Path,
+    password: &str,
+    old_master_key: &MasterKey,
+    files: &[PathBuf],
+    keyfile_bytes: Option<&[u8]>,
+) -> Result<RotationOutcome> {
+    let _journal_lock = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    // Locked files left by the previous rotation get one more attempt first
+    resume_rewrap_locked(vault_dir, old_master_key)?;
+    if let Some(journal) = read_journal(vault_dir)? {
+        if !journal.failed_files.is_empty() && journal.pending_files.is_empty() {
+            return Err(anyhow!(
+                "{} locked files from the previous rotation still need the old Master Key. \
+                 Retry them, or forget the old key, before rotating again.",
+                journal.failed_files.len()
+            ));
+        }
+        return Err(anyhow!("A previous Master Key rotation is unfinished. Log in again to complete it."));
+    }
+    let files = check_rotatable(files, old_master_key, keyfile_bytes)?;
+
+    let mut mk_bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut mk_bytes);
+    let new_master_key = MasterKey(mk_bytes);
+    mk_bytes.zeroize();
+
+    // 1. Journal
+    let vault_files: Vec<String> = VAULT_STORES
+        .iter()
+        .filter(|name| vault_dir.join(name).exists())
+        .map(|name| name.to_string())
+        .chain(std::iter::once(KEYCHAIN_FILE.to_string()))
+        .collect();
+    let secrets = RotationSecrets { old_master_key: old_master_key.0, keyfile_bytes: keyfile_bytes.map(|kb| kb.to_vec()) };
+    let (secrets_nonce, encrypted_secrets) = seal_secrets(&new_master_key, &secrets)?;
+    drop(secrets);
+
+    let mut journal = RotationJournal {
+        stage: RotationStage::Staging,
+        vault_files,
+        pending_files: files.iter().map(|path| path.to_string_lossy().to_string()).collect(),
+        attempts: BTreeMap::new(),
+        failed_files: Vec::new(),
+        secrets_nonce,
+        encrypted_secrets,
+    };
+    write_journal(vault_dir, &journal)?;
+
+    // 2. Staged copies. Any failure here rolls the rotation back.
+    let staged = stage_vault_files(vault_dir, password, old_master_key, &new_master_key, &journal.vault_files);
+    let recovery_code = match staged {
+        Ok(code) => code,
+        Err(e) => {
+            roll_back(vault_dir, &journal)?;
+            return Err(e);
+        }
+    };
+
+    // 3. Commit
+    journal.stage = RotationStage::Committing;
+    write_journal(vault_dir, &journal)?;
+    roll_forward(vault_dir, &mut journal)?;
+
+    Ok(RotationOutcome { new_master_key, recovery_code })
+}
+
+/// Checks every locked file before anything changes, so a rotation never commits
+/// with files it cannot move (they would need the old key). Each split file is
+/// kept once (by the first of its volumes listed). Returns the files to rewrap.
+fn check_rotatable(files: &[PathBuf], old_master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) -> Result<Vec<PathBuf>> {
+    let mut sets = HashSet::new();
+    let mut checked = Vec::new();
+    let mut problems = Vec::new();
+    for path in files {
+        if let Ok(set_id) = volumes::set_id(path) {
+            if !sets.insert(set_id) {
+                continue;
+            }
+        }
+        match crypto_stream::check_master_key_slot(path, old_master_key, keyfile_bytes) {
+            Ok(()) => checked.push(path.clone()),
+            Err(e) => problems.push(format!("{}: {:#}", path.display(), e)),
+        }
+    }
+    if !problems.is_empty() {
+        return Err(anyhow!(
+            "Nothing was changed. These locked files cannot be moved to a new Master Key \
+             (deselect them, or fix them first):\n{}",
+            problems.join("\n")
+        ));
+    }
+    Ok(checked)
+}
+
+/// Writes the staged copy of every vault file. Returns the new Recovery Code.
+fn stage_vault_files(
+    vault_dir: &Path,
+    password: &str,
+    old_master_key: &MasterKey,
+    new_master_key: &MasterKey,
+    vault_files: &[String],
+) -> Result<String> {
+    // Keychain first: it also checks the password
+    let recovery_code = keychain::rotate_keychain(
+        &vault_dir.join(KEYCHAIN_FILE),
+        password,
+        old_master_key,
+        new_master_key,
+        &staged_path(vault_dir, KEYCHAIN_FILE),
+    )?;
+
+    for name in vault_files.iter().filter(|name| name.as_str() != KEYCHAIN_FILE) {
+        let payload = crypto_stream::load_store(&vault_dir.join(name), old_master_key)
+            .with_context(|| format!("Failed to decrypt {}", name))?;
+        // Staged copies use the current format, whatever the original was
+        crypto_stream::save_store(&staged_path(vault_dir, name), new_master_key, &payload.filename, &payload.content)?;
+    }
+    Ok(recovery_code)
+}
+
+/// Deletes the staged copies and the journal. The vault keeps its old key.
+fn roll_back(vault_dir: &Path, journal: &RotationJournal) -> Result<()> {
+    for name in &journal.vault_files {
+        let staged = staged_path(vault_dir, name);
+        if staged.exists() {
+            fs::remove_file(staged)?;
+        }
+    }
+    remove_journal(vault_dir)
+}
+
+/// Moves every staged copy still present over its original.
+fn roll_forward(vault_dir: &Path, journal: &mut RotationJournal) -> Result<()> {
+    for name in &journal.vault_files {
+        let staged = staged_path(vault_dir, name);
+        if staged.exists() {
+            fs::rename(&staged, vault_dir.join(name))?;
+        }
+    }
+    #[cfg(unix)]
+    if let Ok(dir) = fs::File::open(vault_dir) {
+        let _ = dir.sync_all();
+    }
+
+    if journal.pending_files.is_empty() && journal.failed_files.is_empty() {
+        return remove_journal(vault_dir);
+    }
+    journal.stage = RotationStage::Rewrapping;
+    write_journal(vault_dir, journal)
+}
+
+/// True for failures a later attempt may get past (file in use, read-only, disk full...),
+/// false for files that will never rewrap (damaged, or not locked with this vault).
+fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|io| !matches!(io.kind(), ErrorKind::UnexpectedEof | ErrorKind::InvalidData))
+    })
+}
+
+/// Rewraps the pending locked files. Files failing for a transient reason are retried
+/// at the next login, up to `MAX_REWRAP_ATTEMPTS` times. Other failures, and files that
+/// ran out of attempts, move to `failed_files`: the journal (and the old key it holds)
+/// is kept for them until the user retries them or forgets the old key.
+fn rewrap_pending(
+    vault_dir: &Path,
+    journal: &mut RotationJournal,
+    old_master_key: &MasterKey,
+    new_master_key: &MasterKey,
+    keyfile_bytes: Option<&[u8]>,
+) -> Result<Vec<RewrapOutcome>> {
+    if journal.stage != RotationStage::Rewrapping {
+        return Ok(Vec::new());
+    }
+
+    let mut outcomes = Vec::new();
+    for path in journal.pending_files.clone() {
+        let (success, message, retry) = if !Path::new(&path).exists() {
+            (false, format!("File not found. {}", KEPT_OLD_KEY), false)
+        } else {
+            // V4 files have no slots to replace: they move to the stream format first
+            let rotated = crypto_stream::migrate_container(Path::new(&path), old_master_key, keyfile_bytes)
+                .and_then(|_| crypto_stream::rotate_master_key_stream(&path, old_master_key, new_master_key, keyfile_bytes));
+            match rotated {
+                Ok(true) => (true, "Rewrapped".to_string(), false),
+                Ok(false) => (true, "No Master Key slot (nothing to do)".to_string(), false),
+                Err(e) if is_transient(&e) => {
+                    let attempts = journal.attempts.entry(path.clone()).or_insert(0);
+                    *attempts += 1;
+                    if *attempts < MAX_REWRAP_ATTEMPTS {
+                        (false, format!("{:#} (will retry at next login)", e), true)
+                    } else {
+                        (false, format!("{:#} (failed {} times). {}", e, attempts, KEPT_OLD_KEY), false)
+                    }
+                }
+                Err(e) => (false, format!("{:#}. {}", e, KEPT_OLD_KEY), false),
+            }
+        };
+
+        // Progress is saved after each file
+        if !retry {
+            journal.pending_files.retain(|p| *p != path);
+            journal.attempts.remove(&path);
+            if !success {
+                journal.failed_files.push(path.clone());
+            }
+        }
+        write_journal(vault_dir, journal)?;
+        outcomes.push(RewrapOutcome { path, success, message });
+    }
+
+    if journal.pending_files.is_empty() && journal.failed_files.is_empty() {
+        remove_journal(vault_dir)?;
+    }
+    Ok(outcomes)
+}
+
+// Appended to the report of a file that failed for good.
+const KEPT_OLD_KEY: &str = "The old Master Key is kept for this file: retry it, or forget the old key to give it up.";
+
+// --- RECOVERY ---
+
+/// Finishes the vault part of an interrupted rotation. Call before unlocking the keychain:
+/// it decides which keychain (old or new) the password will open.
+pub fn recover_interrupted(vault_dir: &Path) -> Result<()> {
+    let Some(mut journal) = read_journal(vault_dir)? else {
+        return Ok(());
+    };
+    match journal.stage {
+        RotationStage::Staging => roll_back(vault_dir, &journal),
+        RotationStage::Committing => roll_forward(vault_dir, &mut journal),
+        RotationStage::Rewrapping => Ok(()),
+    }
+}
+
+/// Resumes the rewrap of locked files left by an interrupted rotation, once logged in
+/// with the new Master Key. Returns an empty list when nothing was pending.
+pub fn resume_rewrap(vault_dir: &Path, master_key: &MasterKey) -> Result<Vec<RewrapOutcome>> {
+    let _journal_lock = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    resume_rewrap_locked(vault_dir, master_key)
+}
+
+/// Gives the failed files of the last rotation another try (e.g., once their drive is back,
+/// or with the right Keyfile). `keyfile_bytes` replaces the Keyfile recorded at rotation time.
+pub fn retry_failed(vault_dir: &Path, master_key: &MasterKey, keyfile_bytes: Option<&[u8]>) -> Result<Vec<RewrapOutcome>> {
+    let _journal_lock = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(mut journal) = read_journal(vault_dir)? else {
+        return Ok(Vec::new());
+    };
+    if journal.stage != RotationStage::Rewrapping {
+        return Ok(Vec::new());
+    }
+
+    let mut secrets = open_secrets(master_key, &journal)?;
+    if let Some(keyfile_bytes) = keyfile_bytes {
+        secrets.keyfile_bytes = Some(keyfile_bytes.to_vec());
+        (journal.secrets_nonce, journal.encrypted_secrets) = seal_secrets(master_key, &secrets)?;
+    }
+    let failed = std::mem::take(&mut journal.failed_files);
+    journal.pending_files.extend(failed);
+    journal.attempts.clear();
+    write_journal(vault_dir, &journal)?;
+
+    let old_master_key = MasterKey(secrets.old_master_key);
+    rewrap_pending(vault_dir, &mut journal, &old_master_key, master_key, secrets.keyfile_bytes.as_deref())
+}
+
+/// Gives up the failed files of the last rotation, once the user has confirmed it:
+/// wipes the journal and the old Master Key with it. Those files can no longer be opened
+/// with this vault. Returns them, so they can be listed.
+pub fn forget_old_master_key(vault_dir: &Path, master_key: &MasterKey) -> Result<Vec<String>> {
+    let _journal_lock = JOURNAL_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(journal) = read_journal(vault_dir)? else {
+        return Ok(Vec::new());
+    };
+    if journal.stage != RotationStage::Rewrapping || !journal.pending_files.is_empty() {
+        return Err(anyhow!("Locked files are still being moved to the new Master Key. Log in again to finish first."));
+    }
+    // Only the owner of the new key can give the old one up
+    drop(open_secrets(master_key, &journal)?);
+    remove_journal(vault_dir)?;
+    Ok(journal.failed_files)
+}
+
+fn resume_rewrap_locked(vault_dir: &Path, master_key: &MasterKey) -> Result<Vec<RewrapOutcome>> {
+    let Some(mut journal) = read_journal(vault_dir)? else {
+        return Ok(Vec::new());
+    };
+    if journal.stage != RotationStage::Rewrapping {
+        return Ok(Vec::new());
+    }
+
+    let secrets = open_secrets(master_key, &journal)?;
+    let old_master_key = MasterKey(secrets.old_master_key);
+    rewrap_pending(vault_dir, &mut journal, &old_master_key, master_key, secrets.keyfile_bytes.as_deref())
+}
@@ -0,0 +1,195 @@
+use crate::crypto_stream;
+use crate::hybrid;
+use crate::keychain::MasterKey;
+use anyhow::{anyhow, Result};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+// --- CONSTANTS ---
+
+// Domain separation for signatures and signer fingerprints.
+const SIGNATURE_LABEL: &[u8] = b"QRE_SIGNATURE_V1";
+const FINGERPRINT_LABEL: &[u8] = b"QRE_SIGNER_V1";
+
+// An Ed25519 signature, stored as one last `[u32 length][signature]` record.
+pub const SIGNATURE_LEN: usize = 64;
+pub const SIGNATURE_RECORD_LEN: usize = 4 + SIGNATURE_LEN;
+
+// --- SIGNATURES ---
+
+/// Short human-readable fingerprint of a signer's public key (e.g., "3F2A-9C41-07BE-D215").
+pub fn signer_fingerprint(public_key: &[u8; 32]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(FINGERPRINT_LABEL);
+    hasher.update(public_key);
+    hybrid::format_fingerprint(&hasher.finalize().into())
+}
+
+/// Signs `digest` (see `StreamHeader::signature_hasher`) and returns the signature record.
+pub fn sign_record(signing_key: &SigningKey, digest: &[u8]) -> Vec<u8> {
+    let signature = signing_key.sign(&signed_message(digest));
+
+    let mut record = Vec::with_capacity(SIGNATURE_RECORD_LEN);
+    record.extend_from_slice(&(SIGNATURE_LEN as u32).to_le_bytes());
+    record.extend_from_slice(&signature.to_bytes());
+    record
+}
+
+/// Checks a signature record against `digest` and the signer named in the header.
+pub fn verify_record(record: &[u8], digest: &[u8], public_key: &[u8; 32]) -> Result<()> {
+    if record.len() != SIGNATURE_RECORD_LEN
+        || u32::from_le_bytes(record[..4].try_into().unwrap()) as usize != SIGNATURE_LEN
+    {
+        return Err(anyhow!("File is truncated: the signature is missing."));
+    }
+
+    let verifying_key = VerifyingKey::from_bytes(public_key).map_err(|_| anyhow!("Invalid signer public key"))?;
+    let signature = Signature::from_slice(&record[4..]).map_err(|_| anyhow!("Invalid signature encoding"))?;
+    verifying_key
+        .verify(&signed_message(digest), &signature)
+        .map_err(|_| anyhow!("SIGNATURE INVALID: The file was modified after it was signed."))
+}
+
+fn signed_message(digest: &[u8]) -> Vec<u8> {
+    [SIGNATURE_LABEL, digest].concat()
+}
+
+// --- STREAM ADAPTERS ---
+
+/// Passes writes through, adding them to `hasher` when set (signed files only).
+pub(crate) struct HashingWriter<W: Write> {
+    inner: W,
+    hasher: Option<Sha256>,
+}
+
+impl<W: Write> HashingWriter<W> {
+    pub fn new(inner: W, hasher: Option<Sha256>) -> Self {
+        Self { inner, hasher }
+    }
+
+    /// Gives back the writer and the final digest (`None` when not hashing).
+    pub fn finish(self) -> (W, Option<Vec<u8>>) {
+        (self.inner, self.hasher.map(|h| h.finalize().to_vec()))
+    }
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if let Some(hasher) = &mut self.hasher {
+            hasher.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Hides the last `tail_len` bytes of a stream from its reader, and hashes what it releases.
+/// Lets the chunk decoder see End of File right after the Trailer of a signed stream
+/// without knowing the stream length; the held-back bytes are the signature record.
+pub(crate) struct TailReader<R: Read> {
+    inner: R,
+    held: Vec<u8>,
+    tail_len: usize,
+    hasher: Sha256,
+    eof: bool,
+}
+
+impl<R: Read> TailReader<R> {
+    /// `hasher` already holds the signed part of the header.
+    pub fn new(inner: R, tail_len: usize, hasher: Sha256) -> Self {
+        Self { inner, held: Vec::new(), tail_len, hasher, eof: false }
+    }
+
+    /// Reads whatever is left, then returns the digest of the released bytes and the tail.
+    /// The tail is shorter than `tail_len` if the stream was cut short.
+    pub fn finish(mut self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        self.inner.read_to_end(&mut self.held)?;
+        // Bytes beyond the tail were never seen by the reader: they belong to no record
+        if self.held.len() > self.tail_len {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Unexpected data after the final chunk"));
+        }
+        Ok((self.hasher.finalize().to_vec(), self.held))
+    }
+}
+
+impl<R: Read> Read for TailReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.held.len() > self.tail_len {
+                let n = (self.held.len() - self.tail_len).min(buf.len());
+                buf[..n].copy_from_slice(&self.held[..n]);
+                self.hasher.update(&buf[..n]);
+                self.held.drain(..n);
+                return Ok(n);
+            }
+            if self.eof {
+                return Ok(0);
+            }
+
+            let mut chunk = [0u8; 64 * 1024];
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                self.eof = true;
+            }
+            self.held.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+// --- TRUSTED SIGNERS ---
+
+/// A signer the user chose to trust, usually added from a file they received.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrustedSigner {
+    pub id: String,
+    pub name: String,
+    pub fingerprint: String,
+    pub public_key: [u8; 32],
+    pub created_at: i64,
+}
+
+/// Stored sealed in `signers.qre`, next to the keychain.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct TrustedSigners {
+    pub entries: Vec<TrustedSigner>,
+}
+
+impl TrustedSigners {
+    pub fn new() -> Self {
+        Self { entries: Vec::new() }
+    }
+
+    /// The list used to name the signer of a file in a report. Trust never decides whether
+    /// a file opens, so a missing, damaged or unreadable `signers.qre` counts as empty.
+    pub fn load_for_report(path: &Path, master_key: &MasterKey) -> Self {
+        crypto_stream::load_store(path, master_key)
+            .ok()
+            .and_then(|payload| serde_json::from_slice(&payload.content).ok())
+            .unwrap_or_default()
+    }
+
+    /// Adds a signer, or renames it if the same key was already trusted.
+    pub fn upsert(&mut self, name: String, public_key: [u8; 32]) -> TrustedSigner {
+        if let Some(existing) = self.entries.iter_mut().find(|s| s.public_key == public_key) {
+            existing.name = name;
+            return existing.clone();
+        }
+
+        let signer = TrustedSigner {
+            id: uuid::Uuid::new_v4().to_string(),
+            name,
+            fingerprint: signer_fingerprint(&public_key),
+            public_key,
+            created_at: chrono::Utc::now().timestamp_millis(),
+        };
+        self.entries.push(signer.clone());
+        signer
+    }
+}
@@ -1,10 +1,25 @@
 #[cfg(test)]
 mod tests {
+    use crate::archive::SymlinkPolicy;
+    use crate::cipher::CipherSuite;
+    use crate::crypto;
     use crate::crypto_stream;
+    use crate::fuzzing;
+    use crate::inspect;
     use crate::keychain;
+    use crate::parity;
+    use crate::qre_reader::QreReader;
+    use crate::rotation;
+    use crate::signing;
+    use crate::volumes;
     use std::fs;
-    use std::io::Write;
+    use std::io::{Read, Seek, SeekFrom, Write};
     
+    /// Master Key only: no Keyfile, no identity.
+    fn vault_keys(mk: &keychain::MasterKey) -> crypto_stream::UnlockKeys<'_> {
+        crypto_stream::UnlockKeys { master_key: mk, keyfile_bytes: None, identity: None, passphrase: None }
+    }
+
     #[test]
     fn test_streaming_roundtrip() {
         // 1. Setup Temporary Paths
@@ -37,7 +52,7 @@ mod tests {
             &mk,
             None, // No keyfile
             None, // No extra entropy
-            1,    // <--- ADDED: Compression Level (1 = Fast)
+            &crypto_stream::StreamOptions { compression_level: 1, threads: 2, ..Default::default() },
             progress_cb
         ).expect("Encryption failed");
 
@@ -45,10 +60,10 @@ mod tests {
         let result_filename = crypto_stream::decrypt_file_stream(
             encrypted_path.to_str().unwrap(),
             output_dir.to_str().unwrap(),
-            &mk,
-            None, // No keyfile
+            &vault_keys(&mk), // No keyfile
+            0,    // One thread per core
             progress_cb
-        ).expect("Decryption failed");
+        ).expect("Decryption failed").filename;
 
         // 6. Verify Content
         let final_path = output_dir.join(result_filename);
@@ -59,4 +74,1526 @@ mod tests {
         // 7. Cleanup
         let _ = fs::remove_dir_all(test_dir);
     }
-}
\ No newline at end of file
+
+    // --- V6 Tamper Detection ---
+
+    /// Encrypts ~2.5MB (three chunks) into a fresh test directory and returns
+    /// the directory, the encrypted file path and the original data.
+    fn setup_multi_chunk(name: &str) -> (std::path::PathBuf, std::path::PathBuf, Vec<u8>) {
+        let test_dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+
+        let input_path = test_dir.join("big.bin");
+        let encrypted_path = test_dir.join("big.bin.qre");
+        let original_data: Vec<u8> = (0..2_500_000u32).map(|i| (i % 251) as u8).collect();
+        fs::write(&input_path, &original_data).unwrap();
+
+        let mk = keychain::MasterKey([7u8; 32]);
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions { compression_level: 1, threads: 0, ..Default::default() },
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        (test_dir, encrypted_path, original_data)
+    }
+
+    /// Returns the byte range of every chunk record (size prefix included) in a V6 file.
+    fn chunk_records(data: &[u8]) -> Vec<std::ops::Range<usize>> {
+        let header_len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+        let mut pos = 8 + header_len + 32;
+        let mut records = Vec::new();
+        while pos < data.len() {
+            let len = u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            records.push(pos..pos + 4 + len);
+            pos += 4 + len;
+        }
+        records
+    }
+
+    /// Incompressible bytes (xorshift), the same for a given seed.
+    fn pseudo_random(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn try_decrypt(encrypted_path: &std::path::Path, output_dir: &std::path::Path) -> anyhow::Result<String> {
+        crypto_stream::decrypt_file_stream(
+            encrypted_path.to_str().unwrap(),
+            output_dir.to_str().unwrap(),
+            &vault_keys(&keychain::MasterKey([7u8; 32])),
+            0,
+            |_, _| {},
+        )
+        .map(|unlocked| unlocked.filename)
+    }
+
+    #[test]
+    fn test_stream_detects_truncation() {
+        let (test_dir, encrypted_path, _) = setup_multi_chunk("qre_tests_truncation");
+        let data = fs::read(&encrypted_path).unwrap();
+        let records = chunk_records(&data);
+        assert_eq!(records.len(), 4); // 3 data chunks + trailer
+
+        // Chop off the trailer
+        fs::write(&encrypted_path, &data[..records[3].start]).unwrap();
+        let err = try_decrypt(&encrypted_path, &test_dir.join("output")).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+
+        // Chop off the trailer and the last data chunk
+        fs::write(&encrypted_path, &data[..records[2].start]).unwrap();
+        let err = try_decrypt(&encrypted_path, &test_dir.join("output")).unwrap_err();
+        assert!(err.to_string().contains("truncated"), "{}", err);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_stream_detects_reordered_chunks() {
+        let (test_dir, encrypted_path, _) = setup_multi_chunk("qre_tests_reorder");
+        let data = fs::read(&encrypted_path).unwrap();
+        let records = chunk_records(&data);
+
+        // Swap the first two chunks
+        let mut swapped = data[..records[0].start].to_vec();
+        swapped.extend_from_slice(&data[records[1].clone()]);
+        swapped.extend_from_slice(&data[records[0].clone()]);
+        swapped.extend_from_slice(&data[records[2].start..]);
+        fs::write(&encrypted_path, &swapped).unwrap();
+
+        let err = try_decrypt(&encrypted_path, &test_dir.join("output")).unwrap_err();
+        assert!(err.to_string().contains("Chunk 0"), "{}", err);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_stream_detects_appended_data_and_header_tampering() {
+        let (test_dir, encrypted_path, original_data) = setup_multi_chunk("qre_tests_append");
+        let data = fs::read(&encrypted_path).unwrap();
+
+        // Intact file decrypts fine
+        let name = try_decrypt(&encrypted_path, &test_dir.join("output")).unwrap();
+        assert_eq!(fs::read(test_dir.join("output").join(name)).unwrap(), original_data);
+
+        // Appending a copy of the trailer must be rejected
+        let records = chunk_records(&data);
+        let mut appended = data.clone();
+        appended.extend_from_slice(&data[records[3].clone()]);
+        fs::write(&encrypted_path, &appended).unwrap();
+        let err = try_decrypt(&encrypted_path, &test_dir.join("output")).unwrap_err();
+        assert!(err.to_string().contains("after the final chunk"), "{}", err);
+
+        // A header that no longer matches its MAC must be rejected
+        let mut tampered = data.clone();
+        let mac_end = 8 + u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize + 32;
+        tampered[mac_end - 1] ^= 0x01;
+        fs::write(&encrypted_path, &tampered).unwrap();
+        let err = try_decrypt(&encrypted_path, &test_dir.join("output")).unwrap_err();
+        assert!(err.to_string().contains("Header authentication failed"), "{}", err);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_stream_hides_and_restores_metadata() {
+        let test_dir = std::env::temp_dir().join("qre_tests_metadata");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+
+        let input_path = test_dir.join("tax_return_2025.pdf");
+        let encrypted_path = test_dir.join("locked.qre");
+        fs::write(&input_path, b"%PDF-1.7 very private numbers").unwrap();
+
+        // Give the file a recognisable modification time
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        fs::File::options().write(true).open(&input_path).unwrap().set_modified(mtime).unwrap();
+
+        let mk = keychain::MasterKey([9u8; 32]);
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions::default(),
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        // The filename must not appear anywhere in the locked file
+        let locked = fs::read(&encrypted_path).unwrap();
+        assert!(!locked.windows(10).any(|w| w == b"tax_return"));
+
+        let name = crypto_stream::decrypt_file_stream(
+            encrypted_path.to_str().unwrap(),
+            test_dir.join("output").to_str().unwrap(),
+            &vault_keys(&mk),
+            1,
+            |_, _| {},
+        )
+        .expect("Decryption failed")
+        .filename;
+
+        assert_eq!(name, "tax_return_2025.pdf");
+        let restored = fs::metadata(test_dir.join("output").join(&name)).unwrap();
+        assert_eq!(restored.modified().unwrap(), mtime);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_qre_reader_random_access() {
+        let (test_dir, encrypted_path, original_data) = setup_multi_chunk("qre_tests_reader");
+        let mk = keychain::MasterKey([7u8; 32]);
+        let mut reader = QreReader::open(&encrypted_path, &vault_keys(&mk)).expect("Open failed");
+        assert_eq!(reader.total_size(), original_data.len() as u64);
+
+        // A range spanning the boundary between chunk 0 and chunk 1
+        let start = 1024 * 1024 - 100;
+        reader.seek(SeekFrom::Start(start as u64)).unwrap();
+        let mut buf = vec![0u8; 300];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, original_data[start..start + 300]);
+
+        // The tail of the file
+        reader.seek(SeekFrom::End(-50)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, original_data[original_data.len() - 50..]);
+
+        // A file with its trailer cut off is refused
+        let data = fs::read(&encrypted_path).unwrap();
+        let records = chunk_records(&data);
+        fs::write(&encrypted_path, &data[..records[3].start]).unwrap();
+        assert!(QreReader::open(&encrypted_path, &vault_keys(&mk)).is_err());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_parallel_output_matches_single_thread() {
+        let test_dir = std::env::temp_dir().join("qre_tests_parallel");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+
+        let input_path = test_dir.join("data.bin");
+        let data: Vec<u8> = (0..5_300_000u32).map(|i| (i % 97) as u8).collect();
+        fs::write(&input_path, &data).unwrap();
+
+        // A fixed entropy seed makes the header deterministic, so the files must match byte for byte
+        let mk = keychain::MasterKey([3u8; 32]);
+        let encrypt_with = |threads: usize, name: &str| {
+            let out = test_dir.join(name);
+            crypto_stream::encrypt_file_stream(
+                input_path.to_str().unwrap(),
+                out.to_str().unwrap(),
+                &mk,
+                None,
+                Some([5u8; 32]),
+                &crypto_stream::StreamOptions { compression_level: 3, threads, ..Default::default() },
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+            fs::read(out).unwrap()
+        };
+
+        let single = encrypt_with(1, "single.qre");
+        let parallel = encrypt_with(8, "parallel.qre");
+        assert_eq!(single, parallel);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_cipher_suites_roundtrip() {
+        let test_dir = std::env::temp_dir().join("qre_tests_suites");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+
+        let input_path = test_dir.join("notes.txt");
+        let data: Vec<u8> = (0..1_500_000u32).map(|i| (i % 13) as u8).collect();
+        fs::write(&input_path, &data).unwrap();
+        let mk = keychain::MasterKey([9u8; 32]);
+
+        for (label, suite) in [("aes", CipherSuite::Aes256Gcm), ("xchacha", CipherSuite::XChaCha20Poly1305)] {
+            let encrypted_path = test_dir.join(format!("{}.qre", label));
+            let output_dir = test_dir.join(label);
+            fs::create_dir_all(&output_dir).unwrap();
+
+            crypto_stream::encrypt_file_stream(
+                input_path.to_str().unwrap(),
+                encrypted_path.to_str().unwrap(),
+                &mk,
+                None,
+                None,
+                &crypto_stream::StreamOptions { cipher_suite: suite, ..Default::default() },
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+
+            // The suite is recorded in the header and decides the nonce sizes
+            let mut file = fs::File::open(&encrypted_path).unwrap();
+            let raw = crypto_stream::read_stream_header(&mut file).unwrap();
+            assert_eq!(raw.header.cipher_suite, suite);
+            assert_eq!(raw.header.base_nonce.len(), suite.nonce_len());
+
+            let out_name = crypto_stream::decrypt_file_stream(
+                encrypted_path.to_str().unwrap(),
+                output_dir.to_str().unwrap(),
+                &vault_keys(&mk),
+                0,
+                |_, _| {},
+            )
+            .expect("Decryption failed")
+            .filename;
+            assert_eq!(fs::read(output_dir.join(out_name)).unwrap(), data);
+        }
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    // --- Post-Quantum (Hybrid) Key Slots ---
+
+    #[test]
+    fn test_post_quantum_roundtrip() {
+        use crate::hybrid::HybridSecretKey;
+        use rand::rngs::OsRng;
+
+        let test_dir = std::env::temp_dir().join("qre_tests_pq");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+
+        let input_path = test_dir.join("plans.txt");
+        let encrypted_path = test_dir.join("plans.txt.qre");
+        fs::write(&input_path, b"harvest now, decrypt later").unwrap();
+
+        let mk = keychain::MasterKey([4u8; 32]);
+        let identity = HybridSecretKey::generate(&mut OsRng);
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions { recipients: vec![identity.public_key()], ..Default::default() },
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        let decrypt_with = |identity: Option<&HybridSecretKey>| {
+            let keys = crypto_stream::UnlockKeys { master_key: &mk, keyfile_bytes: None, identity, passphrase: None };
+            crypto_stream::decrypt_file_stream(
+                encrypted_path.to_str().unwrap(),
+                test_dir.join("output").to_str().unwrap(),
+                &keys,
+                0,
+                |_, _| {},
+            )
+        };
+
+        // The Master Key alone is not enough: there is no Master Key slot
+        let err = decrypt_with(None).unwrap_err().to_string();
+        assert!(err.contains("another identity"), "{}", err);
+        let stranger = HybridSecretKey::generate(&mut OsRng);
+        assert!(decrypt_with(Some(&stranger)).is_err());
+
+        let name = decrypt_with(Some(&identity)).expect("Decryption failed").filename;
+        assert_eq!(fs::read(test_dir.join("output").join(name)).unwrap(), b"harvest now, decrypt later");
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_keychain_identity_persists() {
+        let test_dir = std::env::temp_dir().join("qre_tests_identity");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let path = test_dir.join("keychain.json");
+
+        let (_, mk) = keychain::init_keychain(&path, "correct horse").unwrap();
+        let first = keychain::load_identity(&path, &mk).unwrap().expect("No identity created");
+        let second = keychain::ensure_identity(&path, &mk).unwrap();
+        assert_eq!(first.public_key(), second.public_key());
+
+        // The secret half is only stored encrypted
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("identity_public"));
+        assert!(keychain::load_identity(&path, &keychain::MasterKey([0u8; 32])).is_err());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_multi_recipient_and_identity_export() {
+        use crate::contacts::ContactsVault;
+        use crate::hybrid::{HybridPublicKey, HybridSecretKey};
+        use rand::rngs::OsRng;
+
+        let test_dir = std::env::temp_dir().join("qre_tests_recipients");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+
+        let input_path = test_dir.join("roadmap.md");
+        let encrypted_path = test_dir.join("roadmap.md.qre");
+        fs::write(&input_path, b"Q3: ship it").unwrap();
+
+        // Colleagues share their identity as text; we import it into our contacts
+        let team: Vec<HybridSecretKey> = (0..5).map(|_| HybridSecretKey::generate(&mut OsRng)).collect();
+        let mut contacts = ContactsVault::new();
+        for (i, member) in team.iter().enumerate() {
+            let exported = member.public_key().to_export_string().unwrap();
+            let imported = HybridPublicKey::from_export_string(&exported).unwrap();
+            contacts.upsert(format!("Colleague {}", i), imported);
+        }
+        // Importing the same identity twice only renames it
+        contacts.upsert("Alice".into(), team[0].public_key());
+        assert_eq!(contacts.entries.len(), 5);
+        assert!(HybridPublicKey::from_export_string("qre-identity-v1:AAAA").is_err());
+
+        let mk = keychain::MasterKey([6u8; 32]);
+        let options = crypto_stream::StreamOptions {
+            recipients: contacts.entries.iter().map(|c| c.public_key.clone()).collect(),
+            ..Default::default()
+        };
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &options,
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        let mut file = fs::File::open(&encrypted_path).unwrap();
+        assert_eq!(crypto_stream::read_stream_header(&mut file).unwrap().header.key_slots.len(), 5);
+
+        // Every colleague opens the same file with their own identity
+        for (i, member) in team.iter().enumerate() {
+            let output_dir = test_dir.join(format!("out_{}", i));
+            fs::create_dir_all(&output_dir).unwrap();
+            let keys = crypto_stream::UnlockKeys { master_key: &mk, keyfile_bytes: None, identity: Some(member), passphrase: None };
+            let name = crypto_stream::decrypt_file_stream(
+                encrypted_path.to_str().unwrap(),
+                output_dir.to_str().unwrap(),
+                &keys,
+                0,
+                |_, _| {},
+            )
+            .expect("Decryption failed")
+            .filename;
+            assert_eq!(fs::read(output_dir.join(name)).unwrap(), b"Q3: ship it");
+        }
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_portable_passphrase_file() {
+        let test_dir = std::env::temp_dir().join("qre_tests_portable");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+
+        let input_path = test_dir.join("contract.pdf");
+        let encrypted_path = test_dir.join("contract.pdf.qre");
+        fs::write(&input_path, b"%PDF-1.7 signed copy").unwrap();
+
+        let sender = keychain::MasterKey([1u8; 32]);
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &sender,
+            None,
+            None,
+            &crypto_stream::StreamOptions { passphrase: Some("blue-river-42".into()), ..Default::default() },
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        // The receiver has a completely different vault
+        let receiver = keychain::MasterKey([2u8; 32]);
+        let decrypt_with = |passphrase: Option<&str>| {
+            let keys = crypto_stream::UnlockKeys { master_key: &receiver, keyfile_bytes: None, identity: None, passphrase };
+            crypto_stream::decrypt_file_stream(
+                encrypted_path.to_str().unwrap(),
+                test_dir.join("output").to_str().unwrap(),
+                &keys,
+                0,
+                |_, _| {},
+            )
+        };
+
+        let err = decrypt_with(None).unwrap_err();
+        assert!(err.downcast_ref::<crypto_stream::PassphraseRequired>().is_some());
+        assert!(decrypt_with(Some("wrong")).is_err());
+
+        let name = decrypt_with(Some("blue-river-42")).expect("Decryption failed").filename;
+        assert_eq!(fs::read(test_dir.join("output").join(name)).unwrap(), b"%PDF-1.7 signed copy");
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_verify_reports_bad_chunk() {
+        let (test_dir, encrypted_path, original_data) = setup_multi_chunk("qre_tests_verify");
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        let report = crypto_stream::verify_file_stream(encrypted_path.to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {})
+            .expect("Verification failed");
+        assert_eq!(report.filename, "big.bin");
+        assert_eq!(report.original_size, original_data.len() as u64);
+        assert_eq!(report.chunk_count, 3);
+
+        // Flip a byte inside the second data chunk
+        let mut data = fs::read(&encrypted_path).unwrap();
+        let records = chunk_records(&data);
+        data[records[1].start + 10] ^= 0xFF;
+        fs::write(&encrypted_path, &data).unwrap();
+
+        let err = crypto_stream::verify_file_stream(encrypted_path.to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {})
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<crypto_stream::ChunkError>().map(|c| c.index), Some(1));
+
+        // Nothing was written next to the file
+        assert!(fs::read_dir(test_dir.join("output")).unwrap().next().is_none());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_failed_decrypt_leaves_no_partial_output() {
+        let (test_dir, encrypted_path, _) = setup_multi_chunk("qre_tests_atomic");
+        let output_dir = test_dir.join("output");
+
+        // Chunks 0 and 1 are fine, chunk 2 is corrupted: the first 2MB of
+        // plaintext were already written when decryption fails
+        let mut data = fs::read(&encrypted_path).unwrap();
+        let records = chunk_records(&data);
+        data[records[2].start + 10] ^= 0xFF;
+        fs::write(&encrypted_path, &data).unwrap();
+
+        assert!(try_decrypt(&encrypted_path, &output_dir).is_err());
+        assert_eq!(fs::read_dir(&output_dir).unwrap().count(), 0, "partial plaintext left behind");
+
+        // A failed encryption leaves no .qre either
+        let missing = test_dir.join("does_not_exist.bin");
+        let locked = test_dir.join("does_not_exist.bin.qre");
+        let mk = keychain::MasterKey([7u8; 32]);
+        assert!(crypto_stream::encrypt_file_stream(
+            missing.to_str().unwrap(),
+            locked.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions::default(),
+            |_, _| {},
+        )
+        .is_err());
+        assert!(!locked.exists());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_in_memory_stream_roundtrip() {
+        // No files at all: plaintext from a buffer, ciphertext into a Vec
+        let original: Vec<u8> = (0..2_500_000u32).map(|i| (i % 251) as u8).collect();
+        let mk = keychain::MasterKey([9u8; 32]);
+
+        let mut locked = Vec::new();
+        crypto_stream::encrypt_stream(
+            original.as_slice(),
+            &mut locked,
+            crypto_stream::FileMetadata::for_stream("backup.tar", None), // Length unknown, like a pipe
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions::default(),
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        let mut restored = Vec::new();
+        let metadata = crypto_stream::decrypt_stream(
+            std::io::Cursor::new(&locked),
+            &mut restored,
+            &vault_keys(&mk),
+            2,
+            Some(locked.len() as u64),
+            |_, _| {},
+        )
+        .expect("Decryption failed");
+
+        assert_eq!(restored, original);
+        assert_eq!(metadata.filename, "backup.tar");
+        assert_eq!(metadata.mime_type.as_deref(), Some("application/x-tar"));
+
+        // Streams get the same tamper checks as files
+        let cut = &locked[..locked.len() - 100];
+        assert!(crypto_stream::decrypt_stream(cut, std::io::sink(), &vault_keys(&mk), 0, None, |_, _| {}).is_err());
+    }
+
+    #[test]
+    fn test_folder_roundtrip_without_plaintext_archive() {
+        let test_dir = std::env::temp_dir().join("qre_tests_folder");
+        let _ = fs::remove_dir_all(&test_dir);
+        let folder = test_dir.join("Photos");
+        fs::create_dir_all(folder.join("2024/summer")).unwrap();
+        fs::create_dir_all(folder.join("empty")).unwrap();
+        fs::write(folder.join("notes.txt"), b"hello").unwrap();
+        fs::write(folder.join("zero.bin"), b"").unwrap();
+        let big: Vec<u8> = (0..1_500_000u32).map(|i| (i % 239) as u8).collect();
+        fs::write(folder.join("2024/summer/beach.raw"), &big).unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("notes.txt", folder.join("inside")).unwrap();
+            std::os::unix::fs::symlink("/etc/hostname", folder.join("outside")).unwrap();
+            // Each link looks inside on its own, but together they climb out of the folder
+            std::os::unix::fs::symlink(".", folder.join("here")).unwrap();
+            std::os::unix::fs::symlink("here/..", folder.join("chained")).unwrap();
+        }
+
+        let mk = keychain::MasterKey([7u8; 32]); // Same key as `try_decrypt`
+        let encrypted_path = test_dir.join("Photos.qre");
+        crypto_stream::encrypt_file_stream(
+            folder.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions { symlinks: SymlinkPolicy::Preserve, ..Default::default() },
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+
+        // Only the folder and the .qre: no temporary zip next to them
+        assert_eq!(fs::read_dir(&test_dir).unwrap().count(), 2);
+
+        let output_dir = test_dir.join("output");
+        fs::create_dir_all(&output_dir).unwrap();
+        let name = try_decrypt(&encrypted_path, &output_dir).expect("Decryption failed");
+        let restored = output_dir.join(name);
+
+        assert_eq!(fs::read(restored.join("notes.txt")).unwrap(), b"hello");
+        assert_eq!(fs::read(restored.join("zero.bin")).unwrap(), b"");
+        assert_eq!(fs::read(restored.join("2024/summer/beach.raw")).unwrap(), big);
+        assert!(restored.join("empty").is_dir());
+        #[cfg(unix)]
+        {
+            assert_eq!(fs::read_link(restored.join("inside")).unwrap(), std::path::Path::new("notes.txt"));
+            // Links leaving the folder are not recreated
+            assert!(fs::symlink_metadata(restored.join("outside")).is_err());
+            assert_eq!(fs::read_link(restored.join("here")).unwrap(), std::path::Path::new("."));
+            assert!(fs::symlink_metadata(restored.join("chained")).is_err());
+        }
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_padding_hides_length() {
+        use rand::{RngCore, SeedableRng};
+        let mk = keychain::MasterKey([7u8; 32]);
+        let lock = |data: &[u8], options: &crypto_stream::StreamOptions| {
+            let mut locked = Vec::new();
+            crypto_stream::encrypt_stream(
+                data,
+                &mut locked,
+                crypto_stream::FileMetadata::for_stream("doc.pdf", None),
+                &mk,
+                None,
+                None,
+                options,
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+            locked
+        };
+        let unlock = |locked: &[u8]| {
+            let mut restored = Vec::new();
+            crypto_stream::decrypt_stream(locked, &mut restored, &vault_keys(&mk), 0, None, |_, _| {})
+                .expect("Decryption failed");
+            restored
+        };
+
+        // Incompressible data: the ciphertext would otherwise track the plaintext length
+        let mut rng = rand_chacha::ChaCha20Rng::seed_from_u64(1);
+        let mut data = vec![0u8; 100_500];
+        rng.fill_bytes(&mut data);
+
+        // 1. Padmé: ~100KB files are rounded to a multiple of 2KB
+        let padme = crypto_stream::StreamOptions { padding: crypto_stream::PaddingScheme::Padme, ..Default::default() };
+        let short = lock(&data[..100_000], &padme);
+        let long = lock(&data, &padme);
+        assert_eq!(short.len() % 2048, 0);
+        assert_eq!(long.len() % 2048, 0);
+        assert_eq!(unlock(&short), &data[..100_000]);
+        assert_eq!(unlock(&long), data);
+
+        // 2. Fixed-size chunks: compressible and random chunks look the same
+        let test_dir = std::env::temp_dir().join("qre_tests_padding");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let mut mixed = vec![0u8; 2_500_000];
+        rng.fill_bytes(&mut mixed[1_048_576..2_097_152]);
+        let fixed = crypto_stream::StreamOptions {
+            padding: crypto_stream::PaddingScheme::Padme,
+            fixed_size_chunks: true,
+            ..Default::default()
+        };
+        let locked = lock(&mixed, &fixed);
+        let records = chunk_records(&locked);
+        assert!(records.len() >= 4);
+        assert!(records[..3].iter().all(|r| r.len() == records[0].len()));
+        assert_eq!(unlock(&locked), mixed);
+
+        // Random access skips the padding records
+        let encrypted_path = test_dir.join("mixed.qre");
+        fs::write(&encrypted_path, &locked).unwrap();
+        let mut reader = QreReader::open(&encrypted_path, &vault_keys(&mk)).expect("Open failed");
+        assert_eq!(reader.total_size(), mixed.len() as u64);
+        let mut all = Vec::new();
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, mixed);
+
+        // Padding is authenticated like the data
+        let mut tampered = locked.clone();
+        let last = records.last().unwrap().end;
+        tampered[last - 20] ^= 1;
+        assert!(crypto_stream::decrypt_stream(&tampered[..], std::io::sink(), &vault_keys(&mk), 0, None, |_, _| {}).is_err());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_inspect_without_key() {
+        let test_dir = std::env::temp_dir().join("qre_tests_inspect");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        let input_path = test_dir.join("report.bin");
+        fs::write(&input_path, vec![1u8; 2_500_000]).unwrap();
+
+        // Master Key + Keyfile, XChaCha20, three data chunks
+        let encrypted_path = test_dir.join("report.bin.qre");
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &keychain::MasterKey([7u8; 32]),
+            Some(b"keyfile hash"),
+            None,
+            &crypto_stream::StreamOptions { cipher_suite: CipherSuite::XChaCha20Poly1305, ..Default::default() },
+            |_, _| {},
+        )
+        .unwrap();
+
+        let report = inspect::inspect_qre(&encrypted_path);
+        assert!(report.well_formed, "{:?}", report.problems);
+        assert_eq!(report.version, Some(6));
+        assert_eq!(report.cipher, Some(CipherSuite::XChaCha20Poly1305));
+        assert_eq!(report.uses_keyfile, Some(true));
+        assert_eq!(report.chunk_count, Some(3));
+        assert_eq!(report.key_slots.len(), 1);
+
+        // Cut inside the last record
+        let data = fs::read(&encrypted_path).unwrap();
+        fs::write(&encrypted_path, &data[..data.len() - 10]).unwrap();
+        let report = inspect::inspect_qre(&encrypted_path);
+        assert!(!report.well_formed);
+        assert_eq!(report.version, Some(6));
+
+        // Not a .qre file at all
+        let report = inspect::inspect_qre(&input_path);
+        assert!(!report.well_formed);
+        assert_eq!(report.format, "unknown");
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_rewrap_keeps_body() {
+        let (test_dir, encrypted_path, original_data) = setup_multi_chunk("qre_tests_rewrap");
+        let output_dir = test_dir.join("output");
+        let mk = keychain::MasterKey([7u8; 32]);
+        let keyfile = b"new keyfile hash".to_vec();
+        let path = encrypted_path.to_str().unwrap();
+
+        let body_of = |data: &[u8]| {
+            let header_len = u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize;
+            data[8 + header_len + 32..].to_vec()
+        };
+        let before = fs::read(&encrypted_path).unwrap();
+
+        // 1. Add a Keyfile
+        crypto_stream::rewrap_file_stream(path, &vault_keys(&mk), &mk, Some(&keyfile), &[], None).expect("Rewrap failed");
+        let after = fs::read(&encrypted_path).unwrap();
+        assert_eq!(body_of(&before), body_of(&after), "body must be copied untouched");
+
+        let err = try_decrypt(&encrypted_path, &output_dir).unwrap_err();
+        assert!(err.to_string().contains("Keyfile"));
+        let with_keyfile = crypto_stream::UnlockKeys { keyfile_bytes: Some(&keyfile), ..vault_keys(&mk) };
+
+        // 2. Wrong current keys: the file is left as it was
+        assert!(crypto_stream::rewrap_file_stream(path, &vault_keys(&mk), &mk, None, &[], Some("x")).is_err());
+        assert_eq!(fs::read(&encrypted_path).unwrap(), after);
+
+        // 3. Convert to a Portable file
+        crypto_stream::rewrap_file_stream(path, &with_keyfile, &mk, None, &[], Some("correct horse")).expect("Rewrap failed");
+        let other_vault = keychain::MasterKey([1u8; 32]);
+        let portable = crypto_stream::UnlockKeys { passphrase: Some("correct horse"), ..vault_keys(&other_vault) };
+        let name = crypto_stream::decrypt_file_stream(path, output_dir.to_str().unwrap(), &portable, 0, |_, _| {})
+            .expect("Decryption failed")
+            .filename;
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+
+        // No temporary files left next to the original
+        assert_eq!(fs::read_dir(&test_dir).unwrap().count(), 3);
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_master_key_rotation() {
+        let test_dir = std::env::temp_dir().join("qre_tests_rotation");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let keychain_path = test_dir.join("keychain.json");
+        let (_, old_mk) = keychain::init_keychain(&keychain_path, "correct horse").unwrap();
+        let identity = keychain::load_identity(&keychain_path, &old_mk).unwrap().unwrap();
+
+        let notes = crypto::encrypt_file_with_master_key(&old_mk, None, "notes.json", b"{\"notes\":[]}", None, 3).unwrap();
+        notes.save(test_dir.join("notes.qre").to_str().unwrap()).unwrap();
+        let locked_path = test_dir.join("secret.txt.qre");
+        fs::write(test_dir.join("secret.txt"), b"top secret").unwrap();
+        crypto_stream::encrypt_file_stream(
+            test_dir.join("secret.txt").to_str().unwrap(),
+            locked_path.to_str().unwrap(),
+            &old_mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions::default(),
+            |_, _| {},
+        )
+        .unwrap();
+
+        // 1. Wrong password: rolled back, nothing left behind
+        assert!(rotation::rotate_vault(&test_dir, "wrong", &old_mk, std::slice::from_ref(&locked_path), None).is_err());
+        assert_eq!(fs::read_dir(&test_dir).unwrap().count(), 5);
+        assert_eq!(keychain::unlock_keychain(&keychain_path, "correct horse").unwrap().0, old_mk.0);
+
+        // 2. Rotation
+        let outcome = rotation::rotate_vault(&test_dir, "correct horse", &old_mk, std::slice::from_ref(&locked_path), None)
+            .expect("Rotation failed");
+        let new_mk = outcome.new_master_key;
+        assert_ne!(new_mk.0, old_mk.0);
+        let files = rotation::resume_rewrap(&test_dir, &new_mk).expect("Rewrap failed");
+        assert!(files.len() == 1 && files[0].success);
+        assert_eq!(fs::read_dir(&test_dir).unwrap().count(), 5, "journal and staged copies must be gone");
+
+        assert_eq!(keychain::unlock_keychain(&keychain_path, "correct horse").unwrap().0, new_mk.0);
+        let rotated_identity = keychain::load_identity(&keychain_path, &new_mk).unwrap().unwrap();
+        assert_eq!(rotated_identity.public_key(), identity.public_key());
+
+        // The V4 store was moved to the stream format on the way
+        let notes_path = test_dir.join("notes.qre");
+        assert!(crypto_stream::load_store(&notes_path, &old_mk).is_err());
+        let payload = crypto_stream::load_store(&notes_path, &new_mk).unwrap();
+        assert_eq!(payload.filename, "notes.json");
+        assert_eq!(payload.content, b"{\"notes\":[]}");
+
+        let output_dir = test_dir.join("output");
+        let path = locked_path.to_str().unwrap();
+        assert!(crypto_stream::decrypt_file_stream(path, output_dir.to_str().unwrap(), &vault_keys(&old_mk), 0, |_, _| {}).is_err());
+        let name = crypto_stream::decrypt_file_stream(path, output_dir.to_str().unwrap(), &vault_keys(&new_mk), 0, |_, _| {})
+            .expect("Decryption failed")
+            .filename;
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), b"top secret");
+
+        let recovered = keychain::recover_with_code(&keychain_path, &outcome.recovery_code, "battery staple").unwrap();
+        assert_eq!(recovered.0, new_mk.0);
+
+        // 3. A file that cannot be rewrapped is caught before anything changes
+        let damaged_path = test_dir.join("damaged.qre");
+        let mut damaged = fs::read(&locked_path).unwrap();
+        damaged[20] ^= 0xFF;
+        fs::write(&damaged_path, damaged).unwrap();
+        let err = rotation::rotate_vault(&test_dir, "battery staple", &new_mk, std::slice::from_ref(&damaged_path), None)
+            .err()
+            .expect("must be rejected");
+        assert!(format!("{:#}", err).contains("Nothing was changed"), "{:#}", err);
+        assert!(rotation::read_journal(&test_dir).unwrap().is_none());
+        assert_eq!(keychain::unlock_keychain(&keychain_path, "battery staple").unwrap().0, new_mk.0);
+        fs::remove_file(&damaged_path).unwrap();
+
+        // 4. Split files are rotated as a set, however many of their volumes are listed
+        fs::create_dir_all(test_dir.join("split")).unwrap();
+        let split_source = test_dir.join("split/archive.bin");
+        let split_data = pseudo_random(2_500_000, 0x2545_F491_4F6C_DD1D);
+        fs::write(&split_source, &split_data).unwrap();
+        let split_base = test_dir.join("split/archive.bin.qre");
+        let options = crypto_stream::StreamOptions { volume_size: Some(volumes::MIN_VOLUME_SIZE), ..Default::default() };
+        crypto_stream::encrypt_file_stream(split_source.to_str().unwrap(), split_base.to_str().unwrap(), &new_mk, None, None, &options, |_, _| {})
+            .unwrap();
+        let targets = [volumes::volume_path(&split_base, 1), volumes::volume_path(&split_base, 2), locked_path.clone()];
+        let outcome = rotation::rotate_vault(&test_dir, "battery staple", &new_mk, &targets, None).expect("Rotation failed");
+        let files = rotation::resume_rewrap(&test_dir, &outcome.new_master_key).expect("Rewrap failed");
+        assert!(files.len() == 2 && files.iter().all(|f| f.success), "{:?}", files);
+        let new_mk = outcome.new_master_key;
+        let volume_3 = volumes::volume_path(&split_base, 3);
+        crypto_stream::verify_file_stream(volume_3.to_str().unwrap(), &vault_keys(&new_mk), 0, |_, _| {}).expect("Verify failed");
+
+        // 5. A file that fails after the commit keeps the old key until the user decides
+        let away = test_dir.join("split/away.qre");
+        let rotate_with_file_away = |mk: &keychain::MasterKey| {
+            let outcome = rotation::rotate_vault(&test_dir, "battery staple", mk, std::slice::from_ref(&locked_path), None)
+                .expect("Rotation failed");
+            fs::rename(&locked_path, &away).unwrap();
+            let files = rotation::resume_rewrap(&test_dir, &outcome.new_master_key).expect("Rewrap failed");
+            assert!(!files[0].success);
+            assert!(files[0].message.contains("old Master Key is kept"), "{}", files[0].message);
+            fs::rename(&away, &locked_path).unwrap();
+            outcome.new_master_key
+        };
+        let previous_mk = new_mk;
+        let new_mk = rotate_with_file_away(&previous_mk);
+        assert_eq!(rotation::read_journal(&test_dir).unwrap().unwrap().failed_files.len(), 1);
+        let err = rotation::rotate_vault(&test_dir, "battery staple", &new_mk, &[], None).err().expect("must wait");
+        assert!(err.to_string().contains("still need the old Master Key"), "{}", err);
+
+        // Retried once the file is back
+        let files = rotation::retry_failed(&test_dir, &new_mk, None).expect("Retry failed");
+        assert!(files.len() == 1 && files[0].success, "{:?}", files);
+        assert!(rotation::read_journal(&test_dir).unwrap().is_none());
+        crypto_stream::verify_file_stream(path, &vault_keys(&new_mk), 0, |_, _| {}).expect("Verify failed");
+
+        // Or given up, only when asked to
+        let previous_mk = new_mk;
+        let new_mk = rotate_with_file_away(&previous_mk);
+        assert_eq!(rotation::forget_old_master_key(&test_dir, &new_mk).unwrap(), vec![path.to_string()]);
+        assert!(rotation::read_journal(&test_dir).unwrap().is_none());
+        assert!(crypto_stream::verify_file_stream(path, &vault_keys(&new_mk), 0, |_, _| {}).is_err());
+        crypto_stream::verify_file_stream(path, &vault_keys(&previous_mk), 0, |_, _| {}).expect("Verify failed");
+
+        // 6. Interrupted while staging: rolled back before the next login
+        fs::write(test_dir.join("notes.qre.rotating"), b"half written").unwrap();
+        let journal = rotation::RotationJournal {
+            stage: rotation::RotationStage::Staging,
+            vault_files: vec!["notes.qre".to_string(), "keychain.json".to_string()],
+            pending_files: Vec::new(),
+            attempts: Default::default(),
+            failed_files: Vec::new(),
+            secrets_nonce: Vec::new(),
+            encrypted_secrets: Vec::new(),
+        };
+        rotation::write_journal(&test_dir, &journal).unwrap();
+        rotation::recover_interrupted(&test_dir).unwrap();
+        assert!(rotation::read_journal(&test_dir).unwrap().is_none());
+        assert!(!test_dir.join("notes.qre.rotating").exists());
+        assert!(crypto_stream::load_store(&notes_path, &new_mk).is_ok());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_migrate_v4_container() {
+        let test_dir = std::env::temp_dir().join("qre_tests_migrate");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let mk = keychain::MasterKey([7u8; 32]);
+        let keyfile = b"keyfile hash".to_vec();
+        let original_data: Vec<u8> = (0..1_500_000u32).map(|i| (i % 199) as u8).collect();
+
+        let container = crypto::encrypt_file_with_master_key(&mk, Some(&keyfile), "report.pdf", &original_data, None, 3).unwrap();
+        let path = test_dir.join("report.pdf.qre");
+        container.save(path.to_str().unwrap()).unwrap();
+
+        // A hash mismatch is reported as an integrity error, not as a wrong key
+        let mut tampered = crypto::EncryptedFileContainer::load(&path).unwrap();
+        tampered.header.original_hash = Some(vec![0u8; 32]);
+        let err = crypto::decrypt_file_with_master_key(&mk, Some(&keyfile), &tampered).unwrap_err();
+        assert!(err.downcast_ref::<crypto_stream::IntegrityError>().is_some());
+
+        // Wrong Keyfile: the container is left as it was
+        let before = fs::read(&path).unwrap();
+        assert!(crypto_stream::migrate_container(&path, &mk, Some(b"wrong")).is_err());
+        assert_eq!(fs::read(&path).unwrap(), before);
+
+        assert!(crypto_stream::migrate_container(&path, &mk, Some(&keyfile)).expect("Migration failed"));
+        assert!(crypto_stream::is_stream_version(u32::from_le_bytes(fs::read(&path).unwrap()[..4].try_into().unwrap())));
+        assert!(!crypto_stream::migrate_container(&path, &mk, Some(&keyfile)).unwrap(), "already migrated");
+
+        let keys = crypto_stream::UnlockKeys { keyfile_bytes: Some(&keyfile), ..vault_keys(&mk) };
+        let output_dir = test_dir.join("output");
+        let name = crypto_stream::decrypt_file_stream(path.to_str().unwrap(), output_dir.to_str().unwrap(), &keys, 0, |_, _| {})
+            .expect("Decryption failed")
+            .filename;
+        assert_eq!(name, "report.pdf");
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+
+        // Vault stores are written in the stream format too
+        let store_path = test_dir.join("passwords.qre");
+        crypto_stream::save_store(&store_path, &mk, "passwords.json", b"{}").unwrap();
+        assert_eq!(crypto_stream::load_store(&store_path, &mk).unwrap().content, b"{}");
+        assert_eq!(inspect::inspect_qre(&store_path).format, "v6-stream");
+
+        // No temporary files left behind
+        assert_eq!(fs::read_dir(&test_dir).unwrap().count(), 3);
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_malformed_headers_are_rejected() {
+        let is_malformed = |e: &anyhow::Error| e.downcast_ref::<crypto_stream::MalformedHeader>().is_some();
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        // 1. V5: a length prefix announcing an 8 EB nonce fails without allocating it
+        let mut crafted = 5u32.to_le_bytes().to_vec();
+        crafted.extend_from_slice(&u64::MAX.to_le_bytes());
+        crafted.extend_from_slice(&[0u8; 64]);
+        let err = crypto_stream::read_stream_header(&mut crafted.as_slice()).err().expect("must be rejected");
+        assert!(is_malformed(&err), "{:#}", err);
+
+        // 2. V4: wrong nonce length, then an oversized ciphertext length prefix
+        let mut container = crypto::encrypt_file_with_master_key(&mk, None, "a.txt", b"hello", None, 3).unwrap();
+        container.header.body_nonce = vec![0u8; 3];
+        let bytes = bincode::serialize(&container).unwrap();
+        let err = crypto::EncryptedFileContainer::read_from(bytes.as_slice(), bytes.len() as u64).unwrap_err();
+        assert!(is_malformed(&err), "{:#}", err);
+        assert!(crypto::decrypt_file_with_master_key(&mk, None, &container).is_err());
+
+        container.header.body_nonce = vec![0u8; 12];
+        let mut bytes = bincode::serialize(&container).unwrap();
+        let len_offset = bytes.len() - container.ciphertext.len() - 8;
+        bytes[len_offset..len_offset + 8].copy_from_slice(&(1u64 << 40).to_le_bytes());
+        let err = crypto::EncryptedFileContainer::read_from(bytes.as_slice(), bytes.len() as u64).unwrap_err();
+        assert!(is_malformed(&err), "{:#}", err);
+
+        // 3. V6: a base nonce that does not match the cipher suite
+        let (test_dir, encrypted_path, _) = setup_multi_chunk("qre_tests_malformed");
+        let data = fs::read(&encrypted_path).unwrap();
+        let raw = crypto_stream::read_stream_header(&mut data.as_slice()).unwrap();
+        let mut header = raw.header;
+        header.base_nonce.truncate(4);
+        let header_bytes = bincode::serialize(&header).unwrap();
+        let mut crafted = 6u32.to_le_bytes().to_vec();
+        crafted.extend_from_slice(&(header_bytes.len() as u32).to_le_bytes());
+        crafted.extend_from_slice(&header_bytes);
+        crafted.extend_from_slice(&[0u8; 32]);
+        let err = crypto_stream::read_stream_header(&mut crafted.as_slice()).err().expect("must be rejected");
+        assert!(is_malformed(&err), "{:#}", err);
+
+        // 4. V6: bytes past the last known field (a newer header layout) are not skipped
+        let mut header_bytes = data[8..8 + u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize].to_vec();
+        header_bytes.push(1);
+        let mut crafted = 6u32.to_le_bytes().to_vec();
+        crafted.extend_from_slice(&(header_bytes.len() as u32).to_le_bytes());
+        crafted.extend_from_slice(&header_bytes);
+        crafted.extend_from_slice(&[0u8; 32]);
+        let err = crypto_stream::read_stream_header(&mut crafted.as_slice()).err().expect("must be rejected");
+        assert!(is_malformed(&err), "{:#}", err);
+
+        // 5. The fuzz entry points survive truncated and bit-flipped inputs
+        let header_end = 8 + u32::from_le_bytes(data[4..8].try_into().unwrap()) as usize + 32;
+        for n in 0..header_end {
+            fuzzing::stream_header(&data[..n]);
+            let mut flipped = data[..header_end].to_vec();
+            flipped[n] ^= 0x80;
+            fuzzing::stream_header(&flipped);
+        }
+        for n in 0..bytes.len() {
+            fuzzing::v4_container(&bytes[..n]);
+        }
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_v6_header_layout_is_frozen() {
+        // If this fails, the header encoding changed: bump the stream version and keep
+        // reading the V6 layout (see `StreamHeader`) instead of updating the bytes below.
+        let header = crypto_stream::StreamHeader {
+            cipher_suite: CipherSuite::XChaCha20Poly1305,
+            key_slots: vec![crypto_stream::KeySlot::MasterKey {
+                validation_nonce: vec![1],
+                encrypted_validation_tag: vec![2],
+                key_wrapping_nonce: vec![3],
+                encrypted_file_key: vec![4],
+                uses_keyfile: true,
+            }],
+            base_nonce: vec![5],
+            metadata_nonce: vec![6],
+            encrypted_metadata: vec![7],
+            padding: crypto_stream::PaddingScheme::Padme,
+            fixed_size_chunks: true,
+            signer: Some([8u8; 32]),
+            adaptive_chunks: true,
+            stream_compression: false,
+        };
+        let encoded: String = bincode::serialize(&header).unwrap().iter().map(|b| format!("{:02x}", b)).collect();
+        let expected = concat!(
+            "0100000001000000000000000000000001000000000000000101000000000000",
+            "0002010000000000000003010000000000000004010100000000000000050100",
+            "0000000000000601000000000000000701000000010108080808080808080808",
+            "080808080808080808080808080808080808080808080100",
+        );
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn test_signed_files() {
+        let test_dir = std::env::temp_dir().join("qre_tests_signing");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let output_dir = test_dir.join("output");
+
+        // 1. The keychain holds a signing key from the start, kept across a rotation
+        let keychain_path = test_dir.join("keychain.json");
+        let (_, mk) = keychain::init_keychain(&keychain_path, "battery staple").unwrap();
+        let signing_key = keychain::load_signing_key(&keychain_path, &mk).unwrap().expect("No signing key");
+        let public_key = signing_key.verifying_key().to_bytes();
+        let new_mk = keychain::MasterKey([3u8; 32]);
+        let rotated_path = test_dir.join("rotated.json");
+        keychain::rotate_keychain(&keychain_path, "battery staple", &mk, &new_mk, &rotated_path).unwrap();
+        let rotated = keychain::load_signing_key(&rotated_path, &new_mk).unwrap().expect("No signing key");
+        assert_eq!(rotated.verifying_key().to_bytes(), public_key);
+
+        // 2. Signed + padded file
+        let input_path = test_dir.join("contract.bin");
+        let encrypted_path = test_dir.join("contract.bin.qre");
+        let original_data: Vec<u8> = (0..1_500_000u32).map(|i| (i % 241) as u8).collect();
+        fs::write(&input_path, &original_data).unwrap();
+        let options = crypto_stream::StreamOptions {
+            padding: crypto_stream::PaddingScheme::Padme,
+            signer: Some(signing_key),
+            ..Default::default()
+        };
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &options,
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+        let path = encrypted_path.to_str().unwrap();
+
+        let unlocked = crypto_stream::decrypt_file_stream(path, output_dir.to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {})
+            .expect("Decryption failed");
+        assert_eq!(unlocked.signer, Some(public_key));
+        assert_eq!(fs::read(output_dir.join(&unlocked.filename)).unwrap(), original_data);
+
+        // A damaged trusted signers list only costs the signer's name in the report
+        let signers_path = test_dir.join("signers.qre");
+        fs::write(&signers_path, b"not a vault store").unwrap();
+        assert!(signing::TrustedSigners::load_for_report(&signers_path, &mk).entries.is_empty());
+        let unlocked = crypto_stream::decrypt_file_stream(path, output_dir.to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {})
+            .expect("Decryption failed");
+        assert_eq!(unlocked.signer, Some(public_key));
+        let mut signers = signing::TrustedSigners::new();
+        signers.upsert("Alice".to_string(), public_key);
+        crypto_stream::save_store(&signers_path, &mk, "signers.json", &serde_json::to_vec(&signers).unwrap()).unwrap();
+        assert_eq!(signing::TrustedSigners::load_for_report(&signers_path, &mk).entries[0].name, "Alice");
+
+        let mut reader = QreReader::open(&encrypted_path, &vault_keys(&mk)).expect("Random access failed");
+        let mut restored = Vec::new();
+        reader.read_to_end(&mut restored).unwrap();
+        assert_eq!(restored, original_data);
+
+        // 3. Checked without any key, and still valid after a rewrap
+        crypto_stream::rewrap_file_stream(path, &vault_keys(&mk), &mk, Some(b"keyfile hash"), &[], None).unwrap();
+        let report = inspect::inspect_qre(&encrypted_path);
+        assert!(report.well_formed, "{:?}", report.problems);
+        assert_eq!(report.signer, Some(signing::signer_fingerprint(&public_key)));
+        assert_eq!(report.signature_valid, Some(true));
+        assert_eq!(report.chunk_count.map(|n| n >= 2), Some(true));
+
+        // 4. A modified signature, or a stripped one, is rejected
+        let data = fs::read(&encrypted_path).unwrap();
+        let keys = crypto_stream::UnlockKeys { keyfile_bytes: Some(b"keyfile hash"), ..vault_keys(&mk) };
+        assert!(crypto_stream::verify_file_stream(path, &keys, 0, |_, _| {}).unwrap().signer.is_some());
+
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 0x01;
+        fs::write(&encrypted_path, &tampered).unwrap();
+        assert!(crypto_stream::verify_file_stream(path, &keys, 0, |_, _| {}).is_err());
+        assert_eq!(inspect::inspect_qre(&encrypted_path).signature_valid, Some(false));
+
+        fs::write(&encrypted_path, &data[..data.len() - signing::SIGNATURE_RECORD_LEN]).unwrap();
+        assert!(crypto_stream::verify_file_stream(path, &keys, 0, |_, _| {}).is_err());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_split_volumes() {
+        let test_dir = std::env::temp_dir().join("qre_tests_volumes");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let output_dir = test_dir.join("output");
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        // Incompressible, so the stream spans several 1MB volumes
+        let input_path = test_dir.join("archive.bin");
+        let original_data = pseudo_random(3_200_000, 0x2545_F491_4F6C_DD1D);
+        fs::write(&input_path, &original_data).unwrap();
+
+        let lock = |base: &std::path::Path| {
+            let options = crypto_stream::StreamOptions { volume_size: Some(volumes::MIN_VOLUME_SIZE), ..Default::default() };
+            crypto_stream::encrypt_file_stream(
+                input_path.to_str().unwrap(),
+                base.to_str().unwrap(),
+                &mk,
+                None,
+                None,
+                &options,
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+        };
+        let base = test_dir.join("archive.bin.qre");
+        lock(&base);
+        let volume = |i: u32| volumes::volume_path(&base, i);
+        assert!(!base.exists());
+        assert!(volume(4).exists() && !volume(5).exists());
+        for i in 1..=4 {
+            assert!(fs::metadata(volume(i)).unwrap().len() <= volumes::MIN_VOLUME_SIZE);
+        }
+
+        // 1. Any volume unlocks the whole set
+        let unlock = |i: u32| {
+            crypto_stream::decrypt_file_stream(volume(i).to_str().unwrap(), output_dir.to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {})
+        };
+        let name = unlock(3).expect("Decryption failed").filename;
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+        crypto_stream::verify_file_stream(volume(1).to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {}).expect("Verify failed");
+
+        // Random access across volume boundaries
+        let mut reader = QreReader::open(&volume(2), &vault_keys(&mk)).expect("Open failed");
+        let mut slice = vec![0u8; 300_000];
+        reader.seek(SeekFrom::Start(900_000)).unwrap();
+        reader.read_exact(&mut slice).unwrap();
+        assert_eq!(slice, original_data[900_000..1_200_000]);
+        reader.seek(SeekFrom::End(-100)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, original_data[original_data.len() - 100..]);
+
+        // Rewrapping rewrites volume 1 only, and reseals it
+        let other_mk = keychain::MasterKey([9u8; 32]);
+        let untouched = fs::read(volume(3)).unwrap();
+        crypto_stream::rewrap_file_stream(volume(2).to_str().unwrap(), &vault_keys(&mk), &other_mk, None, &[], None).expect("Rewrap failed");
+        assert_eq!(fs::read(volume(3)).unwrap(), untouched);
+        assert!(crypto_stream::verify_file_stream(volume(1).to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {}).is_err());
+        crypto_stream::verify_file_stream(volume(4).to_str().unwrap(), &vault_keys(&other_mk), 0, |_, _| {}).expect("Verify failed");
+        assert!(crypto_stream::rotate_master_key_stream(volume(1).to_str().unwrap(), &other_mk, &mk, None).expect("Rotation failed"));
+        crypto_stream::verify_file_stream(volume(1).to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {}).expect("Verify failed");
+
+        let report = inspect::inspect_qre(&volume(1));
+        assert_eq!(report.format, "v6-volume");
+        assert!(report.problems[0].contains("split file"));
+
+        // 2. Swapped volumes
+        fs::rename(volume(2), test_dir.join("tmp")).unwrap();
+        fs::rename(volume(3), volume(2)).unwrap();
+        fs::rename(test_dir.join("tmp"), volume(3)).unwrap();
+        let err = unlock(1).unwrap_err().to_string();
+        assert!(err.contains("misordered"), "{}", err);
+        fs::rename(volume(2), test_dir.join("tmp")).unwrap();
+        fs::rename(volume(3), volume(2)).unwrap();
+        fs::rename(test_dir.join("tmp"), volume(3)).unwrap();
+
+        // 3. Missing volume
+        let saved = fs::read(volume(4)).unwrap();
+        fs::remove_file(volume(4)).unwrap();
+        let err = unlock(1).unwrap_err().to_string();
+        assert!(err.contains("Volume 4 of 4 is missing"), "{}", err);
+        fs::write(volume(4), &saved).unwrap();
+
+        // 4. A crafted volume count is refused before anything is allocated
+        let mut data = fs::read(volume(1)).unwrap();
+        data[24..28].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(volume(1), &data).unwrap();
+        let err = unlock(1).unwrap_err().to_string();
+        assert!(err.contains("damaged"), "{}", err);
+        data[24..28].copy_from_slice(&4u32.to_le_bytes());
+        fs::write(volume(1), &data).unwrap();
+
+        // 5. A volume of another locked file
+        let other = test_dir.join("other.qre");
+        lock(&other);
+        fs::copy(volumes::volume_path(&other, 2), volume(2)).unwrap();
+        let err = unlock(1).unwrap_err().to_string();
+        assert!(err.contains("another locked file"), "{}", err);
+
+        // 6. Headers rewritten consistently still fail their MAC
+        for i in 1..=4 {
+            let mut data = fs::read(volumes::volume_path(&other, i)).unwrap();
+            data[4] ^= 0x01;
+            fs::write(volumes::volume_path(&other, i), data).unwrap();
+        }
+        let err = crypto_stream::verify_file_stream(volumes::volume_path(&other, 1).to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {})
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("failed authentication"), "{}", err);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_parity_repair() {
+        let test_dir = std::env::temp_dir().join("qre_tests_parity");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let output_dir = test_dir.join("output");
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        // Incompressible, so the locked file spans ~10 shards of 64KB
+        let input_path = test_dir.join("photo.raw");
+        let encrypted_path = test_dir.join("photo.raw.qre");
+        let original_data = pseudo_random(600_000, 0x9E37_79B9_7F4A_7C15);
+        fs::write(&input_path, &original_data).unwrap();
+        crypto_stream::encrypt_file_stream(
+            input_path.to_str().unwrap(),
+            encrypted_path.to_str().unwrap(),
+            &mk,
+            None,
+            None,
+            &crypto_stream::StreamOptions::default(),
+            |_, _| {},
+        )
+        .expect("Encryption failed");
+        let parity_path = parity::write_parity(&encrypted_path, 10, 0).expect("Writing parity failed");
+        assert_eq!(parity_path, parity::parity_path(&encrypted_path));
+        let locked = fs::read(&encrypted_path).unwrap();
+
+        let damage = |shards: &[usize]| {
+            let mut data = fs::read(&encrypted_path).unwrap();
+            for shard in shards {
+                data[shard * 64 * 1024 + 100] ^= 0xFF;
+            }
+            fs::write(&encrypted_path, data).unwrap();
+        };
+
+        // 1. Nothing to do on an intact file
+        let report = parity::repair_file(&encrypted_path, 0).unwrap();
+        assert_eq!(report.damaged_shards, 0);
+
+        // 2. Bit rot in two shards (header included) is repaired
+        damage(&[0, 3]);
+        assert!(try_decrypt(&encrypted_path, &output_dir).is_err());
+        let report = parity::repair_file(&encrypted_path, 0).unwrap();
+        assert_eq!((report.damaged_shards, report.repaired_shards, report.unrecoverable_groups), (2, 2, 0));
+        assert_eq!(fs::read(&encrypted_path).unwrap(), locked);
+        let name = try_decrypt(&encrypted_path, &output_dir).expect("Decryption failed");
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+
+        // 3. More damage than parity: reported, file left untouched
+        damage(&[0, 1, 2, 3, 4, 5, 6, 7]);
+        let damaged = fs::read(&encrypted_path).unwrap();
+        let report = parity::repair_file(&encrypted_path, 0).unwrap();
+        assert_eq!(report.unrecoverable_groups, 1);
+        assert_eq!(report.repaired_shards, 0);
+        assert_eq!(fs::read(&encrypted_path).unwrap(), damaged);
+        fs::write(&encrypted_path, &locked).unwrap();
+
+        // 4. Rewrapping refreshes the recovery records (no rollback to the old header)
+        let path = encrypted_path.to_str().unwrap();
+        crypto_stream::rewrap_file_stream(path, &vault_keys(&mk), &mk, None, &[], Some("correct horse")).expect("Rewrap failed");
+        let rewrapped = fs::read(&encrypted_path).unwrap();
+        damage(&[0]);
+        let report = parity::repair_file(&encrypted_path, 0).unwrap();
+        assert_eq!(report.repaired_shards, 1);
+        assert_eq!(fs::read(&encrypted_path).unwrap(), rewrapped);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_verify_against_source() {
+        let test_dir = std::env::temp_dir().join("qre_tests_shred");
+        let _ = fs::remove_dir_all(&test_dir);
+        let folder = test_dir.join("photos");
+        fs::create_dir_all(folder.join("2024")).unwrap();
+        fs::write(folder.join("a.jpg"), vec![1u8; 5000]).unwrap();
+        fs::write(folder.join("2024/b.jpg"), vec![2u8; 7000]).unwrap();
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        let lock = |source: &std::path::Path| {
+            let output = format!("{}.qre", source.display());
+            crypto_stream::encrypt_file_stream(
+                source.to_str().unwrap(),
+                &output,
+                &mk,
+                None,
+                None,
+                &crypto_stream::StreamOptions::default(),
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+            output
+        };
+        let check = |locked: &str, source: &std::path::Path| {
+            crypto_stream::verify_against_source(locked, source, SymlinkPolicy::default(), &vault_keys(&mk), 0, |_, _| {})
+        };
+
+        // 1. A file and a folder match their locked copies
+        let file = folder.join("a.jpg");
+        let locked_file = lock(&file);
+        check(&locked_file, &file).expect("File should match");
+        let locked_folder = lock(&folder);
+        check(&locked_folder, &folder).expect("Folder should match");
+
+        // 2. Originals changed after locking are caught
+        fs::write(&file, vec![1u8; 5001]).unwrap();
+        let err = check(&locked_file, &file).unwrap_err().to_string();
+        assert!(err.contains("does not match the original"), "{}", err);
+        fs::write(folder.join("2024/c.jpg"), b"new").unwrap();
+        assert!(check(&locked_folder, &folder).is_err());
+
+        // 3. Wrong keys never pass
+        let other = keychain::MasterKey([1u8; 32]);
+        assert!(crypto_stream::verify_against_source(&locked_file, &file, SymlinkPolicy::default(), &vault_keys(&other), 0, |_, _| {}).is_err());
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_adaptive_chunk_compression() {
+        let test_dir = std::env::temp_dir().join("qre_tests_adaptive");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let output_dir = test_dir.join("output");
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        // A "video" under a misleading name: one random chunk, then one chunk of text
+        let input_path = test_dir.join("clip.bin");
+        let mut original_data = pseudo_random(crypto_stream::CHUNK_SIZE, 0x2545_F491_4F6C_DD1D);
+        original_data.extend(b"the quick brown fox jumps over the lazy dog. ".iter().cycle().take(600_000));
+        fs::write(&input_path, &original_data).unwrap();
+
+        let lock = |level: i32| {
+            let encrypted_path = test_dir.join(format!("clip.bin.{}.qre", level));
+            crypto_stream::encrypt_file_stream(
+                input_path.to_str().unwrap(),
+                encrypted_path.to_str().unwrap(),
+                &mk,
+                None,
+                None,
+                &crypto_stream::StreamOptions { compression_level: level, ..Default::default() },
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+            encrypted_path
+        };
+
+        // 1. The random chunk is stored (coding byte + tag), the text chunk compressed
+        let encrypted_path = lock(3);
+        let data = fs::read(&encrypted_path).unwrap();
+        let records = chunk_records(&data);
+        assert_eq!(records[0].len(), 4 + crypto_stream::CHUNK_SIZE + 1 + 16);
+        assert!(records[1].len() < 10_000);
+        let name = try_decrypt(&encrypted_path, &output_dir).expect("Decryption failed");
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+
+        // 2. Level 0 stores every chunk
+        let stored_path = lock(0);
+        let data = fs::read(&stored_path).unwrap();
+        assert_eq!(chunk_records(&data)[1].len(), 4 + 600_000 + 1 + 16);
+        let name = try_decrypt(&stored_path, &output_dir).expect("Decryption failed");
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+
+        let report = inspect::inspect_qre(&encrypted_path);
+        assert_eq!(report.compression.as_deref(), Some("zstd (adaptive)"));
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+
+    #[test]
+    fn test_stream_compression() {
+        let test_dir = std::env::temp_dir().join("qre_tests_stream_zstd");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("output")).unwrap();
+        let output_dir = test_dir.join("output");
+        let mk = keychain::MasterKey([7u8; 32]);
+
+        // Logs whose blocks repeat across chunk boundaries (exactly 4 chunks)
+        let alphabet = b"0123456789 abcdef GET POST /api/v1 200 404\n";
+        let block: Vec<u8> = pseudo_random(300_000, 0x9E37_79B9_7F4A_7C15)
+            .iter()
+            .map(|b| alphabet[*b as usize % alphabet.len()])
+            .collect();
+        let original_data: Vec<u8> = block.iter().cycle().take(4 * crypto_stream::CHUNK_SIZE).copied().collect();
+        let input_path = test_dir.join("server.log");
+        fs::write(&input_path, &original_data).unwrap();
+
+        let lock = |name: &str, options: crypto_stream::StreamOptions| {
+            let encrypted_path = test_dir.join(name);
+            crypto_stream::encrypt_file_stream(
+                input_path.to_str().unwrap(),
+                encrypted_path.to_str().unwrap(),
+                &mk,
+                None,
+                None,
+                &options,
+                |_, _| {},
+            )
+            .expect("Encryption failed");
+            encrypted_path
+        };
+        let stream = crypto_stream::StreamOptions { stream_compression: true, ..Default::default() };
+
+        // 1. Smaller than independent chunks, and decodes back in order
+        let per_chunk_path = lock("per_chunk.qre", crypto_stream::StreamOptions::default());
+        let stream_path = lock("stream.qre", stream.clone());
+        let (per_chunk_len, stream_len) = (fs::metadata(&per_chunk_path).unwrap().len(), fs::metadata(&stream_path).unwrap().len());
+        assert!(stream_len * 2 < per_chunk_len, "{} vs {}", stream_len, per_chunk_len);
+        let name = try_decrypt(&stream_path, &output_dir).expect("Decryption failed");
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+        assert_eq!(inspect::inspect_qre(&stream_path).compression.as_deref(), Some("zstd (stream-wide)"));
+
+        // 2. Combined with fixed-size chunks and a signature
+        let signer = ed25519_dalek::SigningKey::from_bytes(&[9u8; 32]);
+        let fixed_path = lock(
+            "fixed.qre",
+            crypto_stream::StreamOptions { fixed_size_chunks: true, signer: Some(signer), ..stream.clone() },
+        );
+        let name = try_decrypt(&fixed_path, &output_dir).expect("Decryption failed");
+        assert_eq!(fs::read(output_dir.join(name)).unwrap(), original_data);
+
+        // 3. A damaged record is reported as that chunk
+        let mut data = fs::read(&stream_path).unwrap();
+        let second = chunk_records(&data)[1].clone();
+        data[second.start + 10] ^= 0x01;
+        fs::write(&stream_path, data).unwrap();
+        let err = crypto_stream::verify_file_stream(stream_path.to_str().unwrap(), &vault_keys(&mk), 0, |_, _| {}).unwrap_err();
+        assert_eq!(err.downcast_ref::<crypto_stream::ChunkError>().map(|c| c.index), Some(1));
+
+        // 4. No random access: chunks only decode in order
+        assert!(QreReader::open(&per_chunk_path, &vault_keys(&mk)).is_ok());
+        let err = QreReader::open(&fixed_path, &vault_keys(&mk)).err().unwrap().to_string();
+        assert!(err.contains("stream-wide"), "{}", err);
+
+        // 5. A record that expands past one chunk is refused, not decoded into RAM
+        let bomb = zstd::encode_all(&vec![0u8; 64 * crypto_stream::CHUNK_SIZE][..], 19).unwrap();
+        let mut decompressor = crypto_stream::StreamDecompressor::new().unwrap();
+        let err = decompressor.segment(&bomb).unwrap_err().to_string();
+        assert!(err.contains("more than"), "{}", err);
+
+        let _ = fs::remove_dir_all(test_dir);
+    }
+}
@@ -5,8 +5,6 @@ use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 use tauri::{AppHandle, Emitter};
 use uuid::Uuid;
-use walkdir::WalkDir;
-use zip::write::SimpleFileOptions;
 
 // --- EVENT HELPERS ---
 
@@ -93,6 +91,108 @@ pub fn get_unique_path(original_path: &Path) -> PathBuf {
     }
 }
 
+// --- ATOMIC OUTPUT ---
+
+/// A file written under a hidden temporary name in its destination directory,
+/// and only moved to its final name once complete (see `commit`).
+///
+/// If the guard is dropped before `commit` (error, panic, cancelled task), the
+/// partial file is overwritten and deleted, so a failed decryption never leaves
+/// half of the plaintext behind and a failed encryption never leaves a broken `.qre`.
+pub struct AtomicFile {
+    file: Option<fs::File>,
+    temp_path: PathBuf,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Creates the temporary file next to `final_path` (same directory, so the
+    /// final rename never crosses filesystems).
+    pub fn create(final_path: &Path) -> std::io::Result<Self> {
+        let parent = final_path.parent().unwrap_or(Path::new("."));
+        let temp_path = parent.join(format!(".qre-partial-{}", Uuid::new_v4()));
+        let file = fs::OpenOptions::new().write(true).create_new(true).open(&temp_path)?;
+        Ok(Self { file: Some(file), temp_path, committed: false })
+    }
+
+    /// The open temporary file. `&File` implements `Write`.
+    pub fn file(&self) -> &fs::File {
+        self.file.as_ref().expect("AtomicFile used while closed")
+    }
+
+    /// Flushes and closes the temporary file without committing it (it is still
+    /// deleted on drop), so many pending files don't hold a descriptor each.
+    pub fn close(&mut self) -> std::io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    /// Opens a closed temporary file again, for writing.
+    pub fn reopen(&mut self) -> std::io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(fs::OpenOptions::new().write(true).open(&self.temp_path)?);
+        }
+        Ok(())
+    }
+
+    /// Path of the temporary file (e.g., to set permissions before the rename).
+    pub fn temp_path(&self) -> &Path {
+        &self.temp_path
+    }
+
+    /// Flushes the data to disk, then atomically renames the file to `final_path`.
+    pub fn commit(mut self, final_path: &Path) -> std::io::Result<()> {
+        if let Some(file) = self.file.take() {
+            file.sync_all()?;
+        }
+        fs::rename(&self.temp_path, final_path)?;
+        self.committed = true;
+
+        // Persist the rename itself (directory entry) on Unix
+        #[cfg(unix)]
+        if let Some(parent) = final_path.parent() {
+            if let Ok(dir) = fs::File::open(parent) {
+                let _ = dir.sync_all();
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.file.take();
+            let _ = wipe_and_remove(&self.temp_path);
+        }
+    }
+}
+
+/// Overwrites a file with zeros, then deletes it.
+/// Used for partial outputs that may contain plaintext. Best effort on Flash storage
+/// (see `shred_file_internal`).
+pub fn wipe_and_remove(path: &Path) -> std::io::Result<()> {
+    if let Ok(mut file) = fs::OpenOptions::new().write(true).open(path) {
+        // A failed overwrite must not prevent the deletion below
+        let _ = overwrite_with_zeros(&mut file);
+    }
+    fs::remove_file(path)
+}
+
+fn overwrite_with_zeros(file: &mut fs::File) -> std::io::Result<()> {
+    let len = file.metadata()?.len();
+    let zeros = vec![0u8; 1024 * 1024];
+    let mut written = 0u64;
+    while written < len {
+        let n = std::cmp::min(zeros.len() as u64, len - written) as usize;
+        file.write_all(&zeros[..n])?;
+        written += n as u64;
+    }
+    file.sync_all()
+}
+
 // --- TRASH LOGIC ---
 
 /// Moves a file to the System Trash / Recycle Bin.
@@ -113,52 +213,6 @@ pub fn move_to_trash(path: &Path) -> Result<(), String> {
     }
 }
 
-// --- ZIP LOGIC ---
-
-/// Compresses a directory into a ZIP file stored on the disk.
-///
-/// This uses a **temporary file** strategy instead of zipping to memory.
-/// This ensures that archiving large folders (e.g., 10GB) does not crash the application due to RAM exhaustion.
-///
-/// Note: The ZIP itself is stored without compression (`CompressionMethod::Stored`) because
-/// the QRE engine applies Zstd compression to the entire stream later.
-pub fn zip_directory_to_file(dir_path: &Path, output_zip_path: &Path) -> Result<(), String> {
-    let file = fs::File::create(output_zip_path).map_err(|e| e.to_string())?;
-    let mut zip = zip::ZipWriter::new(file);
-
-    let options = SimpleFileOptions::default()
-        .compression_method(zip::CompressionMethod::Stored) 
-        .unix_permissions(0o755);
-
-    let prefix = dir_path.parent().unwrap_or(Path::new(""));
-
-    // Walk through the directory recursively
-    for entry in WalkDir::new(dir_path) {
-        let entry = entry.map_err(|e| e.to_string())?;
-        let path = entry.path();
-
-        // Calculate relative path for the zip structure
-        let name = path
-            .strip_prefix(prefix)
-            .map_err(|_| "Path error")?
-            .to_str()
-            .ok_or("Non-UTF8 path")?
-            .replace("\\", "/"); // Normalize Windows paths
-
-        if path.is_file() {
-            zip.start_file(name, options).map_err(|e| e.to_string())?;
-            // Stream file content directly to the zip file
-            let mut f = fs::File::open(path).map_err(|e| e.to_string())?;
-            std::io::copy(&mut f, &mut zip).map_err(|e| e.to_string())?;
-        } else if path.is_dir() && !name.is_empty() {
-            zip.add_directory(name, options).map_err(|e| e.to_string())?;
-        }
-    }
-
-    zip.finish().map_err(|e| e.to_string())?;
-    Ok(())
-}
-
 // --- SHREDDING LOGIC ---
 
 /// Securely deletes a single file by overwriting it with random data.
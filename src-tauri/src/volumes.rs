@@ -0,0 +1,403 @@
+use crate::utils;
+use anyhow::{anyhow, Context, Result};
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+type HmacSha256 = Hmac<Sha256>;
+
+// --- CONSTANTS ---
+
+// Version bytes of a volume file (a slice of a V6 stream).
+// Numbered apart from the stream versions so `unlock_file` can route on it.
+pub const VOLUME_VERSION: u32 = 106;
+
+// [Version (4 bytes)] + [VolumeHeader (32 bytes, fixed-width)] + [HMAC-SHA256 (32 bytes)]
+const VOLUME_HEADER_LEN: u64 = 4 + 32 + 32;
+
+// Smallest volume we write. Anything smaller would only produce thousands of files.
+pub const MIN_VOLUME_SIZE: u64 = 1024 * 1024;
+
+// Most volumes accepted in a set. Headers are only authenticated once the set is
+// complete, so a crafted count must not make us look for millions of files.
+const MAX_VOLUMES: u32 = 10_000;
+
+// --- DATA STRUCTURES ---
+
+/// The plaintext header at the start of every volume.
+/// Its MAC is keyed from the File Key, so the set can only be checked once the
+/// stream header (in volume 1) has been unlocked.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+struct VolumeHeader {
+    // Random, shared by every volume of one locked file.
+    set_id: [u8; 16],
+    // 1-based position of this volume, and the number of volumes in the set.
+    index: u32,
+    count: u32,
+    // Bytes of the stream held by this volume (the file minus this header).
+    payload_len: u64,
+}
+
+impl VolumeHeader {
+    /// HMAC over the version bytes and the serialized header.
+    fn mac(&self, mac_key: &[u8; 32]) -> Result<HmacSha256> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(mac_key).expect("HMAC accepts any key length");
+        mac.update(&VOLUME_VERSION.to_le_bytes());
+        mac.update(&bincode::serialize(self)?);
+        Ok(mac)
+    }
+}
+
+/// Path of volume `index` (1-based): "report.pdf.qre" -> "report.pdf.qre.001".
+pub fn volume_path(base: &Path, index: u32) -> PathBuf {
+    let mut name = base.as_os_str().to_os_string();
+    name.push(format!(".{:03}", index));
+    PathBuf::from(name)
+}
+
+/// Splits a volume path into its base name and number: "report.pdf.qre.002" -> ("report.pdf.qre", 2).
+pub fn split_volume_path(path: &Path) -> Option<(PathBuf, u32)> {
+    let name = path.to_string_lossy();
+    let (base, suffix) = name.rsplit_once('.')?;
+    if suffix.is_empty() || !suffix.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((PathBuf::from(base), suffix.parse().ok()?))
+}
+
+/// A base path (e.g., "report.pdf (1).qre") whose first volume does not exist yet.
+pub fn unique_base(base: &Path) -> PathBuf {
+    if !volume_path(base, 1).exists() {
+        return base.to_path_buf();
+    }
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = base.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let parent = base.parent().unwrap_or(Path::new("."));
+    let mut counter = 1;
+    loop {
+        let candidate = parent.join(format!("{} ({}){}", stem, counter, extension));
+        if !volume_path(&candidate, 1).exists() {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+// --- WRITER ---
+
+/// Splits a stream into `name.qre.001`, `.002`, ... files of `volume_size` bytes each
+/// (the last one is shorter). Volumes are written to temporary files and only renamed
+/// into place by `finish`, once the whole stream is written and every header is sealed.
+/// Only the volume being filled is kept open.
+pub struct VolumeWriter {
+    base: PathBuf,
+    payload_capacity: u64,
+    set_id: [u8; 16],
+    // Temporary file and payload length of each volume written so far.
+    volumes: Vec<(utils::AtomicFile, u64)>,
+}
+
+impl VolumeWriter {
+    pub fn new(base: &Path, volume_size: u64) -> Result<Self> {
+        if volume_size < MIN_VOLUME_SIZE {
+            return Err(anyhow!("Volume size must be at least {} bytes.", MIN_VOLUME_SIZE));
+        }
+        let mut set_id = [0u8; 16];
+        OsRng.fill_bytes(&mut set_id);
+        Ok(Self {
+            base: base.to_path_buf(),
+            payload_capacity: volume_size - VOLUME_HEADER_LEN,
+            set_id,
+            volumes: Vec::new(),
+        })
+    }
+
+    /// Seals every volume header with `mac_key` (derived from the File Key),
+    /// then moves the volumes into place. Returns their paths, in order.
+    pub fn finish(mut self, mac_key: &[u8; 32]) -> Result<Vec<PathBuf>> {
+        let count = self.volumes.len() as u32;
+        let mut paths = Vec::with_capacity(self.volumes.len());
+
+        // 1. Headers: the count is only known now. One volume open at a time.
+        for (i, (volume, payload_len)) in self.volumes.iter_mut().enumerate() {
+            let header = VolumeHeader { set_id: self.set_id, index: i as u32 + 1, count, payload_len: *payload_len };
+            volume.reopen()?;
+            let mut file = volume.file();
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&VOLUME_VERSION.to_le_bytes())?;
+            file.write_all(&bincode::serialize(&header)?)?;
+            file.write_all(&header.mac(mac_key)?.finalize().into_bytes())?;
+            volume.close()?;
+        }
+
+        // 2. Move into place
+        for (i, (volume, _)) in self.volumes.into_iter().enumerate() {
+            let path = volume_path(&self.base, i as u32 + 1);
+            volume.commit(&path)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// The volume being filled, starting a new one when the current one is full.
+    fn current(&mut self) -> io::Result<&mut (utils::AtomicFile, u64)> {
+        let full = !matches!(self.volumes.last(), Some((_, len)) if *len < self.payload_capacity);
+        if full {
+            if self.volumes.len() as u32 >= MAX_VOLUMES {
+                return Err(io::Error::other(format!(
+                    "More than {} volumes: choose a larger volume size.",
+                    MAX_VOLUMES
+                )));
+            }
+            if let Some((previous, _)) = self.volumes.last_mut() {
+                previous.close()?;
+            }
+            let path = volume_path(&self.base, self.volumes.len() as u32 + 1);
+            let volume = utils::AtomicFile::create(&path)?;
+            // Room for the header, written by `finish`
+            volume.file().write_all(&[0u8; VOLUME_HEADER_LEN as usize])?;
+            self.volumes.push((volume, 0));
+        }
+        Ok(self.volumes.last_mut().unwrap())
+    }
+}
+
+impl Write for VolumeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let capacity = self.payload_capacity;
+        let (volume, len) = self.current()?;
+        let room = (capacity - *len).min(buf.len() as u64) as usize;
+        let n = volume.file().write(&buf[..room])?;
+        *len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// --- READER ---
+
+/// Every volume of a locked file, checked for completeness and order.
+pub struct VolumeSet {
+    // Path, header and stored MAC of each volume, in order.
+    volumes: Vec<(PathBuf, VolumeHeader, [u8; 32])>,
+}
+
+/// Reads the version bytes and header of a volume file.
+fn read_volume_header(path: &Path) -> Result<(VolumeHeader, [u8; 32])> {
+    let mut file = File::open(path).with_context(|| format!("Cannot open volume {}", path.display()))?;
+    let mut buf = [0u8; VOLUME_HEADER_LEN as usize];
+    file.read_exact(&mut buf).context("Volume is too short")?;
+    if u32::from_le_bytes(buf[..4].try_into().unwrap()) != VOLUME_VERSION {
+        return Err(anyhow!("{} is not a volume of a .qre file", path.display()));
+    }
+    let header: VolumeHeader = bincode::deserialize(&buf[4..36])?;
+    let mac: [u8; 32] = buf[36..].try_into().unwrap();
+
+    let payload_len = file.metadata()?.len() - VOLUME_HEADER_LEN;
+    if header.payload_len != payload_len {
+        return Err(anyhow!(
+            "Volume {} is truncated or damaged ({} bytes instead of {}).",
+            header.index,
+            payload_len,
+            header.payload_len
+        ));
+    }
+    Ok((header, mac))
+}
+
+/// True if `path` starts with the version bytes of a volume.
+pub fn is_volume(path: &Path) -> Result<bool> {
+    let mut ver_buf = [0u8; 4];
+    let read = File::open(path)?.read_exact(&mut ver_buf);
+    Ok(read.is_ok() && u32::from_le_bytes(ver_buf) == VOLUME_VERSION)
+}
+
+/// Identifies the set a volume belongs to, so a batch holding several volumes
+/// of the same file unlocks it only once.
+pub fn set_id(path: &Path) -> Result<[u8; 16]> {
+    Ok(read_volume_header(path)?.0.set_id)
+}
+
+impl VolumeSet {
+    /// Finds every volume of the set `path` belongs to (any volume can be given),
+    /// next to it and named like it. Fails if one is missing, renamed or out of place.
+    pub fn open(path: &Path) -> Result<Self> {
+        let (first, _) = read_volume_header(path)?;
+        let Some((base, _)) = split_volume_path(path) else {
+            return Err(anyhow!("Volume names must end with their number (e.g., \".001\")."));
+        };
+
+        if first.count == 0 || first.count > MAX_VOLUMES || first.index == 0 || first.index > first.count {
+            return Err(anyhow!("Volume header is damaged (volume {} of {}).", first.index, first.count));
+        }
+        // The last volume must exist before we walk the set (the count is not authenticated yet)
+        if !volume_path(&base, first.count).exists() {
+            return Err(anyhow!(
+                "Volume {} of {} is missing ({}).",
+                first.count,
+                first.count,
+                volume_path(&base, first.count).display()
+            ));
+        }
+
+        let mut volumes = Vec::new();
+        for index in 1..=first.count {
+            let volume = volume_path(&base, index);
+            if !volume.exists() {
+                return Err(anyhow!("Volume {} of {} is missing ({}).", index, first.count, volume.display()));
+            }
+            let (header, mac) = read_volume_header(&volume)?;
+            if header.set_id != first.set_id {
+                return Err(anyhow!("{} belongs to another locked file.", volume.display()));
+            }
+            if header.index != index || header.count != first.count {
+                return Err(anyhow!(
+                    "{} holds volume {} of {}, expected volume {} of {} (renamed or misordered).",
+                    volume.display(),
+                    header.index,
+                    header.count,
+                    index,
+                    first.count
+                ));
+            }
+            volumes.push((volume, header, mac));
+        }
+        Ok(Self { volumes })
+    }
+
+    /// Checks every volume header against `mac_key` (derived from the File Key).
+    pub fn verify(&self, mac_key: &[u8; 32]) -> Result<()> {
+        for (path, header, stored) in &self.volumes {
+            if header.mac(mac_key)?.verify_slice(stored).is_err() {
+                return Err(anyhow!("Volume {} failed authentication ({}).", header.index, path.display()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Total length of the stream spread over the volumes.
+    pub fn stream_len(&self) -> u64 {
+        self.volumes.iter().map(|(_, header, _)| header.payload_len).sum()
+    }
+
+    /// Reads the stream back, volume after volume.
+    pub fn reader(&self) -> VolumeReader {
+        VolumeReader {
+            volumes: self.volumes.iter().map(|(path, header, _)| (path.clone(), header.payload_len)).collect(),
+            position: 0,
+            current: None,
+        }
+    }
+
+    /// Replaces the first `old_len` bytes of the stream (the stream header, always held
+    /// by volume 1) with `new_start`, and seals volume 1's header again with `mac_key`.
+    /// Used to rewrap a split file. Only volume 1 is rewritten: the headers of the others
+    /// do not depend on its length, and the MAC key comes from the File Key, which is kept.
+    /// Returns the path of volume 1.
+    pub fn replace_start(&self, old_len: u64, new_start: &[u8], mac_key: &[u8; 32]) -> Result<PathBuf> {
+        let (path, first, _) = &self.volumes[0];
+        if old_len > first.payload_len {
+            return Err(anyhow!("Volume 1 does not hold the whole header (damaged set?)."));
+        }
+        let header = VolumeHeader { payload_len: first.payload_len - old_len + new_start.len() as u64, ..*first };
+
+        let mut input = File::open(path)?;
+        input.seek(SeekFrom::Start(VOLUME_HEADER_LEN + old_len))?;
+
+        let output = utils::AtomicFile::create(path)?;
+        let mut file = output.file();
+        file.write_all(&VOLUME_VERSION.to_le_bytes())?;
+        file.write_all(&bincode::serialize(&header)?)?;
+        file.write_all(&header.mac(mac_key)?.finalize().into_bytes())?;
+        file.write_all(new_start)?;
+        io::copy(&mut input, &mut file)?;
+
+        std::fs::set_permissions(output.temp_path(), input.metadata()?.permissions())?;
+        output.commit(path)?;
+        Ok(path.clone())
+    }
+}
+
+/// The payloads of a set of volumes, as one stream. Seeking opens the volume holding
+/// the new position, so chunks can be read at random (see `QreReader`).
+pub struct VolumeReader {
+    // Path and payload length of each volume.
+    volumes: Vec<(PathBuf, u64)>,
+    // Offset in the stream.
+    position: u64,
+    // The open volume: its index, the stream offset it starts at, and the file at `position`.
+    current: Option<(usize, u64, File)>,
+}
+
+impl VolumeReader {
+    fn total_len(&self) -> u64 {
+        self.volumes.iter().map(|(_, len)| len).sum()
+    }
+
+    /// Opens the volume holding `position`. Returns false at the end of the stream.
+    fn open_current(&mut self) -> io::Result<bool> {
+        let mut start = 0;
+        for (index, (path, len)) in self.volumes.iter().enumerate() {
+            if self.position < start + len {
+                let mut file = File::open(path)?;
+                file.seek(SeekFrom::Start(VOLUME_HEADER_LEN + self.position - start))?;
+                self.current = Some((index, start, file));
+                return Ok(true);
+            }
+            start += len;
+        }
+        Ok(false)
+    }
+}
+
+impl Read for VolumeReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() && !self.open_current()? {
+            return Ok(0);
+        }
+        let (index, start, file) = self.current.as_mut().unwrap();
+        let end = *start + self.volumes[*index].1;
+        let room = (end - self.position).min(buf.len() as u64) as usize;
+        let n = file.read(&mut buf[..room])?;
+        if n == 0 {
+            // The length was checked when the set was opened: the volume shrank since
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Volume is shorter than its header says"));
+        }
+        self.position += n as u64;
+        if self.position == end {
+            self.current = None;
+        }
+        Ok(n)
+    }
+}
+
+impl Seek for VolumeReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(p) => Some(p),
+            SeekFrom::End(delta) => self.total_len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        }
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Seek before start of stream"))?;
+
+        // Stay in the open volume when the target is inside it
+        match &mut self.current {
+            Some((index, start, file)) if target >= *start && target < *start + self.volumes[*index].1 => {
+                file.seek(SeekFrom::Start(VOLUME_HEADER_LEN + target - *start))?;
+            }
+            _ => self.current = None,
+        }
+        self.position = target;
+        Ok(target)
+    }
+}
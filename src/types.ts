@@ -20,4 +20,5 @@ export interface BatchResult {
   name: string;
   success: boolean;
   message: string;
+  integrity_failed: boolean;
 }